@@ -0,0 +1,39 @@
+//! Where the game keeps its state in work RAM.
+//!
+//! Taken from the well-known RAM map of the original `invaders` ROM. Bootlegs and
+//! hacks built on the same code mostly keep these, but nothing checks that they do.
+//!
+//! Scores and the credit count are packed BCD (see
+//! [`bcd_to_decimal`](crate::memory::bcd_to_decimal)), with two byte values stored
+//! little endian. Each player has a 256 byte block of their own, [`P1_DATA`] and
+//! [`P2_DATA`], which the game swaps between on a change of turn. The game has no
+//! random number generator in RAM; what looks random, like the UFO's score, follows
+//! from counts such as the number of shots fired.
+
+/// The high byte of the current player's block, 0x21 for [`P1_DATA`] or 0x22 for
+/// [`P2_DATA`]
+pub const PLAYER_DATA_MSB: u16 = 0x2067;
+/// 0x80 once the game's vblank handler has run, cleared by the mid-screen one
+pub const VBLANK_STATUS: u16 = 0x2072;
+/// Invaders left alive in the current player's wave
+pub const NUM_ALIENS: u16 = 0x2082;
+/// Credits, in BCD
+pub const CREDITS: u16 = 0x20EB;
+/// Non-zero while a game is being played. The game only clears it after the
+/// `GAME OVER` message has been shown.
+pub const GAME_MODE: u16 = 0x20EF;
+/// The high score, two bytes of BCD
+pub const HI_SCORE: u16 = 0x20F4;
+/// Player 1's score, two bytes of BCD
+pub const P1_SCORE: u16 = 0x20F8;
+/// Player 2's score, two bytes of BCD
+pub const P2_SCORE: u16 = 0x20FC;
+/// The start of player 1's block, which begins with a byte per invader that's 1 while
+/// it's alive
+pub const P1_DATA: u16 = 0x2100;
+/// The start of player 2's block, laid out as [`P1_DATA`]
+pub const P2_DATA: u16 = 0x2200;
+/// Ships player 1 has left, the last byte of their block
+pub const P1_SHIPS: u16 = 0x21FF;
+/// Ships player 2 has left, the last byte of their block
+pub const P2_SHIPS: u16 = 0x22FF;
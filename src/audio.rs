@@ -0,0 +1,167 @@
+//! Gain control shared between the emulator and the sounds it is playing, and sizing
+//! the output buffer.
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use awedio::{NextSample, Sound};
+
+/// How long a change in gain takes to ramp in, short enough to be heard as a cut
+/// but long enough to avoid the click of a hard one
+const FADE_TIME_SECS: f32 = 0.005;
+
+/// How much `+` and `-` change the volume, out of 100
+pub const VOLUME_STEP: u8 = 10;
+
+/// The gain for a volume from 0 to 100, squared so each step sounds about as big as
+/// the last rather than the top few barely changing anything
+pub fn volume_gain(volume: u8) -> f32 {
+    let volume = volume.min(100) as f32 / 100.0;
+    volume * volume
+}
+
+/// A gain level that can be changed from the emulator thread while sounds play
+pub struct Gain {
+    target: AtomicU32,
+}
+
+impl Gain {
+    pub fn new(gain: f32) -> Arc<Self> {
+        Arc::new(Gain {
+            target: AtomicU32::new(gain.to_bits()),
+        })
+    }
+
+    pub fn set(&self, gain: f32) {
+        self.target.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f32 {
+        f32::from_bits(self.target.load(Ordering::Relaxed))
+    }
+}
+
+/// Applies a shared [`Gain`] to a sound, fading linearly whenever it changes
+pub struct Faded<S: Sound> {
+    inner: S,
+    gain: Arc<Gain>,
+    current: f32,
+    step: f32,
+}
+
+impl<S: Sound> Faded<S> {
+    fn new(inner: S, gain: Arc<Gain>) -> Self {
+        let current = gain.get();
+        let step = Self::fade_step(&inner);
+        Faded {
+            inner,
+            gain,
+            current,
+            step,
+        }
+    }
+
+    fn fade_step(inner: &S) -> f32 {
+        let samples = inner.sample_rate() as f32 * inner.channel_count() as f32 * FADE_TIME_SECS;
+        1.0 / samples.max(1.0)
+    }
+}
+
+impl<S: Sound> Sound for Faded<S> {
+    fn channel_count(&self) -> u16 {
+        self.inner.channel_count()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn next_sample(&mut self) -> Result<NextSample, awedio::Error> {
+        let next = self.inner.next_sample()?;
+        match next {
+            NextSample::Sample(sample) => {
+                let target = self.gain.get();
+                if self.current < target {
+                    self.current = (self.current + self.step).min(target);
+                } else if self.current > target {
+                    self.current = (self.current - self.step).max(target);
+                }
+                Ok(NextSample::Sample((sample as f32 * self.current) as i16))
+            }
+            NextSample::MetadataChanged => {
+                self.step = Self::fade_step(&self.inner);
+                Ok(next)
+            }
+            _ => Ok(next),
+        }
+    }
+
+    fn on_start_of_batch(&mut self) {
+        self.inner.on_start_of_batch();
+    }
+}
+
+pub trait SoundExt: Sound + Sized {
+    fn faded(self, gain: &Arc<Gain>) -> Faded<Self> {
+        Faded::new(self, gain.clone())
+    }
+}
+
+impl<S: Sound> SoundExt for S {}
+
+/// Frames of output buffer that hold `latency` worth of sound at `sample_rate`, kept
+/// within the `(min, max)` the device supports if it says. The second value is false
+/// if the buffer had to be resized to fit.
+pub fn buffer_frames(
+    latency: Duration,
+    sample_rate: u32,
+    supported: Option<(u32, u32)>,
+) -> (u32, bool) {
+    let frames = (latency.as_secs_f64() * sample_rate as f64).round() as u32;
+    match supported {
+        Some((min, max)) => {
+            let fitted = frames.clamp(min, max);
+            (fitted, fitted == frames)
+        }
+        None => (frames, true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::audio::{buffer_frames, volume_gain};
+
+    #[test]
+    fn test_volume_gain() {
+        assert_eq!(volume_gain(0), 0.0);
+        assert_eq!(volume_gain(50), 0.25);
+        assert_eq!(volume_gain(100), 1.0);
+        assert_eq!(volume_gain(250), 1.0);
+    }
+
+    #[test]
+    fn test_buffer_frames() {
+        assert_eq!(
+            buffer_frames(Duration::from_millis(20), 48000, None),
+            (960, true)
+        );
+        assert_eq!(
+            buffer_frames(Duration::from_millis(10), 44100, Some((64, 4096))),
+            (441, true)
+        );
+        // too short and too long for the device
+        assert_eq!(
+            buffer_frames(Duration::from_millis(1), 48000, Some((64, 4096))),
+            (64, false)
+        );
+        assert_eq!(
+            buffer_frames(Duration::from_millis(500), 48000, Some((64, 4096))),
+            (4096, false)
+        );
+    }
+}
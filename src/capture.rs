@@ -0,0 +1,507 @@
+//! Session captures for bug reports.
+//!
+//! A capture bundles everything needed to reproduce a session exactly: a checksum of the
+//! ROM it was made with, the inputs the game saw on every frame, the sounds it triggered
+//! and a copy of VRAM every [`SNAPSHOT_INTERVAL`] frames. Replaying the inputs rebuilds
+//! the session and the snapshots and sounds show the moment it stops matching what the
+//! reporter saw.
+//!
+//! The file starts with `SICAPT` and the format version as a little endian `u16`,
+//! followed by a sequence of chunks, each laid out as:
+//!
+//! | bytes | contents                                   |
+//! |-------|--------------------------------------------|
+//! | 4     | tag                                        |
+//! | 4     | payload length (little endian `u32`)       |
+//! | n     | payload                                    |
+//! | 4     | CRC-32 of the payload (little endian)      |
+//!
+//! | tag    | payload                                                                    |
+//! |--------|----------------------------------------------------------------------------|
+//! | `MANI` | `key=value` lines: `rom_crc32`, `frames`, `snapshot_interval`, `snapshots` |
+//! | `INPT` | input ports 0, 1 and 2 for each frame, 3 bytes per frame                   |
+//! | `AUDI` | the sounds in the format of [`AudioLog`], from version 2                   |
+//! | `VRAM` | the frame number (little endian `u64`) then the raw 1bpp VRAM              |
+//! | `END ` | empty, marks a complete capture                                            |
+//!
+//! `MANI` comes first, then `INPT` and `AUDI`, and `END ` last. Version 1 captures
+//! have no `AUDI` and replay without checking the sounds. A capture with a bad
+//! checksum, a missing chunk, counts that disagree with the manifest or no `END ` is
+//! rejected rather than played.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use emu8080::MemoryAccess;
+
+use crate::machine::{AudioLog, Machine, SoundEvent};
+use crate::{ROM_SIZE, VRAM_END, VRAM_SIZE, VRAM_START};
+
+pub const CAPTURE_VERSION: u16 = 2;
+
+/// Frames between VRAM snapshots, one a second
+pub const SNAPSHOT_INTERVAL: u64 = 60;
+
+const MAGIC: &[u8; 6] = b"SICAPT";
+
+/// no real chunk comes close, so anything bigger is corruption rather than a huge capture
+const MAX_CHUNK_SIZE: usize = 64 * 1024 * 1024;
+
+/// CRC-32 (IEEE) as used by zip and PNG
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// The guest's video memory as the game wrote it, one bit per pixel
+pub fn vram_snapshot(machine: &Machine) -> Vec<u8> {
+    (VRAM_START..VRAM_END)
+        .map(|addr| machine.memory.read_byte(addr as u16))
+        .collect()
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Capture {
+    pub rom_crc32: u32,
+    pub snapshot_interval: u64,
+    /// input ports 0, 1 and 2 as latched on each frame
+    pub inputs: Vec<[u8; 3]>,
+    /// the sounds as an [`AudioLog`], `None` in version 1 captures
+    pub audio_log: Option<String>,
+    /// (frame, VRAM) pairs in frame order
+    pub snapshots: Vec<(u64, Vec<u8>)>,
+}
+
+impl Capture {
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn write_to(&self, out: &mut impl Write) -> Result<(), Error> {
+        out.write_all(MAGIC)?;
+        out.write_all(&CAPTURE_VERSION.to_le_bytes())?;
+
+        let manifest = format!(
+            "rom_crc32={:08x}\nframes={}\nsnapshot_interval={}\nsnapshots={}\n",
+            self.rom_crc32,
+            self.inputs.len(),
+            self.snapshot_interval,
+            self.snapshots.len()
+        );
+        write_chunk(out, b"MANI", manifest.as_bytes())?;
+
+        write_chunk(out, b"INPT", &self.inputs.concat())?;
+
+        if let Some(ref audio_log) = self.audio_log {
+            write_chunk(out, b"AUDI", audio_log.as_bytes())?;
+        }
+
+        for (frame, vram) in &self.snapshots {
+            let payload = [&frame.to_le_bytes()[..], vram].concat();
+            write_chunk(out, b"VRAM", &payload)?;
+        }
+
+        write_chunk(out, b"END ", &[])
+    }
+
+    pub fn read_from(input: &mut impl Read) -> Result<Self, Error> {
+        let mut header = [0u8; 8];
+        read_exact(input, &mut header)?;
+        if &header[..6] != MAGIC {
+            return Err(invalid("not a session capture"));
+        }
+        let version = u16::from_le_bytes([header[6], header[7]]);
+        if !(1..=CAPTURE_VERSION).contains(&version) {
+            return Err(invalid(format!(
+                "capture format version {} is not supported (expected {})",
+                version, CAPTURE_VERSION
+            )));
+        }
+
+        let (tag, manifest) = read_chunk(input)?;
+        if &tag != b"MANI" {
+            return Err(invalid("capture does not start with a manifest"));
+        }
+        let manifest = String::from_utf8(manifest).map_err(|_| invalid("manifest is not text"))?;
+
+        let mut rom_crc32 = None;
+        let mut frames = None;
+        let mut snapshot_interval = None;
+        let mut snapshot_count = None;
+        for line in manifest.lines() {
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(format!("bad manifest line '{}'", line)))?;
+            let bad_value = || invalid(format!("bad manifest value for '{}'", key));
+            match key {
+                "rom_crc32" => {
+                    rom_crc32 = Some(u32::from_str_radix(value, 16).map_err(|_| bad_value())?)
+                }
+                "frames" => frames = Some(value.parse::<usize>().map_err(|_| bad_value())?),
+                "snapshot_interval" => {
+                    snapshot_interval = Some(value.parse::<u64>().map_err(|_| bad_value())?)
+                }
+                "snapshots" => {
+                    snapshot_count = Some(value.parse::<usize>().map_err(|_| bad_value())?)
+                }
+                // later versions may add keys
+                _ => {}
+            }
+        }
+        let missing = |key: &str| invalid(format!("manifest is missing '{}'", key));
+        let rom_crc32 = rom_crc32.ok_or_else(|| missing("rom_crc32"))?;
+        let frames = frames.ok_or_else(|| missing("frames"))?;
+        let snapshot_interval = snapshot_interval.ok_or_else(|| missing("snapshot_interval"))?;
+        let snapshot_count = snapshot_count.ok_or_else(|| missing("snapshots"))?;
+
+        let (tag, payload) = read_chunk(input)?;
+        if &tag != b"INPT" {
+            return Err(invalid("capture is missing its inputs"));
+        }
+        if payload.len() != frames * 3 {
+            return Err(invalid(format!(
+                "capture has {} bytes of input for {} frames",
+                payload.len(),
+                frames
+            )));
+        }
+        let inputs = payload
+            .chunks_exact(3)
+            .map(|ports| [ports[0], ports[1], ports[2]])
+            .collect();
+
+        let mut audio_log = None;
+        let mut snapshots: Vec<(u64, Vec<u8>)> = Vec::new();
+        loop {
+            let (tag, payload) = read_chunk(input)?;
+            match &tag {
+                b"AUDI" if version >= 2 && audio_log.is_none() && snapshots.is_empty() => {
+                    audio_log = Some(
+                        String::from_utf8(payload).map_err(|_| invalid("audio log is not text"))?,
+                    );
+                }
+                b"VRAM" => {
+                    if payload.len() != 8 + VRAM_SIZE {
+                        return Err(invalid("VRAM snapshot is the wrong size"));
+                    }
+                    let frame = u64::from_le_bytes(payload[..8].try_into().unwrap());
+                    if snapshots.last().is_some_and(|(last, _)| *last >= frame) {
+                        return Err(invalid("VRAM snapshots are out of order"));
+                    }
+                    snapshots.push((frame, payload[8..].to_vec()));
+                }
+                b"END " => break,
+                _ => {
+                    return Err(invalid(format!(
+                        "unknown chunk '{}'",
+                        String::from_utf8_lossy(&tag)
+                    )))
+                }
+            }
+        }
+
+        if version >= 2 && audio_log.is_none() {
+            return Err(invalid("capture is missing its audio log"));
+        }
+        if snapshots.len() != snapshot_count {
+            return Err(invalid(format!(
+                "manifest lists {} VRAM snapshots but the capture has {}",
+                snapshot_count,
+                snapshots.len()
+            )));
+        }
+
+        Ok(Capture {
+            rom_crc32,
+            snapshot_interval,
+            inputs,
+            audio_log,
+            snapshots,
+        })
+    }
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn read_exact(input: &mut impl Read, buf: &mut [u8]) -> Result<(), Error> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => invalid("capture is truncated"),
+        _ => e,
+    })
+}
+
+fn write_chunk(out: &mut impl Write, tag: &[u8; 4], payload: &[u8]) -> Result<(), Error> {
+    out.write_all(tag)?;
+    out.write_all(&(payload.len() as u32).to_le_bytes())?;
+    out.write_all(payload)?;
+    out.write_all(&crc32(payload).to_le_bytes())
+}
+
+fn read_chunk(input: &mut impl Read) -> Result<([u8; 4], Vec<u8>), Error> {
+    let mut header = [0u8; 8];
+    read_exact(input, &mut header)?;
+    let tag: [u8; 4] = header[..4].try_into().unwrap();
+    let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+    if len > MAX_CHUNK_SIZE {
+        return Err(invalid("chunk is too large"));
+    }
+
+    let mut payload = vec![0u8; len];
+    read_exact(input, &mut payload)?;
+
+    let mut crc = [0u8; 4];
+    read_exact(input, &mut crc)?;
+    if u32::from_le_bytes(crc) != crc32(&payload) {
+        return Err(invalid(format!(
+            "checksum mismatch in '{}' chunk",
+            String::from_utf8_lossy(&tag)
+        )));
+    }
+
+    Ok((tag, payload))
+}
+
+/// Builds a capture while the machine runs and writes it out when the session ends
+pub struct CaptureRecorder {
+    path: PathBuf,
+    capture: Capture,
+    audio_log: AudioLog<Vec<u8>>,
+}
+
+impl CaptureRecorder {
+    pub fn start(path: PathBuf, rom: &[u8; ROM_SIZE], machine: &mut Machine) -> Self {
+        machine.start_recording();
+        CaptureRecorder {
+            path,
+            capture: Capture {
+                rom_crc32: crc32(rom),
+                snapshot_interval: SNAPSHOT_INTERVAL,
+                inputs: Vec::new(),
+                audio_log: None,
+                snapshots: Vec::new(),
+            },
+            audio_log: AudioLog::new(Vec::new()),
+        }
+    }
+
+    /// Call for each sound as it's drained from the machine
+    pub fn record_sound(&mut self, frame: u64, event: SoundEvent) {
+        // writing to a Vec can't fail
+        let _ = self.audio_log.record(frame, event);
+    }
+
+    /// Call once per VBlank
+    pub fn record_frame(&mut self, machine: &Machine) {
+        if machine
+            .frame()
+            .is_multiple_of(self.capture.snapshot_interval)
+        {
+            self.capture
+                .snapshots
+                .push((machine.frame(), vram_snapshot(machine)));
+        }
+    }
+
+    pub fn finish(mut self, machine: &mut Machine) -> Result<PathBuf, Error> {
+        self.capture.inputs = machine.take_recording().unwrap_or_default();
+        self.capture.audio_log = Some(String::from_utf8_lossy(&self.audio_log.into_inner()).into());
+        self.capture.save(&self.path)?;
+        Ok(self.path)
+    }
+}
+
+/// Feeds a capture's inputs to the machine and checks VRAM and the sounds against the
+/// capture's
+pub struct CaptureReplay {
+    snapshots: VecDeque<(u64, Vec<u8>)>,
+    /// the audio log's lines still to be heard
+    sounds: VecDeque<String>,
+}
+
+impl CaptureReplay {
+    pub fn start(capture: Capture, machine: &mut Machine) -> Self {
+        machine.start_replay(capture.inputs);
+        CaptureReplay {
+            snapshots: capture.snapshots.into(),
+            sounds: capture
+                .audio_log
+                .unwrap_or_default()
+                .lines()
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+
+    /// Call for each sound as it's drained from the machine. Fails on the first one
+    /// that isn't the next in the capture's audio log. Sounds after the end of the log
+    /// come after the end of the capture and aren't checked.
+    pub fn check_sound(&mut self, frame: u64, event: SoundEvent) -> Result<(), String> {
+        let Some(expected) = self.sounds.pop_front() else {
+            return Ok(());
+        };
+        let heard = format!("{} {}", frame, event);
+        if heard != expected {
+            return Err(format!(
+                "replay's sounds diverged from the capture at frame {}: expected '{}', heard '{}'",
+                frame, expected, heard
+            ));
+        }
+        Ok(())
+    }
+
+    /// Call once per VBlank. Fails on the first snapshot that doesn't match.
+    pub fn check_frame(&mut self, machine: &Machine) -> Result<(), String> {
+        while let Some((frame, vram)) = self.snapshots.front() {
+            if *frame > machine.frame() {
+                break;
+            }
+            if *frame < machine.frame() {
+                return Err(format!("replay skipped the snapshot at frame {}", frame));
+            }
+            if *vram != vram_snapshot(machine) {
+                return Err(format!(
+                    "replay diverged from the capture at frame {}",
+                    frame
+                ));
+            }
+            self.snapshots.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Every snapshot and sound has been matched
+    pub fn finished(&self) -> bool {
+        self.snapshots.is_empty() && self.sounds.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::capture::{crc32, Capture, CaptureRecorder, CaptureReplay};
+    use crate::machine::{new_inputs, AudioLog, CabinetType, InputPulse, Machine};
+    use crate::memory::{RotationMode, SpaceInvadersMemory};
+    use crate::{test_rom, VRAM_SIZE};
+
+    fn capture() -> Capture {
+        Capture {
+            rom_crc32: 0x1234_5678,
+            snapshot_interval: 60,
+            inputs: vec![[0x8F, 0x08, 0x00], [0x8F, 0x09, 0x00]],
+            audio_log: Some("1 amp on\n1 shot\n".to_string()),
+            snapshots: vec![(0, vec![0; VRAM_SIZE]), (60, vec![0xFF; VRAM_SIZE])],
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_capture_round_trip() {
+        let mut bytes = Vec::new();
+        capture().write_to(&mut bytes).unwrap();
+        assert_eq!(
+            Capture::read_from(&mut bytes.as_slice()).unwrap(),
+            capture()
+        );
+    }
+
+    #[test]
+    fn test_corrupt_capture() {
+        let mut bytes = Vec::new();
+        capture().write_to(&mut bytes).unwrap();
+
+        let truncated = &bytes[..bytes.len() - 12];
+        assert!(Capture::read_from(&mut &truncated[..]).is_err());
+
+        let mut flipped = bytes.clone();
+        let last = flipped.len() - 20;
+        flipped[last] ^= 0x01;
+        assert!(Capture::read_from(&mut flipped.as_slice()).is_err());
+
+        assert!(Capture::read_from(&mut &b"PK\x03\x04"[..]).is_err());
+
+        // version 2 needs its audio log
+        let mut silent = Vec::new();
+        Capture {
+            audio_log: None,
+            ..capture()
+        }
+        .write_to(&mut silent)
+        .unwrap();
+        assert!(Capture::read_from(&mut silent.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        let rom = test_rom();
+
+        let new_machine = || {
+            let memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+            Machine::new(memory, new_inputs(), CabinetType::Upright)
+        };
+
+        let mut machine = new_machine();
+        let mut recorder = CaptureRecorder::start("unused".into(), &rom, &mut machine);
+        machine.input_queue.push(InputPulse::credit());
+        machine.input_queue.push(InputPulse::start_1p());
+        while machine.frame() < 600 {
+            let vblank = machine.step().vblank;
+            let frame = machine.frame();
+            for event in machine.drain_sound_events() {
+                recorder.record_sound(frame, event);
+            }
+            if vblank {
+                recorder.record_frame(&machine);
+            }
+        }
+        let mut capture = recorder.capture.clone();
+        capture.inputs = machine.take_recording().unwrap();
+        capture.audio_log = Some(String::from_utf8(recorder.audio_log.into_inner()).unwrap());
+        let recorded_log = capture.audio_log.clone().unwrap();
+        assert!(!recorded_log.is_empty());
+
+        // through the file format, as a bug report would be
+        let mut bytes = Vec::new();
+        capture.write_to(&mut bytes).unwrap();
+        let capture = Capture::read_from(&mut bytes.as_slice()).unwrap();
+
+        let mut machine = new_machine();
+        let mut replay = CaptureReplay::start(capture, &mut machine);
+        let mut replayed_log = AudioLog::new(Vec::new());
+        while machine.frame() < 600 {
+            let vblank = machine.step().vblank;
+            let frame = machine.frame();
+            for event in machine.drain_sound_events() {
+                replay.check_sound(frame, event).unwrap();
+                replayed_log.record(frame, event).unwrap();
+            }
+            if vblank {
+                replay.check_frame(&machine).unwrap();
+            }
+        }
+        assert!(replay.finished());
+        assert_eq!(
+            String::from_utf8(replayed_log.into_inner()).unwrap(),
+            recorded_log
+        );
+    }
+}
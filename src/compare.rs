@@ -0,0 +1,152 @@
+//! Running two ROMs side by side, for `--compare-rom`.
+//!
+//! Both ROMs run headless from power on with the same inputs, one frame at a time,
+//! and work RAM and VRAM are compared at every vblank. A patch that only changes what
+//! it means to runs in step with the original until the first frame it has an effect,
+//! so the frame and the bytes that differ on it show whether that's where it should.
+
+use std::fmt;
+
+use crate::capture::vram_snapshot;
+use crate::machine::{new_inputs, CabinetType, Machine};
+use crate::memory::{RotationMode, SpaceInvadersMemory};
+use crate::{RAM_START, ROM_SIZE, SCREEN_WIDTH_PIXELS};
+
+/// Frames compared without a replay to set the length, a minute of emulated time
+pub const COMPARE_FRAMES: u64 = 3600;
+
+/// RAM differences listed in full, the rest are only counted
+const LISTED_RAM_BYTES: usize = 8;
+
+const VRAM_LINE_BYTES: usize = SCREEN_WIDTH_PIXELS / 8;
+
+/// The first frame the two ROMs stopped matching on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub frame: u64,
+    /// (address, first ROM's value, second ROM's value) for each RAM byte that differs
+    pub ram: Vec<(u16, u8, u8)>,
+    /// VRAM bytes that differ
+    pub vram_bytes: usize,
+    /// the first and last line, in the game's own layout, with a VRAM difference
+    pub vram_lines: Option<(usize, usize)>,
+}
+
+/// Runs both ROMs for `frames` frames, the first `inputs.len()` of them with the
+/// given inputs and the rest with none, and returns where they first differ
+pub fn compare_roms(
+    a: [u8; ROM_SIZE],
+    b: [u8; ROM_SIZE],
+    inputs: &[[u8; 3]],
+    frames: u64,
+) -> Option<Divergence> {
+    let mut machines = [a, b].map(|rom| {
+        let memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+        let mut machine = Machine::new(memory, new_inputs(), CabinetType::Upright);
+        machine.start_replay(inputs.to_vec());
+        machine
+    });
+
+    while machines[0].frame() < frames {
+        for machine in &mut machines {
+            machine.run_frame();
+        }
+        if let Some(divergence) = diff(&machines[0], &machines[1]) {
+            return Some(divergence);
+        }
+    }
+    None
+}
+
+fn diff(a: &Machine, b: &Machine) -> Option<Divergence> {
+    let ram: Vec<(u16, u8, u8)> = a
+        .memory
+        .save_ram()
+        .into_iter()
+        .zip(b.memory.save_ram())
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(i, (a, b))| ((RAM_START + i) as u16, a, b))
+        .collect();
+
+    let vram_diffs: Vec<usize> = vram_snapshot(a)
+        .into_iter()
+        .zip(vram_snapshot(b))
+        .enumerate()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(i, _)| i)
+        .collect();
+
+    if ram.is_empty() && vram_diffs.is_empty() {
+        return None;
+    }
+    Some(Divergence {
+        frame: a.frame(),
+        ram,
+        vram_bytes: vram_diffs.len(),
+        vram_lines: vram_diffs
+            .first()
+            .zip(vram_diffs.last())
+            .map(|(first, last)| (first / VRAM_LINE_BYTES, last / VRAM_LINE_BYTES)),
+    })
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Diverged at frame {}", self.frame)?;
+
+        write!(f, "RAM:  {} bytes differ", self.ram.len())?;
+        for (addr, a, b) in self.ram.iter().take(LISTED_RAM_BYTES) {
+            write!(f, "\n  {:04X}  {:02X} -> {:02X}", addr, a, b)?;
+        }
+        if self.ram.len() > LISTED_RAM_BYTES {
+            write!(f, "\n  and {} more", self.ram.len() - LISTED_RAM_BYTES)?;
+        }
+        writeln!(f)?;
+
+        write!(f, "VRAM: {} bytes differ", self.vram_bytes)?;
+        if let Some((first, last)) = self.vram_lines {
+            write!(f, " on lines {}-{}", first, last)?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compare::compare_roms;
+    use crate::{test_rom, ROM_SIZE};
+
+    fn rom(program: &[u8]) -> [u8; ROM_SIZE] {
+        let mut rom = [0u8; ROM_SIZE];
+        rom[..program.len()].copy_from_slice(program);
+        rom
+    }
+
+    #[test]
+    fn test_compare_roms() {
+        // MVI A,$42; STA $2010; STA $2400; HLT
+        let program = [0x3E, 0x42, 0x32, 0x10, 0x20, 0x32, 0x00, 0x24, 0x76];
+        let a = rom(&program);
+        assert_eq!(compare_roms(a, a, &[], 10), None);
+
+        let mut patched = program;
+        patched[1] = 0x43;
+        let divergence = compare_roms(a, rom(&patched), &[], 10).unwrap();
+        // the first vblank is due at power on, so it comes before the stores
+        assert_eq!(divergence.frame, 2);
+        assert_eq!(divergence.ram, [(0x2010, 0x42, 0x43)]);
+        assert_eq!(divergence.vram_bytes, 1);
+        assert_eq!(divergence.vram_lines, Some((0, 0)));
+
+        let text = divergence.to_string();
+        assert!(text.contains("Diverged at frame 2"), "{}", text);
+        assert!(text.contains("2010  42 -> 43"), "{}", text);
+    }
+
+    #[test]
+    fn test_compare_same_rom() {
+        let rom = test_rom();
+        assert_eq!(compare_roms(rom, rom, &[], 300), None);
+    }
+}
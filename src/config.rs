@@ -0,0 +1,389 @@
+//! Defaults from a config file.
+//!
+//! The file is TOML with a key for each option worth keeping between runs, named
+//! after its flag without the dashes:
+//!
+//! ```toml
+//! rotate = "cw"
+//! pixel-grid = 0.4
+//! phosphor = "green"
+//! mute = ["ufo"]
+//! borderless = true
+//!
+//! [keys]
+//! p1-shot = "Space"
+//! ```
+//!
+//! It's turned back into flags that go ahead of the command line, so the command
+//! line wins over the file and the file over the built-in defaults, and both are
+//! checked by the same code. Switches can only be turned on from the file, and
+//! `mute` adds to any `--mute` given.
+//!
+//! The same keys can be set with environment variables, see [`Config::from_env`].
+//! They go between the file and the command line, so the order of precedence is the
+//! command line, then the environment, then the file, then the defaults. The file and
+//! the environment are put together with [`Config::merge`] before they're turned into
+//! flags, so `SPACE_INVADERS_FULLSCREEN=false` turns off `fullscreen = true` from the
+//! file.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "space-invaders/config.toml";
+
+/// What the environment variables read by [`Config::from_env`] start with
+pub const ENV_PREFIX: &str = "SPACE_INVADERS_";
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub rom: Option<PathBuf>,
+    pub rotate: Option<String>,
+    pub cabinet: Option<String>,
+    /// false for `--no-quick-start`
+    pub quick_start: Option<bool>,
+    pub pixel_grid: Option<f32>,
+    pub persistence: Option<f32>,
+    pub linear_light: bool,
+    pub filter: Option<String>,
+    pub phosphor: Option<String>,
+    /// a PNG, or `classic`
+    pub overlay: Option<PathBuf>,
+    pub autofire: Option<f64>,
+    pub autofire_p1: Option<f64>,
+    pub autofire_p2: Option<f64>,
+    pub freeze_on_game_over: bool,
+    /// in seconds
+    pub auto_restart: Option<f64>,
+    pub restart_when: Option<String>,
+    pub start_paused: bool,
+    /// the window's size, 1 to 6 times the game's
+    pub scale: Option<u32>,
+    pub borderless: bool,
+    pub always_on_top: bool,
+    pub fullscreen: bool,
+    pub software_render: bool,
+    /// in milliseconds
+    pub audio_latency: Option<u64>,
+    pub samples: Option<PathBuf>,
+    /// false for `--no-audio`
+    pub audio: Option<bool>,
+    pub catchup: Option<String>,
+    pub mute: Vec<String>,
+    /// 0 to 100
+    pub volume: Option<u8>,
+    pub debug_overlay: bool,
+    pub show_beam: bool,
+    pub accessible_hud: bool,
+    pub hud_corner: Option<String>,
+    pub hud_scale: Option<usize>,
+    /// ships at the start of a game, 3 to 6
+    pub lives: Option<u8>,
+    pub deadzone: Option<f32>,
+    pub sensitivity: Option<f32>,
+    pub invert_x: bool,
+    pub rumble: bool,
+    /// 1 to 100
+    pub rumble_strength: Option<u8>,
+    pub mouse_control: bool,
+    pub mouse_deadzone: Option<f32>,
+    pub mouse_sensitivity: Option<f32>,
+    pub pad_rom: bool,
+    pub watch_rom: bool,
+    pub port0_controls: bool,
+    /// control names to keys, see [`crate::keys`]
+    pub keys: BTreeMap<String, String>,
+    /// the keys as they were set, for [`Config::merge`]
+    #[serde(skip)]
+    table: toml::Table,
+}
+
+impl Config {
+    /// `space-invaders/config.toml` in `$XDG_CONFIG_HOME`, `~/.config` or `%APPDATA%`,
+    /// whichever is set first
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+        Some(dir.join(CONFIG_FILE))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config {}: {}", path.display(), e))?;
+        Config::parse(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    /// Errors give the line and column, with the line quoted
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config: Config = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.table = toml::from_str(text).map_err(|e| e.to_string())?;
+        Ok(config)
+    }
+
+    fn from_table(table: toml::Table) -> Result<Self, String> {
+        let mut config: Config = toml::Value::Table(table.clone())
+            .try_into()
+            .map_err(|e| e.to_string())?;
+        config.table = table;
+        Ok(config)
+    }
+
+    /// This config with every key set in `over` replacing its own, and the controls in
+    /// `over`'s `[keys]` replacing the same controls here. Only keys that were set in
+    /// the file or environment count, not fields filled in by hand.
+    pub fn merge(&self, over: &Config) -> Result<Self, String> {
+        let mut table = self.table.clone();
+        for (key, value) in &over.table {
+            match (table.get_mut(key), value) {
+                (Some(toml::Value::Table(keys)), toml::Value::Table(over_keys)) => {
+                    keys.extend(over_keys.clone());
+                }
+                _ => {
+                    table.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        Config::from_table(table)
+    }
+
+    /// The keys set by `SPACE_INVADERS_*` variables, each named after its key in upper
+    /// case with underscores for the dashes, like `SPACE_INVADERS_PIXEL_GRID=0.4` or
+    /// `SPACE_INVADERS_FULLSCREEN=true`. Values are written as in the file except that
+    /// strings can go without quotes and `SPACE_INVADERS_MUTE` also takes a list
+    /// separated by commas, as `--mute` does, like `ufo,fleet`. Other variables are
+    /// ignored, and an error names the variable at fault.
+    pub fn from_env(vars: impl IntoIterator<Item = (String, String)>) -> Result<Self, String> {
+        let mut table = toml::Table::new();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            let key = key.to_ascii_lowercase().replace('_', "-");
+            // anything that isn't a TOML value is taken as a string
+            let value = toml::from_str::<toml::Table>(&format!("value = {}", value))
+                .ok()
+                .and_then(|mut parsed| parsed.remove("value"))
+                .unwrap_or(toml::Value::String(value));
+            let value = match value {
+                toml::Value::String(list) if key == "mute" => toml::Value::Array(
+                    list.split(',')
+                        .map(|category| toml::Value::String(category.trim().to_string()))
+                        .collect(),
+                ),
+                value => value,
+            };
+
+            // tried alone so an error can be blamed on this variable
+            let mut one = toml::Table::new();
+            one.insert(key.clone(), value.clone());
+            toml::Value::Table(one)
+                .try_into::<Config>()
+                .map_err(|e| format!("Invalid {}: {}", name, e))?;
+            table.insert(key, value);
+        }
+        Config::from_table(table)
+    }
+
+    /// The flags this config stands for, to go before the command line
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut value = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(format!("--{}", flag));
+                args.push(value);
+            }
+        };
+
+        value("rom", self.rom.as_ref().map(|p| p.display().to_string()));
+        value("rotate", self.rotate.clone());
+        value("cabinet", self.cabinet.clone());
+        value("pixel-grid", self.pixel_grid.map(|v| v.to_string()));
+        value("persistence", self.persistence.map(|v| v.to_string()));
+        value("filter", self.filter.clone());
+        value("phosphor", self.phosphor.clone());
+        value(
+            "overlay",
+            self.overlay.as_ref().map(|p| p.display().to_string()),
+        );
+        value("autofire", self.autofire.map(|v| v.to_string()));
+        value("autofire-p1", self.autofire_p1.map(|v| v.to_string()));
+        value("autofire-p2", self.autofire_p2.map(|v| v.to_string()));
+        value("audio-latency", self.audio_latency.map(|v| v.to_string()));
+        value(
+            "samples",
+            self.samples.as_ref().map(|p| p.display().to_string()),
+        );
+        value("catchup", self.catchup.clone());
+        value("auto-restart", self.auto_restart.map(|v| v.to_string()));
+        value("restart-when", self.restart_when.clone());
+        value("hud-corner", self.hud_corner.clone());
+        value("scale", self.scale.map(|v| v.to_string()));
+        value("hud-scale", self.hud_scale.map(|v| v.to_string()));
+        value("lives", self.lives.map(|v| v.to_string()));
+        value("mute", (!self.mute.is_empty()).then(|| self.mute.join(",")));
+        value("volume", self.volume.map(|v| v.to_string()));
+        value("deadzone", self.deadzone.map(|v| v.to_string()));
+        value("sensitivity", self.sensitivity.map(|v| v.to_string()));
+        value(
+            "rumble-strength",
+            self.rumble_strength.map(|v| v.to_string()),
+        );
+        value("mouse-deadzone", self.mouse_deadzone.map(|v| v.to_string()));
+        value(
+            "mouse-sensitivity",
+            self.mouse_sensitivity.map(|v| v.to_string()),
+        );
+
+        for (control, keys) in &self.keys {
+            args.push("--bind".to_string());
+            args.push(format!("{}={}", control, keys));
+        }
+
+        let switches = [
+            ("no-quick-start", self.quick_start == Some(false)),
+            ("no-audio", self.audio == Some(false)),
+            ("linear-light", self.linear_light),
+            ("freeze-on-game-over", self.freeze_on_game_over),
+            ("start-paused", self.start_paused),
+            ("borderless", self.borderless),
+            ("always-on-top", self.always_on_top),
+            ("fullscreen", self.fullscreen),
+            ("software-render", self.software_render),
+            ("debug-overlay", self.debug_overlay),
+            ("show-beam", self.show_beam),
+            ("accessible-hud", self.accessible_hud),
+            ("invert-x", self.invert_x),
+            ("rumble", self.rumble),
+            ("mouse-control", self.mouse_control),
+            ("pad-rom", self.pad_rom),
+            ("watch-rom", self.watch_rom),
+            ("port0-controls", self.port0_controls),
+        ];
+        for (flag, on) in switches {
+            if on {
+                args.push(format!("--{}", flag));
+            }
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::config::Config;
+
+    #[test]
+    fn test_config_to_args() {
+        assert!(Config::default().to_args().is_empty());
+
+        let config = Config {
+            rotate: Some("cw".to_string()),
+            pixel_grid: Some(0.5),
+            scale: Some(3),
+            quick_start: Some(false),
+            mute: vec!["ufo".to_string(), "fleet".to_string()],
+            borderless: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.to_args(),
+            [
+                "--rotate",
+                "cw",
+                "--pixel-grid",
+                "0.5",
+                "--scale",
+                "3",
+                "--mute",
+                "ufo,fleet",
+                "--no-quick-start",
+                "--borderless"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_config_parse() {
+        let config =
+            Config::parse("rotate = \"cw\"\nmute = [\"ufo\"]\nborderless = true\n").unwrap();
+        assert_eq!(config.rotate.as_deref(), Some("cw"));
+        assert_eq!(config.mute, ["ufo"]);
+        assert!(config.borderless);
+        assert!(!config.start_paused);
+
+        let error = Config::parse("borderless = true\nrotate = 90\n").unwrap_err();
+        assert!(error.contains("line 2"), "{}", error);
+        assert!(Config::parse("rotation = \"cw\"").is_err());
+    }
+
+    #[test]
+    fn test_config_from_env() {
+        let vars = [
+            ("SPACE_INVADERS_ROTATE", "cw"),
+            ("SPACE_INVADERS_PIXEL_GRID", "0.4"),
+            ("SPACE_INVADERS_FULLSCREEN", "true"),
+            ("SPACE_INVADERS_MUTE", "ufo,fleet"),
+            ("SPACE_INVADERS_AUDIO", "false"),
+            ("SPACE_INVADERS_ROM", "roms/invaders.bin"),
+            ("HOME", "/home/player"),
+        ];
+        let config =
+            Config::from_env(vars.map(|(name, value)| (name.to_string(), value.to_string())))
+                .unwrap();
+        assert_eq!(config.rotate.as_deref(), Some("cw"));
+        assert_eq!(config.pixel_grid, Some(0.4));
+        assert!(config.fullscreen);
+        assert_eq!(config.mute, ["ufo", "fleet"]);
+        assert_eq!(config.audio, Some(false));
+        assert!(config.to_args().contains(&"--no-audio".to_string()));
+        assert_eq!(config.rom.as_deref(), Some(Path::new("roms/invaders.bin")));
+        // still takes an array, as the file does
+        let config =
+            Config::from_env([("SPACE_INVADERS_MUTE".to_string(), "[\"shots\"]".to_string())])
+                .unwrap();
+        assert_eq!(config.mute, ["shots"]);
+
+        let error = |name: &str, value: &str| {
+            Config::from_env([(name.to_string(), value.to_string())]).unwrap_err()
+        };
+        assert!(error("SPACE_INVADERS_FULLSCREEN", "1").contains("SPACE_INVADERS_FULLSCREEN"));
+        assert!(error("SPACE_INVADERS_ROTATION", "cw").contains("SPACE_INVADERS_ROTATION"));
+    }
+
+    #[test]
+    fn test_config_merge() {
+        let file = Config::parse(
+            "fullscreen = true\nrotate = \"cw\"\nvolume = 50\n[keys]\np1-shot = \"Space\"\np1-left = \"a\"\n",
+        )
+        .unwrap();
+        let env = Config::from_env(
+            [
+                ("SPACE_INVADERS_FULLSCREEN", "false"),
+                ("SPACE_INVADERS_VOLUME", "80"),
+                ("SPACE_INVADERS_KEYS", "{ p1-shot = \"z\" }"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+        .unwrap();
+
+        let merged = file.merge(&env).unwrap();
+        // the environment's false wins over the file's true
+        assert!(!merged.fullscreen);
+        assert!(!merged.to_args().contains(&"--fullscreen".to_string()));
+        assert_eq!(merged.volume, Some(80));
+        assert_eq!(merged.rotate.as_deref(), Some("cw"));
+        assert_eq!(merged.keys["p1-shot"], "z");
+        assert_eq!(merged.keys["p1-left"], "a");
+
+        // nothing set leaves the other side as it was
+        assert_eq!(file.merge(&Config::default()).unwrap(), file);
+        assert_eq!(Config::default().merge(&env).unwrap(), env);
+    }
+}
@@ -0,0 +1,262 @@
+//! Crash reports for bug reports.
+//!
+//! [`CrashReporter::install`] sets a panic hook that writes a plain text report to
+//! `space-invaders-crash-<unix time>.txt` in the working directory before the default
+//! hook prints the panic, so the report is on disk even if the process aborts. It
+//! holds:
+//!
+//! ```text
+//! panic: attempt to subtract with overflow
+//! location: src/machine.rs:812:9
+//! thread: main
+//! version: 0.1.0
+//! rom_crc32: 1b5bd0b4
+//! frame: 1234
+//! pc: $0A5F
+//! last 3 log lines:
+//! INFO space_invaders: Paused
+//! ...
+//! ```
+//!
+//! The ROM, frame and PC are `unknown` until the ROM is loaded and the first frame
+//! has run, and the frame and PC are as of the last vblank rather than the
+//! instruction that panicked. Log lines are kept by [`LogTail`] whether or not they
+//! were shown, down to info level.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::capture::crc32;
+use crate::machine::Machine;
+
+/// Log lines kept for the report
+pub const LOG_LINES: usize = 50;
+
+/// The most recent log lines, shared between the logger and the panic hook
+pub type LogLines = Arc<Mutex<VecDeque<String>>>;
+
+/// Passes records on to another logger, keeping the last [`LOG_LINES`] of them for a
+/// crash report, including those it filters out
+pub struct LogTail {
+    inner: Box<dyn Log>,
+    lines: LogLines,
+}
+
+impl LogTail {
+    pub fn new(inner: Box<dyn Log>) -> Self {
+        LogTail {
+            inner,
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(LOG_LINES))),
+        }
+    }
+
+    pub fn lines(&self) -> LogLines {
+        self.lines.clone()
+    }
+
+    /// Installs the logger, letting through at least info so there's something to
+    /// keep. `inner_level` is the most detailed level the inner logger shows.
+    pub fn init(self, inner_level: LevelFilter) -> Result<(), log::SetLoggerError> {
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(inner_level.max(LevelFilter::Info));
+        Ok(())
+    }
+}
+
+impl Log for LogTail {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Info || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+
+        if let Ok(mut lines) = self.lines.lock() {
+            if lines.len() == LOG_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(format!(
+                "{} {}: {}",
+                record.level(),
+                record.target(),
+                record.args()
+            ));
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// What the machine was doing, for a crash report
+#[derive(Default)]
+struct Context {
+    rom_crc32: OnceLock<u32>,
+    /// set once the first frame has run
+    running: AtomicBool,
+    frame: AtomicU64,
+    pc: AtomicU16,
+}
+
+/// Writes a crash report when anything panics, see the [module docs](self)
+#[derive(Clone)]
+pub struct CrashReporter {
+    context: Arc<Context>,
+}
+
+impl CrashReporter {
+    /// Sets the panic hook, keeping the one already set to run after the report is
+    /// written. A later hook that does the same, as `--dump-on-exit` does, runs first.
+    pub fn install(log_lines: LogLines) -> Self {
+        let context = Arc::new(Context::default());
+
+        let hook_context = context.clone();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let message = info
+                .payload()
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("(no message)");
+            let location = info
+                .location()
+                .map(|location| location.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            let thread = std::thread::current();
+            // the panic may have come from inside the logger
+            let lines: Vec<String> = match log_lines.try_lock() {
+                Ok(lines) => lines.iter().cloned().collect(),
+                Err(_) => Vec::new(),
+            };
+
+            let report = hook_context.report(
+                message,
+                &location,
+                thread.name().unwrap_or("unnamed"),
+                &lines,
+            );
+            let path = report_path();
+            match std::fs::write(&path, report) {
+                Ok(()) => eprintln!("Saved a crash report to {}", path.display()),
+                Err(e) => eprintln!("Error saving crash report: {}", e),
+            }
+            default_hook(info);
+        }));
+
+        CrashReporter { context }
+    }
+
+    /// Records the ROM being run. Only the first call counts.
+    pub fn set_rom(&self, rom: &[u8]) {
+        let _ = self.context.rom_crc32.set(crc32(rom));
+    }
+
+    /// Records the frame and PC, called once a frame
+    pub fn update(&self, machine: &Machine) {
+        self.context.frame.store(machine.frame(), Ordering::Relaxed);
+        self.context.pc.store(machine.cpu.pc(), Ordering::Relaxed);
+        self.context.running.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Context {
+    fn report(&self, message: &str, location: &str, thread: &str, lines: &[String]) -> String {
+        let mut report = String::new();
+        // writing to a String can't fail
+        let _ = writeln!(report, "panic: {}", message);
+        let _ = writeln!(report, "location: {}", location);
+        let _ = writeln!(report, "thread: {}", thread);
+        let _ = writeln!(report, "version: {}", env!("CARGO_PKG_VERSION"));
+        let _ = match self.rom_crc32.get() {
+            Some(crc) => writeln!(report, "rom_crc32: {:08x}", crc),
+            None => writeln!(report, "rom_crc32: unknown"),
+        };
+        let _ = if self.running.load(Ordering::Relaxed) {
+            writeln!(
+                report,
+                "frame: {}\npc: ${:04X}",
+                self.frame.load(Ordering::Relaxed),
+                self.pc.load(Ordering::Relaxed)
+            )
+        } else {
+            writeln!(report, "frame: unknown\npc: unknown")
+        };
+        let _ = writeln!(report, "last {} log lines:", lines.len());
+        for line in lines {
+            let _ = writeln!(report, "{}", line);
+        }
+        report
+    }
+}
+
+fn report_path() -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    PathBuf::from(format!("space-invaders-crash-{}.txt", secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use crate::crash::{Context, LogTail, LOG_LINES};
+
+    struct Quiet;
+
+    impl log::Log for Quiet {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            false
+        }
+        fn log(&self, _: &log::Record) {}
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn test_log_tail() {
+        let tail = LogTail::new(Box::new(Quiet));
+        for i in 0..LOG_LINES + 2 {
+            log::Log::log(
+                &tail,
+                &log::Record::builder()
+                    .level(log::Level::Warn)
+                    .target("test")
+                    .args(format_args!("line {}", i))
+                    .build(),
+            );
+        }
+
+        // kept even though the inner logger shows nothing
+        let lines = tail.lines();
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines.len(), LOG_LINES);
+        assert_eq!(lines[0], "WARN test: line 2");
+    }
+
+    #[test]
+    fn test_report() {
+        let context = Context::default();
+        let report = context.report("oops", "src/main.rs:1:1", "main", &[]);
+        assert!(report.starts_with("panic: oops\nlocation: src/main.rs:1:1\nthread: main\n"));
+        assert!(report.contains("rom_crc32: unknown\nframe: unknown\npc: unknown\n"));
+
+        let _ = context.rom_crc32.set(0x1234);
+        context.frame.store(60, Ordering::Relaxed);
+        context.pc.store(0x0A5F, Ordering::Relaxed);
+        context.running.store(true, Ordering::Relaxed);
+        let report = context.report("oops", "here", "emulator", &["INFO a: b".to_string()]);
+        assert!(report.contains("rom_crc32: 00001234\nframe: 60\npc: $0A5F\n"));
+        assert!(report.ends_with("last 1 log lines:\nINFO a: b\n"));
+    }
+}
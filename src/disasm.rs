@@ -0,0 +1,166 @@
+//! Intel 8080 disassembler.
+//!
+//! Mnemonics follow Intel's assembler with operands in hex, so `MVI A,$42` and
+//! `JMP $18D4`. Opcodes the 8080 doesn't document decode to the instruction they
+//! behave as, marked with a `*`.
+
+/// Mnemonic and length in bytes of every opcode. `d8` and `d16`/`a16` stand for the
+/// immediate byte and word that follow the opcode.
+#[rustfmt::skip]
+const OPCODES: [(&str, usize); 256] = [
+    // 0x00
+    ("NOP", 1), ("LXI B,d16", 3), ("STAX B", 1), ("INX B", 1), ("INR B", 1), ("DCR B", 1), ("MVI B,d8", 2), ("RLC", 1),
+    // 0x08
+    ("*NOP", 1), ("DAD B", 1), ("LDAX B", 1), ("DCX B", 1), ("INR C", 1), ("DCR C", 1), ("MVI C,d8", 2), ("RRC", 1),
+    // 0x10
+    ("*NOP", 1), ("LXI D,d16", 3), ("STAX D", 1), ("INX D", 1), ("INR D", 1), ("DCR D", 1), ("MVI D,d8", 2), ("RAL", 1),
+    // 0x18
+    ("*NOP", 1), ("DAD D", 1), ("LDAX D", 1), ("DCX D", 1), ("INR E", 1), ("DCR E", 1), ("MVI E,d8", 2), ("RAR", 1),
+    // 0x20
+    ("*NOP", 1), ("LXI H,d16", 3), ("SHLD a16", 3), ("INX H", 1), ("INR H", 1), ("DCR H", 1), ("MVI H,d8", 2), ("DAA", 1),
+    // 0x28
+    ("*NOP", 1), ("DAD H", 1), ("LHLD a16", 3), ("DCX H", 1), ("INR L", 1), ("DCR L", 1), ("MVI L,d8", 2), ("CMA", 1),
+    // 0x30
+    ("*NOP", 1), ("LXI SP,d16", 3), ("STA a16", 3), ("INX SP", 1), ("INR M", 1), ("DCR M", 1), ("MVI M,d8", 2), ("STC", 1),
+    // 0x38
+    ("*NOP", 1), ("DAD SP", 1), ("LDA a16", 3), ("DCX SP", 1), ("INR A", 1), ("DCR A", 1), ("MVI A,d8", 2), ("CMC", 1),
+    // 0x40
+    ("MOV B,B", 1), ("MOV B,C", 1), ("MOV B,D", 1), ("MOV B,E", 1), ("MOV B,H", 1), ("MOV B,L", 1), ("MOV B,M", 1), ("MOV B,A", 1),
+    // 0x48
+    ("MOV C,B", 1), ("MOV C,C", 1), ("MOV C,D", 1), ("MOV C,E", 1), ("MOV C,H", 1), ("MOV C,L", 1), ("MOV C,M", 1), ("MOV C,A", 1),
+    // 0x50
+    ("MOV D,B", 1), ("MOV D,C", 1), ("MOV D,D", 1), ("MOV D,E", 1), ("MOV D,H", 1), ("MOV D,L", 1), ("MOV D,M", 1), ("MOV D,A", 1),
+    // 0x58
+    ("MOV E,B", 1), ("MOV E,C", 1), ("MOV E,D", 1), ("MOV E,E", 1), ("MOV E,H", 1), ("MOV E,L", 1), ("MOV E,M", 1), ("MOV E,A", 1),
+    // 0x60
+    ("MOV H,B", 1), ("MOV H,C", 1), ("MOV H,D", 1), ("MOV H,E", 1), ("MOV H,H", 1), ("MOV H,L", 1), ("MOV H,M", 1), ("MOV H,A", 1),
+    // 0x68
+    ("MOV L,B", 1), ("MOV L,C", 1), ("MOV L,D", 1), ("MOV L,E", 1), ("MOV L,H", 1), ("MOV L,L", 1), ("MOV L,M", 1), ("MOV L,A", 1),
+    // 0x70
+    ("MOV M,B", 1), ("MOV M,C", 1), ("MOV M,D", 1), ("MOV M,E", 1), ("MOV M,H", 1), ("MOV M,L", 1), ("HLT", 1), ("MOV M,A", 1),
+    // 0x78
+    ("MOV A,B", 1), ("MOV A,C", 1), ("MOV A,D", 1), ("MOV A,E", 1), ("MOV A,H", 1), ("MOV A,L", 1), ("MOV A,M", 1), ("MOV A,A", 1),
+    // 0x80
+    ("ADD B", 1), ("ADD C", 1), ("ADD D", 1), ("ADD E", 1), ("ADD H", 1), ("ADD L", 1), ("ADD M", 1), ("ADD A", 1),
+    // 0x88
+    ("ADC B", 1), ("ADC C", 1), ("ADC D", 1), ("ADC E", 1), ("ADC H", 1), ("ADC L", 1), ("ADC M", 1), ("ADC A", 1),
+    // 0x90
+    ("SUB B", 1), ("SUB C", 1), ("SUB D", 1), ("SUB E", 1), ("SUB H", 1), ("SUB L", 1), ("SUB M", 1), ("SUB A", 1),
+    // 0x98
+    ("SBB B", 1), ("SBB C", 1), ("SBB D", 1), ("SBB E", 1), ("SBB H", 1), ("SBB L", 1), ("SBB M", 1), ("SBB A", 1),
+    // 0xA0
+    ("ANA B", 1), ("ANA C", 1), ("ANA D", 1), ("ANA E", 1), ("ANA H", 1), ("ANA L", 1), ("ANA M", 1), ("ANA A", 1),
+    // 0xA8
+    ("XRA B", 1), ("XRA C", 1), ("XRA D", 1), ("XRA E", 1), ("XRA H", 1), ("XRA L", 1), ("XRA M", 1), ("XRA A", 1),
+    // 0xB0
+    ("ORA B", 1), ("ORA C", 1), ("ORA D", 1), ("ORA E", 1), ("ORA H", 1), ("ORA L", 1), ("ORA M", 1), ("ORA A", 1),
+    // 0xB8
+    ("CMP B", 1), ("CMP C", 1), ("CMP D", 1), ("CMP E", 1), ("CMP H", 1), ("CMP L", 1), ("CMP M", 1), ("CMP A", 1),
+    // 0xC0
+    ("RNZ", 1), ("POP B", 1), ("JNZ a16", 3), ("JMP a16", 3), ("CNZ a16", 3), ("PUSH B", 1), ("ADI d8", 2), ("RST 0", 1),
+    // 0xC8
+    ("RZ", 1), ("RET", 1), ("JZ a16", 3), ("*JMP a16", 3), ("CZ a16", 3), ("CALL a16", 3), ("ACI d8", 2), ("RST 1", 1),
+    // 0xD0
+    ("RNC", 1), ("POP D", 1), ("JNC a16", 3), ("OUT d8", 2), ("CNC a16", 3), ("PUSH D", 1), ("SUI d8", 2), ("RST 2", 1),
+    // 0xD8
+    ("RC", 1), ("*RET", 1), ("JC a16", 3), ("IN d8", 2), ("CC a16", 3), ("*CALL a16", 3), ("SBI d8", 2), ("RST 3", 1),
+    // 0xE0
+    ("RPO", 1), ("POP H", 1), ("JPO a16", 3), ("XTHL", 1), ("CPO a16", 3), ("PUSH H", 1), ("ANI d8", 2), ("RST 4", 1),
+    // 0xE8
+    ("RPE", 1), ("PCHL", 1), ("JPE a16", 3), ("XCHG", 1), ("CPE a16", 3), ("*CALL a16", 3), ("XRI d8", 2), ("RST 5", 1),
+    // 0xF0
+    ("RP", 1), ("POP PSW", 1), ("JP a16", 3), ("DI", 1), ("CP a16", 3), ("PUSH PSW", 1), ("ORI d8", 2), ("RST 6", 1),
+    // 0xF8
+    ("RM", 1), ("SPHL", 1), ("JM a16", 3), ("EI", 1), ("CM a16", 3), ("*CALL a16", 3), ("CPI d8", 2), ("RST 7", 1),
+
+];
+
+/// Decodes the instruction at the start of `bytes`, returning its length and mnemonic.
+/// If `bytes` ends before the instruction does, the first byte is shown as data.
+pub fn disassemble_instruction(bytes: &[u8]) -> (usize, String) {
+    let Some(&opcode) = bytes.first() else {
+        return (0, String::new());
+    };
+
+    let (template, len) = OPCODES[opcode as usize];
+    if bytes.len() < len {
+        return (1, format!("DB ${:02X}", opcode));
+    }
+
+    let mnemonic = match len {
+        2 => template.replace("d8", &format!("${:02X}", bytes[1])),
+        3 => {
+            let word = format!("${:04X}", u16::from_le_bytes([bytes[1], bytes[2]]));
+            template.replace("d16", &word).replace("a16", &word)
+        }
+        _ => template.to_string(),
+    };
+
+    (len, mnemonic)
+}
+
+/// Disassembles `code` as loaded at `origin`, one `(address, length, mnemonic)` per instruction
+pub fn disassemble(code: &[u8], origin: u16) -> Vec<(u16, usize, String)> {
+    let mut listing = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let (len, mnemonic) = disassemble_instruction(&code[offset..]);
+        listing.push((origin.wrapping_add(offset as u16), len, mnemonic));
+        offset += len;
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::disasm::{disassemble, disassemble_instruction};
+
+    #[test]
+    fn test_disassemble_instruction() {
+        assert_eq!(disassemble_instruction(&[0x00]), (1, "NOP".to_string()));
+        assert_eq!(
+            disassemble_instruction(&[0x3E, 0x42]),
+            (2, "MVI A,$42".to_string())
+        );
+        assert_eq!(
+            disassemble_instruction(&[0x31, 0x00, 0x24]),
+            (3, "LXI SP,$2400".to_string())
+        );
+        assert_eq!(
+            disassemble_instruction(&[0xC3, 0xD4, 0x18]),
+            (3, "JMP $18D4".to_string())
+        );
+        assert_eq!(disassemble_instruction(&[0x7E]), (1, "MOV A,M".to_string()));
+        assert_eq!(
+            disassemble_instruction(&[0xD3, 0x06]),
+            (2, "OUT $06".to_string())
+        );
+        assert_eq!(disassemble_instruction(&[0xDF]), (1, "RST 3".to_string()));
+        assert_eq!(
+            disassemble_instruction(&[0xF5]),
+            (1, "PUSH PSW".to_string())
+        );
+        assert_eq!(
+            disassemble_instruction(&[0xEC, 0x34, 0x12]),
+            (3, "CPE $1234".to_string())
+        );
+        assert_eq!(
+            disassemble_instruction(&[0xDD, 0x00, 0x00]).1,
+            "*CALL $0000"
+        );
+
+        // cut off before the operand
+        assert_eq!(
+            disassemble_instruction(&[0xCD, 0x00]),
+            (1, "DB $CD".to_string())
+        );
+        assert_eq!(disassemble_instruction(&[]), (0, String::new()));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let listing = disassemble(&[0x00, 0x00, 0x00, 0xC3, 0xD4, 0x18], 0x0000);
+        assert_eq!(listing.len(), 4);
+        assert_eq!(listing[3], (0x0003, 3, "JMP $18D4".to_string()));
+    }
+}
@@ -0,0 +1,350 @@
+//! Machine-readable event stream for bots, overlays and analytics.
+//!
+//! Each event is written as a single JSON object on its own line:
+//!
+//! ```text
+//! {"frame":1234,"timestamp_ms":1700000000000,"event":"score","player":1,"score":150}
+//! ```
+//!
+//! Every line carries `frame` (VBlanks since power on), `timestamp_ms` (milliseconds
+//! since the UNIX epoch) and `event`. The remaining fields depend on the event:
+//!
+//! | event           | fields                                      |
+//! |-----------------|---------------------------------------------|
+//! | `score`         | `player` (1 or 2), `score` (as shown)       |
+//! | `life_lost`     | `player` (1 or 2), `lives` (remaining)      |
+//! | `game_start`    |                                             |
+//! | `game_over`     |                                             |
+//! | `wave_cleared`  | `player` (1 or 2), `wave` (1 for the first) |
+//! | `coin_inserted` | `credits` (after the coin)                  |
+//! | `ufo_destroyed` |                                             |
+//!
+//! New fields may be added to an event but existing ones will not change meaning.
+//!
+//! Apart from `ufo_destroyed`, which comes from the sound it makes, events are found
+//! by [`RamWatcher`] comparing RAM (see [`addr`]) with the frame before, so each
+//! change gives one event however long the new value stays:
+//!
+//! - `game_start` and `game_over` when [`addr::GAME_MODE`] turns non-zero and back
+//! - `score` when a player's score changes
+//! - `life_lost` when a player's ship count goes down during a game
+//! - `coin_inserted` when [`addr::CREDITS`] goes up
+//! - `wave_cleared` when [`addr::NUM_ALIENS`] reaches zero during a game, for the
+//!   player [`addr::PLAYER_DATA_MSB`] points at. Waves are counted here from the start
+//!   of the game rather than read from the game.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::error;
+
+use emu8080::MemoryAccess;
+
+use crate::addr;
+use crate::memory::{bcd_to_decimal, SpaceInvadersMemory};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Score { player: u8, score: u16 },
+    LifeLost { player: u8, lives: u8 },
+    GameStart,
+    GameOver,
+    CoinInserted { credits: u8 },
+    UfoDestroyed,
+    WaveCleared { player: u8, wave: u16 },
+}
+
+impl Event {
+    fn to_json(self, frame: u64, timestamp_ms: u128) -> String {
+        let fields = match self {
+            Event::Score { player, score } => {
+                format!(r#""event":"score","player":{},"score":{}"#, player, score)
+            }
+            Event::LifeLost { player, lives } => {
+                format!(
+                    r#""event":"life_lost","player":{},"lives":{}"#,
+                    player, lives
+                )
+            }
+            Event::GameStart => r#""event":"game_start""#.to_string(),
+            Event::GameOver => r#""event":"game_over""#.to_string(),
+            Event::CoinInserted { credits } => {
+                format!(r#""event":"coin_inserted","credits":{}"#, credits)
+            }
+            Event::UfoDestroyed => r#""event":"ufo_destroyed""#.to_string(),
+            Event::WaveCleared { player, wave } => {
+                format!(
+                    r#""event":"wave_cleared","player":{},"wave":{}"#,
+                    player, wave
+                )
+            }
+        };
+
+        format!(
+            r#"{{"frame":{},"timestamp_ms":{},{}}}"#,
+            frame, timestamp_ms, fields
+        )
+    }
+}
+
+/// Writes events from a background thread so a slow disk can't stall emulation. The
+/// thread is joined when the writer is dropped, so every event emitted is on disk by
+/// then.
+pub struct EventWriter {
+    sender: Option<Sender<String>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EventWriter {
+    pub fn create(path: &Path) -> Result<Self, std::io::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (sender, receiver) = channel::<String>();
+
+        let thread = std::thread::spawn(move || {
+            for line in receiver {
+                if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+                    error!("Error writing event stream: {}", e);
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn emit(&self, frame: u64, event: Event) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+
+        // the writer thread only goes away after an I/O error, which it has already logged
+        if let Some(ref sender) = self.sender {
+            let _ = sender.send(event.to_json(frame, timestamp_ms));
+        }
+    }
+}
+
+impl Drop for EventWriter {
+    fn drop(&mut self) {
+        // closing the channel lets the thread finish the lines still queued and stop
+        self.sender = None;
+        if let Some(Err(e)) = self.thread.take().map(|thread| thread.join()) {
+            error!("Error joining event stream thread: {:?}", e);
+        }
+    }
+}
+
+/// Derives events by comparing the game's RAM between frames
+#[derive(Default)]
+pub struct RamWatcher {
+    scores: [u16; 2],
+    ships: [u8; 2],
+    credits: u8,
+    in_game: bool,
+    aliens: u8,
+    /// waves cleared by each player this game
+    waves: [u16; 2],
+}
+
+impl RamWatcher {
+    pub fn update(&mut self, memory: &SpaceInvadersMemory) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        // only cleared once GAME OVER is up, so the event comes with it on screen
+        let in_game = memory.read_byte(addr::GAME_MODE) != 0;
+        if in_game && !self.in_game {
+            events.push(Event::GameStart);
+            self.waves = [0; 2];
+        } else if !in_game && self.in_game {
+            events.push(Event::GameOver);
+        }
+        self.in_game = in_game;
+
+        let scores = [
+            u16::from_le_bytes(memory.read_bytes::<2>(addr::P1_SCORE)),
+            u16::from_le_bytes(memory.read_bytes::<2>(addr::P2_SCORE)),
+        ];
+        let ships = [
+            memory.read_byte(addr::P1_SHIPS),
+            memory.read_byte(addr::P2_SHIPS),
+        ];
+
+        for (i, &score) in scores.iter().enumerate() {
+            let player = i as u8 + 1;
+
+            if score != self.scores[i] {
+                events.push(Event::Score {
+                    player,
+                    score: bcd_to_decimal(score),
+                });
+            }
+
+            // the ship count is also rewritten when a game starts
+            if in_game && ships[i] < self.ships[i] {
+                events.push(Event::LifeLost {
+                    player,
+                    lives: ships[i],
+                });
+            }
+        }
+        self.scores = scores;
+        self.ships = ships;
+
+        let credits = bcd_to_decimal(memory.read_byte(addr::CREDITS) as u16) as u8;
+        if credits > self.credits {
+            events.push(Event::CoinInserted { credits });
+        }
+        self.credits = credits;
+
+        let aliens = memory.read_byte(addr::NUM_ALIENS);
+        if in_game && aliens == 0 && self.aliens != 0 {
+            let player = if memory.read_byte(addr::PLAYER_DATA_MSB) == 0x22 {
+                2
+            } else {
+                1
+            };
+            let waves = &mut self.waves[player as usize - 1];
+            *waves += 1;
+            events.push(Event::WaveCleared {
+                player,
+                wave: *waves,
+            });
+        }
+        self.aliens = aliens;
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::addr;
+    use crate::events::{Event, EventWriter, RamWatcher};
+    use crate::memory::{RotationMode, SpaceInvadersMemory};
+    use crate::ROM_SIZE;
+    use emu8080::MemoryAccess;
+
+    #[test]
+    fn test_event_writer_flushes_on_drop() {
+        let path = std::env::temp_dir().join(format!("events-test-{}.jsonl", std::process::id()));
+        let writer = EventWriter::create(&path).unwrap();
+        for frame in 0..100 {
+            writer.emit(frame, Event::GameStart);
+        }
+        writer.emit(100, Event::GameOver);
+        drop(writer);
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(text.lines().count(), 101);
+        assert!(text
+            .lines()
+            .last()
+            .unwrap()
+            .contains(r#""event":"game_over""#));
+    }
+
+    #[test]
+    fn test_event_json() {
+        assert_eq!(
+            Event::Score {
+                player: 2,
+                score: 150
+            }
+            .to_json(7, 1000),
+            r#"{"frame":7,"timestamp_ms":1000,"event":"score","player":2,"score":150}"#
+        );
+        assert_eq!(
+            Event::GameOver.to_json(1, 2),
+            r#"{"frame":1,"timestamp_ms":2,"event":"game_over"}"#
+        );
+    }
+
+    #[test]
+    fn test_ram_watcher() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        let mut watcher = RamWatcher::default();
+        assert_eq!(watcher.update(&memory), vec![]);
+
+        memory.write_byte(addr::CREDITS, 0x01);
+        assert_eq!(
+            watcher.update(&memory),
+            vec![Event::CoinInserted { credits: 1 }]
+        );
+
+        memory.write_byte(addr::CREDITS, 0x00);
+        memory.write_byte(addr::GAME_MODE, 0x01);
+        memory.write_byte(addr::P1_SHIPS, 0x03);
+        assert_eq!(watcher.update(&memory), vec![Event::GameStart]);
+
+        memory.write_bytes(addr::P1_SCORE, &[0x50, 0x01]);
+        memory.write_byte(addr::P1_SHIPS, 0x02);
+        assert_eq!(
+            watcher.update(&memory),
+            vec![
+                Event::Score {
+                    player: 1,
+                    score: 150
+                },
+                Event::LifeLost {
+                    player: 1,
+                    lives: 2
+                }
+            ]
+        );
+
+        memory.write_byte(addr::GAME_MODE, 0x00);
+        assert_eq!(watcher.update(&memory), vec![Event::GameOver]);
+    }
+
+    #[test]
+    fn test_wave_cleared() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        let mut watcher = RamWatcher::default();
+
+        // the attract mode's demo clearing a wave doesn't count
+        memory.write_byte(addr::NUM_ALIENS, 55);
+        watcher.update(&memory);
+        memory.write_byte(addr::NUM_ALIENS, 0);
+        assert_eq!(watcher.update(&memory), vec![]);
+
+        memory.write_byte(addr::GAME_MODE, 0x01);
+        memory.write_byte(addr::PLAYER_DATA_MSB, 0x21);
+        memory.write_byte(addr::NUM_ALIENS, 55);
+        assert_eq!(watcher.update(&memory), vec![Event::GameStart]);
+
+        memory.write_byte(addr::NUM_ALIENS, 0);
+        assert_eq!(
+            watcher.update(&memory),
+            vec![Event::WaveCleared { player: 1, wave: 1 }]
+        );
+        // staying at zero until the next wave is set up is the same clear
+        assert_eq!(watcher.update(&memory), vec![]);
+
+        memory.write_byte(addr::PLAYER_DATA_MSB, 0x22);
+        memory.write_byte(addr::NUM_ALIENS, 55);
+        watcher.update(&memory);
+        memory.write_byte(addr::NUM_ALIENS, 0);
+        assert_eq!(
+            watcher.update(&memory),
+            vec![Event::WaveCleared { player: 2, wave: 1 }]
+        );
+
+        memory.write_byte(addr::PLAYER_DATA_MSB, 0x21);
+        memory.write_byte(addr::NUM_ALIENS, 55);
+        watcher.update(&memory);
+        memory.write_byte(addr::NUM_ALIENS, 0);
+        assert_eq!(
+            watcher.update(&memory),
+            vec![Event::WaveCleared { player: 1, wave: 2 }]
+        );
+    }
+}
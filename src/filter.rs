@@ -0,0 +1,291 @@
+//! Scaling the finished frame up to the window.
+//!
+//! `pixels` scales by whole multiples with nearest-neighbour sampling and puts black
+//! bars around the rest, which keeps every game pixel the same crisp square. With
+//! [`Filter::Linear`] the frame is instead stretched to fill as much of the window as
+//! its aspect ratio allows and sampled bilinearly, so windows that aren't a whole
+//! multiple of the display get no bars and no uneven pixels, at the cost of softer
+//! edges.
+//!
+//! Fullscreen always scales by whole multiples, as [`integer_fit`] works out, since
+//! a monitor is rarely a multiple of the display and the bars are the price of even
+//! pixels at any filter.
+
+use pixels::{wgpu, Pixels, PixelsContext};
+
+/// How the frame is sampled when it's scaled to the window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Filter {
+    /// whole multiples only, every pixel crisp and the same size
+    #[default]
+    Nearest,
+    /// fills the window, smooth at any size but slightly blurred
+    Linear,
+}
+
+impl std::str::FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest" => Ok(Filter::Nearest),
+            "linear" => Ok(Filter::Linear),
+            _ => Err(format!(
+                "Unknown filter '{}' (expected nearest or linear)",
+                s
+            )),
+        }
+    }
+}
+
+/// Draws one triangle over the viewport and samples the frame across it
+const LINEAR_SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) tex_coord: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.tex_coord = uv;
+    return out;
+}
+
+@group(0) @binding(0) var frame: texture_2d<f32>;
+@group(0) @binding(1) var frame_sampler: sampler;
+
+@fragment
+fn fs_main(@location(0) tex_coord: vec2<f32>) -> @location(0) vec4<f32> {
+    return textureSample(frame, frame_sampler, tex_coord);
+}
+"#;
+
+/// Replaces the `pixels` scaling renderer for [`Filter::Linear`], through
+/// [`Pixels::render_with`]
+pub struct LinearRenderer {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    frame_size: (u32, u32),
+    surface_size: (u32, u32),
+    /// whole multiples only, for fullscreen
+    integer: bool,
+}
+
+impl LinearRenderer {
+    pub fn new(pixels: &Pixels, surface_width: u32, surface_height: u32, integer: bool) -> Self {
+        let context = pixels.context();
+        let device = &context.device;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("linear_filter_shader"),
+            source: wgpu::ShaderSource::Wgsl(LINEAR_SHADER.into()),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("linear_filter_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let texture_view = context
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("linear_filter_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("linear_filter_bind_group"),
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("linear_filter_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("linear_filter_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: Default::default(),
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: Default::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: pixels.render_texture_format(),
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        });
+
+        LinearRenderer {
+            pipeline,
+            bind_group,
+            frame_size: (context.texture_extent.width, context.texture_extent.height),
+            surface_size: (surface_width, surface_height),
+            integer,
+        }
+    }
+
+    /// Whole multiples only or not, as when going in or out of fullscreen
+    pub fn set_integer(&mut self, integer: bool) {
+        self.integer = integer;
+    }
+
+    /// Call alongside [`Pixels::resize_surface`]
+    pub fn resize(&mut self, surface_width: u32, surface_height: u32) {
+        self.surface_size = (surface_width, surface_height);
+    }
+
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        _context: &PixelsContext,
+    ) {
+        let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("linear_filter_pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        let (x, y, width, height) = if self.integer {
+            let (scale, x, y) = integer_fit(self.frame_size, self.surface_size);
+            (
+                x as f32,
+                y as f32,
+                (self.frame_size.0 * scale) as f32,
+                (self.frame_size.1 * scale) as f32,
+            )
+        } else {
+            fit(self.frame_size, self.surface_size)
+        };
+        pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+/// The largest rectangle with the frame's aspect ratio centred in the surface, as
+/// `(x, y, width, height)`
+pub(crate) fn fit(frame: (u32, u32), surface: (u32, u32)) -> (f32, f32, f32, f32) {
+    let scale = (surface.0 as f32 / frame.0 as f32).min(surface.1 as f32 / frame.1 as f32);
+    let width = frame.0 as f32 * scale;
+    let height = frame.1 as f32 * scale;
+    (
+        (surface.0 as f32 - width) / 2.0,
+        (surface.1 as f32 - height) / 2.0,
+        width,
+        height,
+    )
+}
+
+/// The largest whole multiple of the frame that fits in the surface, centred, as
+/// `(scale, x, y)`. The scale is at least 1, so a surface smaller than the frame
+/// crops it rather than shrinking it.
+pub fn integer_fit(frame: (u32, u32), surface: (u32, u32)) -> (u32, u32, u32) {
+    let scale = (surface.0 / frame.0).min(surface.1 / frame.1).max(1);
+    (
+        scale,
+        surface.0.saturating_sub(frame.0 * scale) / 2,
+        surface.1.saturating_sub(frame.1 * scale) / 2,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::filter::{fit, integer_fit, Filter};
+
+    #[test]
+    fn test_filter_from_str() {
+        assert_eq!("nearest".parse(), Ok(Filter::Nearest));
+        assert_eq!("linear".parse(), Ok(Filter::Linear));
+        assert!("bicubic".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn test_fit() {
+        assert_eq!(fit((224, 256), (224, 256)), (0.0, 0.0, 224.0, 256.0));
+        // wider window, bars either side
+        assert_eq!(fit((224, 256), (1000, 512)), (276.0, 0.0, 448.0, 512.0));
+        // an odd scale
+        assert_eq!(fit((256, 224), (640, 700)), (0.0, 70.0, 640.0, 560.0));
+
+        // however far the window is stretched, the frame keeps its shape
+        assert_eq!(fit((224, 256), (4480, 256)), (2128.0, 0.0, 224.0, 256.0));
+        assert_eq!(fit((224, 256), (224, 5120)), (0.0, 2432.0, 224.0, 256.0));
+        for surface in [(10_000, 300), (300, 10_000), (3, 7919)] {
+            let (_, _, width, height) = fit((224, 256), surface);
+            assert!((width / height - 0.875).abs() < 1e-3, "{:?}", surface);
+        }
+    }
+
+    #[test]
+    fn test_integer_fit() {
+        assert_eq!(integer_fit((224, 256), (224, 256)), (1, 0, 0));
+        // 1080p fits four times the height, with bars on all sides
+        assert_eq!(integer_fit((224, 256), (1920, 1080)), (4, 512, 28));
+        assert_eq!(integer_fit((224, 256), (2560, 1440)), (5, 720, 80));
+        // rotated sideways
+        assert_eq!(integer_fit((256, 224), (1920, 1080)), (4, 448, 92));
+        // too small to fit even once
+        assert_eq!(integer_fit((224, 256), (200, 200)), (1, 0, 0));
+        // a long thin window, bars all along the long side
+        assert_eq!(integer_fit((224, 256), (4000, 300)), (1, 1888, 22));
+        assert_eq!(integer_fit((224, 256), (300, 4000)), (1, 38, 1872));
+    }
+}
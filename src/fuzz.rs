@@ -0,0 +1,40 @@
+//! Headless entry point for fuzzing the CPU core and the board around it.
+//!
+//! A fuzz target feeds arbitrary bytes in as the ROM and checks the run neither panics
+//! nor hangs. The instruction cap keeps ROMs that never return (which is most of them)
+//! finite.
+
+use crate::machine::{new_inputs, CabinetType, Machine};
+use crate::memory::{RotationMode, SpaceInvadersMemory};
+use crate::ROM_SIZE;
+
+/// Runs at most `max_instructions` instructions from power on with `rom` mapped at 0x0000
+/// and returns the machine in its final state. Short images are padded with zeros and
+/// anything past the ROM region is ignored.
+pub fn run_rom(rom: &[u8], max_instructions: u64) -> Machine {
+    let mut image = [0u8; ROM_SIZE];
+    let len = rom.len().min(ROM_SIZE);
+    image[..len].copy_from_slice(&rom[..len]);
+
+    let memory = SpaceInvadersMemory::new(image, RotationMode::default());
+    let mut machine = Machine::new(memory, new_inputs(), CabinetType::Upright);
+    machine.run_instructions(max_instructions);
+    machine
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fuzz::run_rom;
+    use crate::ROM_SIZE;
+
+    #[test]
+    fn test_run_rom_terminates() {
+        // JMP 0x0000
+        let machine = run_rom(&[0xC3, 0x00, 0x00], 10_000);
+        assert!(machine.emu_clock() > 0);
+
+        // oversized and empty images are both accepted
+        run_rom(&vec![0xFF; ROM_SIZE * 2], 10_000);
+        run_rom(&[], 10_000);
+    }
+}
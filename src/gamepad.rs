@@ -0,0 +1,275 @@
+//! Gamepads, through gilrs, working player 1's controls.
+//!
+//! The d-pad or the left stick moves, the south face button (A on an Xbox pad, cross
+//! on a PlayStation one) or the west one fires, Start starts a one player game and
+//! Select puts a coin in. The stick goes through a [`DigitalAxis`], so `--deadzone`,
+//! `--sensitivity` and `--invert-x` apply to it.
+//!
+//! With `--rumble`, pads with force feedback give a short rumble when the player loses
+//! a ship, as strong as `--rumble-strength` asks. Pads without it are left out.
+//!
+//! Pads can be plugged in and pulled out while the game runs. Each pad's state is kept
+//! on its own and a control is held while any pad holds it, so one pad letting go
+//! doesn't release a button another is holding, and a pad pulled out lets go of
+//! everything it held.
+
+use std::collections::HashMap;
+
+use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Replay, Ticks};
+use gilrs::{Axis, EventType, Gilrs};
+use log::{debug, info, warn};
+
+use crate::joystick::{AxisConfig, AxisDirection, DigitalAxis};
+use crate::machine::Button;
+
+/// How long a lost ship rumbles for
+const RUMBLE_TIME_MS: u32 = 300;
+
+/// Default `--rumble-strength`, out of 100
+pub const DEFAULT_RUMBLE_STRENGTH: u8 = 75;
+
+/// The force feedback magnitude for a strength out of 100
+pub fn rumble_magnitude(strength: u8) -> u16 {
+    (u16::MAX as u32 * strength.min(100) as u32 / 100) as u16
+}
+
+/// The controls a pad can work
+const PAD_BUTTONS: [Button; 5] = [
+    Button::P1Left,
+    Button::P1Right,
+    Button::P1Fire,
+    Button::Start1P,
+    Button::Coin,
+];
+
+/// What one pad is holding
+#[derive(Clone, Copy, Debug, Default)]
+struct PadState {
+    stick: DigitalAxis,
+    left: bool,
+    right: bool,
+    fire: [bool; 2],
+    start: bool,
+    select: bool,
+}
+
+impl PadState {
+    fn holds(&self, button: Button) -> bool {
+        match button {
+            Button::P1Left => self.left || self.stick.direction() == AxisDirection::Negative,
+            Button::P1Right => self.right || self.stick.direction() == AxisDirection::Positive,
+            Button::P1Fire => self.fire[0] || self.fire[1],
+            Button::Start1P => self.start,
+            Button::Coin => self.select,
+            _ => false,
+        }
+    }
+}
+
+/// Every pad's state, turned into presses and releases of the cabinet's controls.
+/// Kept apart from gilrs so it can be driven by hand.
+#[derive(Clone, Debug, Default)]
+pub struct PadInputs {
+    stick: AxisConfig,
+    /// by gilrs's pad id
+    pads: HashMap<usize, PadState>,
+    held: Vec<Button>,
+}
+
+impl PadInputs {
+    pub fn new(stick: AxisConfig) -> Self {
+        PadInputs {
+            stick,
+            pads: HashMap::new(),
+            held: Vec::new(),
+        }
+    }
+
+    fn pad(&mut self, pad: usize) -> &mut PadState {
+        let stick = self.stick;
+        self.pads.entry(pad).or_insert_with(|| PadState {
+            stick: DigitalAxis::new(stick),
+            ..Default::default()
+        })
+    }
+
+    /// A pad's button went down or up, ignoring the buttons that aren't mapped
+    pub fn button(&mut self, pad: usize, button: gilrs::Button, pressed: bool) {
+        let state = self.pad(pad);
+        let switch = match button {
+            gilrs::Button::DPadLeft => &mut state.left,
+            gilrs::Button::DPadRight => &mut state.right,
+            gilrs::Button::South => &mut state.fire[0],
+            gilrs::Button::West => &mut state.fire[1],
+            gilrs::Button::Start => &mut state.start,
+            gilrs::Button::Select => &mut state.select,
+            _ => return,
+        };
+        *switch = pressed;
+    }
+
+    /// The left stick's sideways position, from -1.0 to 1.0
+    pub fn stick(&mut self, pad: usize, position: f32) {
+        self.pad(pad).stick.update(position);
+    }
+
+    /// Forgets a pad that was pulled out, letting go of whatever it held
+    pub fn disconnect(&mut self, pad: usize) {
+        self.pads.remove(&pad);
+    }
+
+    /// The controls pressed or released since the last call, with true for pressed
+    pub fn changes(&mut self) -> Vec<(Button, bool)> {
+        let mut changes = Vec::new();
+        for button in PAD_BUTTONS {
+            let held = self.pads.values().any(|pad| pad.holds(button));
+            if held != self.held.contains(&button) {
+                if held {
+                    self.held.push(button);
+                } else {
+                    self.held.retain(|&other| other != button);
+                }
+                changes.push((button, held));
+            }
+        }
+        changes
+    }
+}
+
+/// The connected pads, polled from the event loop
+pub struct Gamepads {
+    gilrs: Gilrs,
+    inputs: PadInputs,
+    /// the last rumble, which stops if it's dropped
+    rumble: Option<Effect>,
+}
+
+impl Gamepads {
+    pub fn new(stick: AxisConfig) -> Result<Self, String> {
+        let gilrs = Gilrs::new().map_err(|e| format!("Could not set up gamepads: {}", e))?;
+        for (_, gamepad) in gilrs.gamepads() {
+            info!("Gamepad connected: {}", gamepad.name());
+        }
+        Ok(Gamepads {
+            gilrs,
+            inputs: PadInputs::new(stick),
+            rumble: None,
+        })
+    }
+
+    /// Rumbles every connected pad that has force feedback, `strength` out of 100
+    pub fn rumble(&mut self, strength: u8) {
+        let pads: Vec<_> = self
+            .gilrs
+            .gamepads()
+            .filter(|(_, gamepad)| gamepad.is_ff_supported())
+            .map(|(id, _)| id)
+            .collect();
+        if pads.is_empty() {
+            debug!("No gamepad with force feedback to rumble");
+            return;
+        }
+
+        let effect = EffectBuilder::new()
+            .add_effect(BaseEffect {
+                kind: BaseEffectType::Strong {
+                    magnitude: rumble_magnitude(strength),
+                },
+                scheduling: Replay {
+                    play_for: Ticks::from_ms(RUMBLE_TIME_MS),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .gamepads(&pads)
+            .finish(&mut self.gilrs);
+        match effect.and_then(|effect| effect.play().map(|_| effect)) {
+            Ok(effect) => self.rumble = Some(effect),
+            Err(e) => warn!("Could not rumble the gamepads: {}", e),
+        }
+    }
+
+    /// Handles the pads' events since the last call, returning the controls pressed or
+    /// released as [`PadInputs::changes`] does
+    pub fn poll(&mut self) -> Vec<(Button, bool)> {
+        while let Some(event) = self.gilrs.next_event() {
+            let pad = usize::from(event.id);
+            match event.event {
+                EventType::ButtonPressed(button, _) => self.inputs.button(pad, button, true),
+                EventType::ButtonReleased(button, _) => self.inputs.button(pad, button, false),
+                EventType::AxisChanged(Axis::LeftStickX, position, _) => {
+                    self.inputs.stick(pad, position)
+                }
+                EventType::Connected => {
+                    info!("Gamepad connected: {}", self.gilrs.gamepad(event.id).name());
+                }
+                EventType::Disconnected => {
+                    info!("Gamepad disconnected");
+                    self.inputs.disconnect(pad);
+                }
+                _ => {}
+            }
+        }
+        self.inputs.changes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gamepad::{rumble_magnitude, PadInputs};
+    use crate::joystick::AxisConfig;
+    use crate::machine::Button;
+
+    #[test]
+    fn test_rumble_magnitude() {
+        assert_eq!(rumble_magnitude(0), 0);
+        assert_eq!(rumble_magnitude(50), 32767);
+        assert_eq!(rumble_magnitude(100), u16::MAX);
+        assert_eq!(rumble_magnitude(200), u16::MAX);
+    }
+
+    #[test]
+    fn test_pad_inputs() {
+        let mut inputs = PadInputs::new(AxisConfig::default());
+        assert_eq!(inputs.changes(), []);
+
+        inputs.button(0, gilrs::Button::South, true);
+        inputs.button(0, gilrs::Button::North, true);
+        inputs.stick(0, -0.9);
+        assert_eq!(
+            inputs.changes(),
+            [(Button::P1Left, true), (Button::P1Fire, true)]
+        );
+        // only changes are reported
+        assert_eq!(inputs.changes(), []);
+
+        // held by either fire button
+        inputs.button(0, gilrs::Button::West, true);
+        inputs.button(0, gilrs::Button::South, false);
+        inputs.button(0, gilrs::Button::Select, true);
+        assert_eq!(inputs.changes(), [(Button::Coin, true)]);
+
+        // a second pad holding fire keeps it held when the first is pulled out
+        inputs.button(1, gilrs::Button::South, true);
+        inputs.button(1, gilrs::Button::DPadRight, true);
+        inputs.button(1, gilrs::Button::Start, true);
+        assert_eq!(
+            inputs.changes(),
+            [(Button::P1Right, true), (Button::Start1P, true)]
+        );
+        inputs.disconnect(0);
+        assert_eq!(
+            inputs.changes(),
+            [(Button::P1Left, false), (Button::Coin, false)]
+        );
+        inputs.disconnect(1);
+        assert_eq!(
+            inputs.changes(),
+            [
+                (Button::P1Right, false),
+                (Button::P1Fire, false),
+                (Button::Start1P, false)
+            ]
+        );
+    }
+}
@@ -0,0 +1,135 @@
+//! Where the input ports' bits come from.
+//!
+//! Every frame the machine polls each [`InputSource`] and merges what they return into
+//! the three bytes the game reads that frame:
+//!
+//! - the first source to return [`InputState::Exclusive`] is used alone, so a replay
+//!   or a remote player can take over completely and give up again when it's done
+//! - otherwise every [`InputState::Held`] is ORed together, so the keyboard, a
+//!   gamepad and a bot can all press buttons at once
+//! - [`InputState::Idle`] sources are left out
+//!
+//! Sources are polled in order: an input replay first, then the shared [`Inputs`]
+//! that the keyboard and mouse write to, which is always there and carries the DIP
+//! switches, then the [`InputQueue`], then anything added with
+//! [`Machine::add_input_source`](crate::machine::Machine::add_input_source).
+//! Since bits are only ever ORed in, a source can press a button but not release one
+//! another source holds, and the active-high bits that read as 1 with nothing pressed
+//! come from the shared inputs.
+
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::machine::{InputQueue, Inputs};
+
+/// What one source is doing to the input ports this frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputState {
+    /// nothing to add this frame
+    #[default]
+    Idle,
+    /// bits to OR with the other sources'
+    Held([u8; 3]),
+    /// these ports exactly, whatever the other sources say
+    Exclusive([u8; 3]),
+}
+
+/// Something that presses the cabinet's buttons, polled once per emulated frame when
+/// the inputs are sampled
+pub trait InputSource: Send {
+    fn poll(&mut self) -> InputState;
+}
+
+/// Merges the states polled from every source, see the [module docs](self). Only
+/// [`InputState::Idle`] if every state is.
+pub fn merge(states: impl IntoIterator<Item = InputState>) -> InputState {
+    let mut merged = InputState::Idle;
+    for state in states {
+        match (state, merged) {
+            (InputState::Exclusive(_), _) => return state,
+            (InputState::Held(ports), InputState::Held(held)) => {
+                merged =
+                    InputState::Held([held[0] | ports[0], held[1] | ports[1], held[2] | ports[2]]);
+            }
+            (InputState::Held(_), _) => merged = state,
+            (InputState::Idle, _) => {}
+        }
+    }
+    merged
+}
+
+/// The ports as the keyboard and mouse handlers have left them
+impl InputSource for Arc<Inputs> {
+    fn poll(&mut self) -> InputState {
+        InputState::Held([
+            self.0.load(Ordering::Relaxed),
+            self.1.load(Ordering::Relaxed),
+            self.2.load(Ordering::Relaxed),
+        ])
+    }
+}
+
+impl InputSource for InputQueue {
+    fn poll(&mut self) -> InputState {
+        if self.is_idle() {
+            return InputState::Idle;
+        }
+        InputState::Held([0, self.held(1), self.held(2)])
+    }
+}
+
+/// Recorded inputs, a frame at a time until they run out
+impl InputSource for std::vec::IntoIter<[u8; 3]> {
+    fn poll(&mut self) -> InputState {
+        self.next().map_or(InputState::Idle, InputState::Exclusive)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::input::{merge, InputSource, InputState};
+    use crate::machine::{new_inputs, InputPulse, InputQueue};
+
+    #[test]
+    fn test_merge() {
+        assert_eq!(merge([]), InputState::Idle);
+        assert_eq!(merge([InputState::Idle]), InputState::Idle);
+        assert_eq!(
+            merge([
+                InputState::Held([0x01, 0x00, 0x00]),
+                InputState::Idle,
+                InputState::Held([0x00, 0x20, 0x01]),
+                InputState::Held([0x01, 0x40, 0x00]),
+            ]),
+            InputState::Held([0x01, 0x60, 0x01])
+        );
+
+        // the first exclusive source wins outright
+        assert_eq!(
+            merge([
+                InputState::Held([0xFF, 0xFF, 0xFF]),
+                InputState::Exclusive([0x01, 0x02, 0x03]),
+                InputState::Exclusive([0x04, 0x05, 0x06]),
+            ]),
+            InputState::Exclusive([0x01, 0x02, 0x03])
+        );
+    }
+
+    #[test]
+    fn test_sources() {
+        let mut inputs = new_inputs();
+        assert_eq!(
+            inputs.poll(),
+            InputState::Held([0b1000_1111, 0b0000_1000, 0b0000_0000])
+        );
+
+        let mut queue = InputQueue::new();
+        assert_eq!(queue.poll(), InputState::Idle);
+        queue.push(InputPulse::credit());
+        assert_eq!(queue.poll(), InputState::Held([0, 0x01, 0]));
+
+        let mut replay = vec![[1, 2, 3]].into_iter();
+        assert_eq!(replay.poll(), InputState::Exclusive([1, 2, 3]));
+        assert_eq!(replay.poll(), InputState::Idle);
+    }
+}
@@ -0,0 +1,214 @@
+//! Turning analog stick positions, and mouse movement, into the cabinet's digital
+//! left/right switches.
+
+use std::time::{Duration, Instant};
+
+/// How far past the deadzone edge a stick has to come back before it lets go, as a
+/// fraction of the deadzone. Without it a stick resting on the edge would chatter.
+const RELEASE_FRACTION: f32 = 0.75;
+
+/// Settings for one stick axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisConfig {
+    /// positions closer to the centre than this (0.0 to 1.0) count as centred
+    pub deadzone: f32,
+    /// multiplies the position before the deadzone is applied
+    pub sensitivity: f32,
+    /// swaps the ends of the axis
+    pub inverted: bool,
+}
+
+impl Default for AxisConfig {
+    fn default() -> Self {
+        AxisConfig {
+            deadzone: 0.25,
+            sensitivity: 1.0,
+            inverted: false,
+        }
+    }
+}
+
+impl AxisConfig {
+    pub fn new(deadzone: f32, sensitivity: f32, inverted: bool) -> Result<Self, String> {
+        if !(0.0..1.0).contains(&deadzone) {
+            return Err(format!(
+                "Deadzone must be at least 0 and below 1, got {}",
+                deadzone
+            ));
+        }
+        if sensitivity <= 0.0 {
+            return Err(format!("Sensitivity must be above 0, got {}", sensitivity));
+        }
+
+        Ok(AxisConfig {
+            deadzone,
+            sensitivity,
+            inverted,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AxisDirection {
+    /// left or up
+    Negative,
+    #[default]
+    Centre,
+    /// right or down
+    Positive,
+}
+
+/// One stick axis acting as a pair of switches
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DigitalAxis {
+    pub config: AxisConfig,
+    direction: AxisDirection,
+}
+
+impl DigitalAxis {
+    pub fn new(config: AxisConfig) -> Self {
+        DigitalAxis {
+            config,
+            direction: AxisDirection::Centre,
+        }
+    }
+
+    pub fn direction(&self) -> AxisDirection {
+        self.direction
+    }
+
+    /// Feeds in a new stick position from -1.0 to 1.0 and returns the direction held
+    pub fn update(&mut self, position: f32) -> AxisDirection {
+        let mut position = (position * self.config.sensitivity).clamp(-1.0, 1.0);
+        if self.config.inverted {
+            position = -position;
+        }
+
+        let press = self.config.deadzone;
+        let release = self.config.deadzone * RELEASE_FRACTION;
+
+        self.direction = match self.direction {
+            AxisDirection::Positive if position > release => AxisDirection::Positive,
+            AxisDirection::Negative if position < -release => AxisDirection::Negative,
+            _ if position > press => AxisDirection::Positive,
+            _ if position < -press => AxisDirection::Negative,
+            _ => AxisDirection::Centre,
+        };
+
+        self.direction
+    }
+}
+
+/// Pixels the cursor has to move between two events to push all the way, before
+/// sensitivity
+pub const MOUSE_FULL_SCALE: f64 = 8.0;
+/// How long the cursor has to stay still before the mouse lets go
+pub const MOUSE_IDLE: Duration = Duration::from_millis(60);
+
+/// Sideways mouse movement acting as a pair of switches, held while the cursor keeps
+/// moving and let go once it stops. The deadzone is a fraction of
+/// [`MOUSE_FULL_SCALE`], so slow drifts and jitter don't move the ship.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MouseAxis {
+    axis: DigitalAxis,
+    last_x: Option<f64>,
+    last_moved: Option<Instant>,
+}
+
+impl MouseAxis {
+    pub fn new(config: AxisConfig) -> Self {
+        MouseAxis {
+            axis: DigitalAxis::new(config),
+            last_x: None,
+            last_moved: None,
+        }
+    }
+
+    pub fn direction(&self) -> AxisDirection {
+        self.axis.direction()
+    }
+
+    /// Feeds in the cursor's new x position in pixels and returns the direction held.
+    /// The first position after [`MouseAxis::leave`] only sets where it starts from.
+    pub fn moved(&mut self, x: f64, now: Instant) -> AxisDirection {
+        let dx = self.last_x.map_or(0.0, |last| x - last);
+        self.last_x = Some(x);
+        self.last_moved = Some(now);
+        self.axis.update((dx / MOUSE_FULL_SCALE) as f32)
+    }
+
+    /// Lets go once the cursor has been still for [`MOUSE_IDLE`], returning the
+    /// direction held
+    pub fn idle(&mut self, now: Instant) -> AxisDirection {
+        if self
+            .last_moved
+            .is_some_and(|moved| now.duration_since(moved) >= MOUSE_IDLE)
+        {
+            self.last_moved = None;
+            return self.axis.update(0.0);
+        }
+        self.axis.direction()
+    }
+
+    /// Lets go straight away, for when the cursor leaves the window
+    pub fn leave(&mut self) -> AxisDirection {
+        self.last_x = None;
+        self.last_moved = None;
+        self.axis.update(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::joystick::{AxisConfig, AxisDirection, DigitalAxis, MouseAxis, MOUSE_IDLE};
+
+    #[test]
+    fn test_digital_axis() {
+        assert!(AxisConfig::new(1.0, 1.0, false).is_err());
+        assert!(AxisConfig::new(0.2, 0.0, false).is_err());
+
+        let mut axis = DigitalAxis::new(AxisConfig::new(0.2, 1.0, false).unwrap());
+        assert_eq!(axis.update(0.1), AxisDirection::Centre);
+        assert_eq!(axis.update(0.21), AxisDirection::Positive);
+
+        // wobbling just under the threshold doesn't let go
+        assert_eq!(axis.update(0.19), AxisDirection::Positive);
+        assert_eq!(axis.update(0.21), AxisDirection::Positive);
+        assert_eq!(axis.update(0.1), AxisDirection::Centre);
+
+        // nor does it take the other direction straight from the centre
+        assert_eq!(axis.update(-0.19), AxisDirection::Centre);
+        assert_eq!(axis.update(-0.5), AxisDirection::Negative);
+        assert_eq!(axis.update(0.5), AxisDirection::Positive);
+    }
+
+    #[test]
+    fn test_axis_sensitivity_and_inversion() {
+        let mut axis = DigitalAxis::new(AxisConfig::new(0.2, 2.0, true).unwrap());
+        assert_eq!(axis.update(0.15), AxisDirection::Negative);
+        assert_eq!(axis.update(-0.05), AxisDirection::Centre);
+        assert_eq!(axis.update(-0.15), AxisDirection::Positive);
+    }
+
+    #[test]
+    fn test_mouse_axis() {
+        let mut mouse = MouseAxis::new(AxisConfig::new(0.25, 1.0, false).unwrap());
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        // the first position is only a starting point, and a pixel is jitter
+        assert_eq!(mouse.moved(100.0, at(0)), AxisDirection::Centre);
+        assert_eq!(mouse.moved(101.0, at(10)), AxisDirection::Centre);
+        assert_eq!(mouse.moved(95.0, at(20)), AxisDirection::Negative);
+
+        // held until the cursor has been still for long enough
+        assert_eq!(mouse.idle(at(20) + MOUSE_IDLE / 2), AxisDirection::Negative);
+        assert_eq!(mouse.idle(at(20) + MOUSE_IDLE), AxisDirection::Centre);
+
+        assert_eq!(mouse.moved(105.0, at(200)), AxisDirection::Positive);
+        assert_eq!(mouse.leave(), AxisDirection::Centre);
+        assert_eq!(mouse.moved(0.0, at(210)), AxisDirection::Centre);
+    }
+}
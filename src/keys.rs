@@ -0,0 +1,177 @@
+//! Which keys work the cabinet's controls, set with `--bind` or the `[keys]` table of
+//! the config file.
+//!
+//! Keys go by the names winit gives them: a key that types a character by that
+//! character, like `c` or `1`, and any other by its name, like `ArrowLeft` or `Space`.
+//! Letters match whether or not shift is held. Binding a control replaces its default
+//! keys:
+//!
+//! | control    | default      |
+//! |------------|--------------|
+//! | `credit`   | `c`          |
+//! | `start-1p` | `1`          |
+//! | `start-2p` | `2`          |
+//! | `p1-left`  | `ArrowLeft`  |
+//! | `p1-right` | `ArrowRight` |
+//! | `p1-shot`  | `ArrowUp`    |
+//! | `p2-left`  | `a`          |
+//! | `p2-right` | `d`          |
+//! | `p2-shot`  | `w`          |
+//! | `tilt`     | none         |
+//!
+//! A key bound to a control goes to the game even if it's also one of the emulator's
+//! hotkeys, so binding `p` gives up pausing from the keyboard.
+
+use crate::machine::Button;
+
+/// The keys other than characters that can be bound
+const NAMED_KEYS: [&str; 27] = [
+    "ArrowLeft",
+    "ArrowRight",
+    "ArrowUp",
+    "ArrowDown",
+    "Space",
+    "Enter",
+    "Tab",
+    "Backspace",
+    "Shift",
+    "Control",
+    "Alt",
+    "Home",
+    "End",
+    "PageUp",
+    "PageDown",
+    "Insert",
+    "Delete",
+    "F1",
+    "F2",
+    "F3",
+    "F4",
+    "F5",
+    "F6",
+    "F7",
+    "F8",
+    "F9",
+    "F10",
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyBindings {
+    bindings: Vec<(Button, Vec<String>)>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let keys = |keys: &[&str]| keys.iter().map(|key| key.to_string()).collect();
+        KeyBindings {
+            bindings: vec![
+                (Button::Coin, keys(&["c"])),
+                (Button::Start1P, keys(&["1"])),
+                (Button::Start2P, keys(&["2"])),
+                (Button::P1Left, keys(&["ArrowLeft"])),
+                (Button::P1Right, keys(&["ArrowRight"])),
+                (Button::P1Fire, keys(&["ArrowUp"])),
+                (Button::P2Left, keys(&["a"])),
+                (Button::P2Right, keys(&["d"])),
+                (Button::P2Fire, keys(&["w"])),
+            ],
+        }
+    }
+}
+
+impl KeyBindings {
+    /// Takes `control=key`, or several keys separated by commas, as `--bind` does,
+    /// replacing the keys the control had. The keys are taken from any other control
+    /// they were bound to.
+    pub fn bind(&mut self, binding: &str) -> Result<(), String> {
+        let (control, keys) = binding
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid binding '{}' (expected CONTROL=KEY)", binding))?;
+        let button: Button = control.parse()?;
+        let keys = keys
+            .split(',')
+            .map(key_name)
+            .collect::<Result<Vec<String>, String>>()?;
+
+        self.bindings.retain(|(bound, _)| *bound != button);
+        for (_, bound_keys) in &mut self.bindings {
+            bound_keys.retain(|key| !keys.contains(key));
+        }
+        self.bindings.push((button, keys));
+        Ok(())
+    }
+
+    /// The control `key` is bound to, `key` named as in the [module docs](self)
+    pub fn button(&self, key: &str) -> Option<Button> {
+        let key = fold_case(key);
+        self.bindings
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|&(button, _)| button)
+    }
+}
+
+/// A key's name as it's looked up, or an error for a key that can't be bound
+fn key_name(key: &str) -> Result<String, String> {
+    if key.chars().count() == 1 {
+        return Ok(fold_case(key));
+    }
+    NAMED_KEYS
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(key))
+        .map(|name| name.to_string())
+        .ok_or_else(|| {
+            format!(
+                "Unknown key '{}' (expected a character or one of {})",
+                key,
+                NAMED_KEYS.join(", ")
+            )
+        })
+}
+
+/// Characters in lower case, so shift doesn't change which key it is
+fn fold_case(key: &str) -> String {
+    if key.chars().count() == 1 {
+        key.to_lowercase()
+    } else {
+        key.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keys::KeyBindings;
+    use crate::machine::Button;
+
+    #[test]
+    fn test_default_bindings() {
+        let keys = KeyBindings::default();
+        assert_eq!(keys.button("c"), Some(Button::Coin));
+        assert_eq!(keys.button("C"), Some(Button::Coin));
+        assert_eq!(keys.button("ArrowUp"), Some(Button::P1Fire));
+        assert_eq!(keys.button("d"), Some(Button::P2Right));
+        assert_eq!(keys.button("p"), None);
+        assert_eq!(keys.button("Space"), None);
+    }
+
+    #[test]
+    fn test_bind() {
+        let mut keys = KeyBindings::default();
+        keys.bind("p1-shot=space,Z").unwrap();
+        assert_eq!(keys.button("Space"), Some(Button::P1Fire));
+        assert_eq!(keys.button("z"), Some(Button::P1Fire));
+        // the default is replaced
+        assert_eq!(keys.button("ArrowUp"), None);
+        assert_eq!(keys.button("ArrowLeft"), Some(Button::P1Left));
+
+        keys.bind("tilt=t").unwrap();
+        assert_eq!(keys.button("t"), Some(Button::Tilt));
+        // player 2 loses a key to player 1
+        keys.bind("p1-left=a").unwrap();
+        assert_eq!(keys.button("a"), Some(Button::P1Left));
+
+        assert!(keys.bind("p1-shot").is_err());
+        assert!(keys.bind("p3-shot=x").is_err());
+        assert!(keys.bind("p1-shot=ArrowSideways").is_err());
+    }
+}
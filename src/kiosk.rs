@@ -0,0 +1,222 @@
+//! Keeping an unattended machine lively, for `--auto-restart`.
+//!
+//! [`AutoRestart`] is shown the machine once a frame and asks for a reset when it has
+//! been idle for the timeout. What counts as idle is set with [`Idle`]. Whatever it's
+//! set to, a screen that hasn't changed for the timeout means the game has wedged, as
+//! the attract mode never stands still for more than a few seconds, and staying
+//! paused for the timeout counts too, so a kiosk left frozen on
+//! `--freeze-on-game-over` comes back.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use emu8080::MemoryAccess;
+
+use crate::addr;
+use crate::machine::Machine;
+
+/// What counts as nobody being there
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Idle {
+    /// the inputs haven't changed, in a game or out of one
+    #[default]
+    NoInput,
+    /// no game is being played, so it's sat on the attract mode since the last one
+    GameOver,
+}
+
+impl std::str::FromStr for Idle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "no-input" => Ok(Idle::NoInput),
+            "game-over" => Ok(Idle::GameOver),
+            _ => Err(format!(
+                "Unknown idle condition '{}' (expected no-input or game-over)",
+                s
+            )),
+        }
+    }
+}
+
+/// Why [`AutoRestart`] asked for a reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartReason {
+    NoInput,
+    GameOver,
+    Wedged,
+    Paused,
+}
+
+impl fmt::Display for RestartReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RestartReason::NoInput => "no input",
+            RestartReason::GameOver => "no game played",
+            RestartReason::Wedged => "screen frozen",
+            RestartReason::Paused => "left paused",
+        })
+    }
+}
+
+/// What the machine looked like on one frame, as far as [`AutoRestart`] cares
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Activity {
+    pub inputs: [u8; 3],
+    pub in_game: bool,
+    /// see [`Machine::frame_hash`]
+    pub screen: u64,
+}
+
+impl Activity {
+    pub fn of(machine: &Machine) -> Self {
+        Activity {
+            inputs: machine.latched_inputs(),
+            in_game: machine.memory.read_byte(addr::GAME_MODE) != 0,
+            screen: machine.frame_hash(),
+        }
+    }
+}
+
+pub struct AutoRestart {
+    timeout: Duration,
+    idle: Idle,
+    /// when someone was last there, going by `idle`
+    active: Instant,
+    /// when the screen last changed
+    drawn: Instant,
+    last: Option<Activity>,
+}
+
+impl AutoRestart {
+    pub fn new(timeout: Duration, idle: Idle, now: Instant) -> Self {
+        AutoRestart {
+            timeout,
+            idle,
+            active: now,
+            drawn: now,
+            last: None,
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Takes in a frame, returning why a reset is due if it is. The timers start again
+    /// after each reset asked for.
+    pub fn update(&mut self, now: Instant, activity: Activity) -> Option<RestartReason> {
+        let last = self.last.replace(activity);
+        let active = match self.idle {
+            Idle::NoInput => last.is_some_and(|last| last.inputs != activity.inputs),
+            Idle::GameOver => activity.in_game,
+        };
+        if active {
+            self.active = now;
+        }
+        if last.is_some_and(|last| last.screen != activity.screen) {
+            self.drawn = now;
+        }
+
+        let reason = if now.duration_since(self.drawn) >= self.timeout {
+            RestartReason::Wedged
+        } else if now.duration_since(self.active) >= self.timeout {
+            match self.idle {
+                Idle::NoInput => RestartReason::NoInput,
+                Idle::GameOver => RestartReason::GameOver,
+            }
+        } else {
+            return None;
+        };
+        self.restarted(now);
+        Some(reason)
+    }
+
+    /// Called instead of [`AutoRestart::update`] while the emulator is paused
+    pub fn update_paused(&mut self, now: Instant) -> Option<RestartReason> {
+        if now.duration_since(self.active.max(self.drawn)) < self.timeout {
+            return None;
+        }
+        self.restarted(now);
+        Some(RestartReason::Paused)
+    }
+
+    fn restarted(&mut self, now: Instant) {
+        self.active = now;
+        self.drawn = now;
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::kiosk::{Activity, AutoRestart, Idle, RestartReason};
+
+    fn activity(inputs: u8, in_game: bool, screen: u64) -> Activity {
+        Activity {
+            inputs: [inputs, 0, 0],
+            in_game,
+            screen,
+        }
+    }
+
+    #[test]
+    fn test_idle_from_str() {
+        assert_eq!("no-input".parse(), Ok(Idle::NoInput));
+        assert_eq!("game-over".parse(), Ok(Idle::GameOver));
+        assert!("never".parse::<Idle>().is_err());
+    }
+
+    #[test]
+    fn test_no_input() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut restart = AutoRestart::new(Duration::from_secs(10), Idle::NoInput, start);
+
+        assert_eq!(restart.update(at(0), activity(0, false, 1)), None);
+        assert_eq!(restart.update(at(5), activity(1, true, 2)), None);
+        // ten seconds since the press, the screen changing all along
+        assert_eq!(restart.update(at(14), activity(1, true, 3)), None);
+        assert_eq!(
+            restart.update(at(15), activity(1, true, 4)),
+            Some(RestartReason::NoInput)
+        );
+        // and counting again from the reset
+        assert_eq!(restart.update(at(16), activity(1, true, 5)), None);
+    }
+
+    #[test]
+    fn test_game_over() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut restart = AutoRestart::new(Duration::from_secs(10), Idle::GameOver, start);
+
+        // a game with nobody pressing anything is still someone there
+        assert_eq!(restart.update(at(8), activity(0, true, 1)), None);
+        assert_eq!(restart.update(at(17), activity(0, false, 2)), None);
+        assert_eq!(
+            restart.update(at(18), activity(0, false, 3)),
+            Some(RestartReason::GameOver)
+        );
+    }
+
+    #[test]
+    fn test_wedged() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut restart = AutoRestart::new(Duration::from_secs(10), Idle::GameOver, start);
+
+        assert_eq!(restart.update(at(0), activity(0, true, 1)), None);
+        assert_eq!(restart.update(at(9), activity(0, true, 1)), None);
+        assert_eq!(
+            restart.update(at(10), activity(0, true, 1)),
+            Some(RestartReason::Wedged)
+        );
+
+        assert_eq!(restart.update_paused(at(19)), None);
+        assert_eq!(restart.update_paused(at(20)), Some(RestartReason::Paused));
+    }
+}
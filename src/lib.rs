@@ -0,0 +1,149 @@
+#![allow(dead_code)]
+
+pub mod addr;
+pub mod audio;
+pub mod capture;
+pub mod compare;
+pub mod config;
+pub mod crash;
+pub mod disasm;
+pub mod events;
+pub mod filter;
+pub mod fuzz;
+pub mod gamepad;
+pub mod input;
+pub mod joystick;
+pub mod keys;
+pub mod kiosk;
+pub mod machine;
+pub mod memory;
+pub mod render;
+pub mod rominfo;
+pub mod selftest;
+pub mod software;
+pub mod timing;
+pub mod trace;
+pub mod watch;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use log::warn;
+
+use crate::machine::Machine;
+
+#[allow(non_camel_case_types)]
+
+pub const SCREEN_WIDTH_PIXELS: usize = 256;
+pub const SCREEN_HEIGHT_PIXELS: usize = 224;
+
+pub const DISPLAY_WIDTH_PIXELS: usize = SCREEN_HEIGHT_PIXELS;
+pub const DISPLAY_HEIGHT_PIXELS: usize = SCREEN_WIDTH_PIXELS;
+
+pub const SCREEN_SIZE_PIXELS: usize = SCREEN_WIDTH_PIXELS * SCREEN_HEIGHT_PIXELS;
+pub const DISPLAY_BUFFER_SIZE: usize = SCREEN_SIZE_PIXELS * 4;
+
+pub const DISPLAY_TIME_NANO_SEC: u64 = 16_666_667;
+
+pub const ROM_SIZE: usize = 0x2000;
+pub const RAM_SIZE: usize = 0x400;
+pub const VRAM_SIZE: usize = 0x1C00;
+
+pub const ROM_START: usize = 0;
+pub const RAM_START: usize = 0x2000;
+pub const VRAM_START: usize = 0x2400;
+
+pub const ROM_END: usize = ROM_START + ROM_SIZE;
+pub const RAM_END: usize = RAM_START + RAM_SIZE;
+pub const VRAM_END: usize = VRAM_START + VRAM_SIZE;
+
+/// The board decodes A0-A14 only
+pub const ADDRESS_MASK: usize = 0x7FFF;
+/// Where RAM and VRAM repeat above the empty ROM space at 0x4000
+pub const RAM_MIRROR_START: usize = 0x6000;
+
+/// A machine running on a thread of its own, for embedders that drive it from
+/// elsewhere. The thread runs while `running` is set and hands the machine back when
+/// it's cleared. Dropping the handle shuts the thread down, so whatever it records on
+/// the way out is still finished.
+pub struct EmulatorThread {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Machine>>,
+}
+
+impl EmulatorThread {
+    /// Sets `running` and starts `run` on a new thread. `run` should return the machine
+    /// once `running` is cleared.
+    pub fn spawn(running: Arc<AtomicBool>, run: impl FnOnce() -> Machine + Send + 'static) -> Self {
+        running.store(true, Ordering::Relaxed);
+        EmulatorThread {
+            running,
+            thread: Some(std::thread::spawn(run)),
+        }
+    }
+
+    /// Clears `running`, waits for the thread to finish and returns the machine as it
+    /// left it. None if the thread panicked or was already shut down, so it's safe to
+    /// call more than once.
+    pub fn shutdown(&mut self) -> Option<Machine> {
+        self.running.store(false, Ordering::Relaxed);
+        match self.thread.take()?.join() {
+            Ok(machine) => Some(machine),
+            Err(e) => {
+                warn!("Error joining emulator thread: {:?}", e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for EmulatorThread {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// The `invaders` ROM from `src/assets`, for tests that run the real game. Panics if
+/// it's missing or the wrong size rather than letting those tests pass without it.
+#[cfg(test)]
+pub(crate) fn test_rom() -> [u8; ROM_SIZE] {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/assets/invaders.bin");
+    let rom = std::fs::read(path)
+        .unwrap_or_else(|e| panic!("tests need the game ROM at {}: {}", path, e));
+    let len = rom.len();
+    rom.try_into().unwrap_or_else(|_| {
+        panic!(
+            "tests need the game ROM at {} to be {} bytes, it's {}",
+            path, ROM_SIZE, len
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use crate::machine::Machine;
+    use crate::EmulatorThread;
+
+    #[test]
+    fn test_emulator_thread_shutdown() {
+        let running = Arc::new(AtomicBool::new(false));
+        let running_emu = running.clone();
+        let mut emulator = EmulatorThread::spawn(running.clone(), move || {
+            // NOPs, wrapping round the empty ROM
+            let mut machine = Machine::with_program(&[]).unwrap();
+            while running_emu.load(Ordering::Relaxed) {
+                machine.run_frame();
+            }
+            machine
+        });
+        assert!(running.load(Ordering::Relaxed));
+
+        assert!(emulator.shutdown().is_some());
+        assert!(!running.load(Ordering::Relaxed));
+        assert!(emulator.shutdown().is_none());
+    }
+}
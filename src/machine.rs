@@ -0,0 +1,1919 @@
+use std::cell::OnceCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    Arc,
+};
+
+use log::warn;
+use modular_bitfield::prelude::*;
+
+use emu8080::CYCLE_TIME_NANO_SECS;
+use emu8080::{Instruction, Intel8080, MemoryAccess};
+
+use crate::addr;
+use crate::input::{merge, InputSource, InputState};
+use crate::memory::{render_vram, AccessStats, RotationMode, SpaceInvadersMemory};
+use crate::{DISPLAY_BUFFER_SIZE, DISPLAY_TIME_NANO_SEC, VRAM_END, VRAM_SIZE, VRAM_START};
+
+/// The three input ports, shared between the machine and whatever drives it
+pub type Inputs = (AtomicU8, AtomicU8, AtomicU8);
+
+/// Inputs as they read with nothing pressed and the default DIP switches
+pub fn new_inputs() -> Arc<Inputs> {
+    Arc::new((
+        AtomicU8::new(0b1000_1111),
+        AtomicU8::new(0b0000_1000),
+        AtomicU8::new(0b0000_0000),
+    ))
+}
+
+/// Which cabinet the board is installed in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CabinetType {
+    #[default]
+    Upright,
+    /// table cabinet where the screen is flipped for player 2
+    Cocktail,
+}
+
+impl std::str::FromStr for CabinetType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "upright" => Ok(CabinetType::Upright),
+            "cocktail" => Ok(CabinetType::Cocktail),
+            _ => Err(format!(
+                "Unknown cabinet type '{}' (expected upright or cocktail)",
+                s
+            )),
+        }
+    }
+}
+
+#[bitfield]
+pub struct SpaceInvadersInput0 {
+    pub dip_4: bool,
+    #[skip]
+    __: B3,
+    pub fire: bool,
+    pub left: bool,
+    pub right: bool,
+    #[skip]
+    __: B1,
+}
+
+#[bitfield]
+pub struct SpaceInvadersInput1 {
+    pub credit: bool,
+    pub start_2p: bool,
+    pub start_1p: bool,
+    #[skip(setters)]
+    pub always_one: bool,
+    pub p1_shot: bool,
+    pub p1_left: bool,
+    pub p1_right: bool,
+    #[skip]
+    __: B1,
+}
+
+#[bitfield]
+pub struct SpaceInvadersInput2 {
+    pub dip_3: bool,
+    pub dip_5: bool,
+    pub tilt: bool,
+    pub dip_6: bool,
+    pub p2_shot: bool,
+    pub p2_left: bool,
+    pub p2_right: bool,
+    pub dip_7: bool,
+}
+
+/// A control on the cabinet, so callers can press one without knowing which port and
+/// bit it's wired to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Coin,
+    Start1P,
+    Start2P,
+    P1Left,
+    P1Right,
+    P1Fire,
+    P2Left,
+    P2Right,
+    P2Fire,
+    Tilt,
+}
+
+impl Button {
+    pub const ALL: [Button; 10] = [
+        Button::Coin,
+        Button::Start1P,
+        Button::Start2P,
+        Button::P1Left,
+        Button::P1Right,
+        Button::P1Fire,
+        Button::P2Left,
+        Button::P2Right,
+        Button::P2Fire,
+        Button::Tilt,
+    ];
+
+    /// What it's called on the command line and in the config file, after the names
+    /// of the input bits
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Coin => "credit",
+            Button::Start1P => "start-1p",
+            Button::Start2P => "start-2p",
+            Button::P1Left => "p1-left",
+            Button::P1Right => "p1-right",
+            Button::P1Fire => "p1-shot",
+            Button::P2Left => "p2-left",
+            Button::P2Right => "p2-right",
+            Button::P2Fire => "p2-shot",
+            Button::Tilt => "tilt",
+        }
+    }
+
+    /// The input port the button is wired to and its bit on that port
+    pub fn port_mask(self) -> (u8, u8) {
+        let port1 = SpaceInvadersInput1::new();
+        let port2 = SpaceInvadersInput2::new();
+        match self {
+            Button::Coin => (1, port1.with_credit(true).into_bytes()[0]),
+            Button::Start1P => (1, port1.with_start_1p(true).into_bytes()[0]),
+            Button::Start2P => (1, port1.with_start_2p(true).into_bytes()[0]),
+            Button::P1Left => (1, port1.with_p1_left(true).into_bytes()[0]),
+            Button::P1Right => (1, port1.with_p1_right(true).into_bytes()[0]),
+            Button::P1Fire => (1, port1.with_p1_shot(true).into_bytes()[0]),
+            Button::P2Left => (2, port2.with_p2_left(true).into_bytes()[0]),
+            Button::P2Right => (2, port2.with_p2_right(true).into_bytes()[0]),
+            Button::P2Fire => (2, port2.with_p2_shot(true).into_bytes()[0]),
+            Button::Tilt => (2, port2.with_tilt(true).into_bytes()[0]),
+        }
+    }
+
+    /// The button's bit on port 0, where some bootlegs read player 1's controls
+    pub fn port0_mask(self) -> Option<u8> {
+        let port0 = SpaceInvadersInput0::new();
+        match self {
+            Button::P1Left => Some(port0.with_left(true).into_bytes()[0]),
+            Button::P1Right => Some(port0.with_right(true).into_bytes()[0]),
+            Button::P1Fire => Some(port0.with_fire(true).into_bytes()[0]),
+            _ => None,
+        }
+    }
+
+    /// Presses or releases the button in `inputs`, leaving the other bits alone
+    pub fn set(self, inputs: &Inputs, pressed: bool) {
+        let (port, mask) = self.port_mask();
+        let port = match port {
+            1 => &inputs.1,
+            _ => &inputs.2,
+        };
+        set_bits(port, mask, pressed);
+    }
+
+    /// As [`Button::set`] for the button's bit on port 0, if it has one
+    pub fn set_port0(self, inputs: &Inputs, pressed: bool) {
+        if let Some(mask) = self.port0_mask() {
+            set_bits(&inputs.0, mask, pressed);
+        }
+    }
+}
+
+impl std::str::FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Button::ALL.iter().map(|button| button.name()).collect();
+                format!(
+                    "Unknown control '{}' (expected one of {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+fn set_bits(port: &AtomicU8, mask: u8, on: bool) {
+    if on {
+        port.fetch_or(mask, Ordering::Relaxed);
+    } else {
+        port.fetch_and(!mask, Ordering::Relaxed);
+    }
+}
+
+/// The DIP switches on input port 2 that set up the game. The game only reads the
+/// number of lives when a game starts, and the others when it boots, so changes made
+/// while it's running show after a reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DipSwitches {
+    /// ships at the start of a game, 3 to 6
+    pub lives: u8,
+    /// an extra ship at 1000 points rather than 1500
+    pub bonus_at_1000: bool,
+    /// show the coin info on the demo screen
+    pub coin_info: bool,
+}
+
+impl Default for DipSwitches {
+    fn default() -> Self {
+        DipSwitches::read(&new_inputs())
+    }
+}
+
+impl DipSwitches {
+    pub fn read(inputs: &Inputs) -> Self {
+        let port2 = SpaceInvadersInput2::from_bytes([inputs.2.load(Ordering::Relaxed)]);
+        DipSwitches {
+            lives: 3 + port2.dip_3() as u8 + 2 * port2.dip_5() as u8,
+            bonus_at_1000: port2.dip_6(),
+            // the switch turns it off
+            coin_info: !port2.dip_7(),
+        }
+    }
+
+    /// Sets the switches in `inputs`, leaving the controls on the same port alone.
+    /// Lives outside 3 to 6 are clamped.
+    pub fn write(self, inputs: &Inputs) {
+        let extra_lives = self.lives.clamp(3, 6) - 3;
+        let _ = inputs
+            .2
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |port2| {
+                let mut port2 = SpaceInvadersInput2::from_bytes([port2]);
+                port2.set_dip_3(extra_lives & 1 != 0);
+                port2.set_dip_5(extra_lives & 2 != 0);
+                port2.set_dip_6(self.bonus_at_1000);
+                port2.set_dip_7(!self.coin_info);
+                Some(port2.into_bytes()[0])
+            });
+    }
+
+    /// One more life, going back round to 3 after 6
+    pub fn next_lives(self) -> Self {
+        DipSwitches {
+            lives: if self.lives >= 6 { 3 } else { self.lives + 1 },
+            ..self
+        }
+    }
+}
+
+#[bitfield]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct SpaceInvadersAudioOutput1 {
+    #[skip(setters)]
+    pub ufo: bool,
+    #[skip(setters)]
+    pub shot: bool,
+    #[skip(setters)]
+    pub flash: bool,
+    #[skip(setters)]
+    pub invader_die: bool,
+    #[skip(setters)]
+    pub extended_play: bool,
+    #[skip(setters)]
+    pub amp_enable: bool,
+    #[skip]
+    __: B2,
+}
+
+#[bitfield]
+#[derive(Debug)]
+#[allow(dead_code)]
+pub struct SpaceInvadersAudioOutput2 {
+    #[skip(setters)]
+    pub fleet_movement_1: bool,
+    #[skip(setters)]
+    pub fleet_movement_2: bool,
+    #[skip(setters)]
+    pub fleet_movement_3: bool,
+    #[skip(setters)]
+    pub fleet_movement_4: bool,
+    #[skip(setters)]
+    pub ufo_hit: bool,
+    #[skip(setters)]
+    pub flip_screen: bool,
+    #[skip]
+    __: B2,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShiftRegister {
+    register: u16,
+    amount: u8,
+}
+
+impl ShiftRegister {
+    pub fn new() -> Self {
+        ShiftRegister {
+            register: 0,
+            amount: 0,
+        }
+    }
+
+    pub fn input_data(&mut self, input: u8) {
+        self.register = ((input as u16) << 8) | (self.register >> 8);
+    }
+
+    pub fn input_amount(&mut self, amount: u8) {
+        self.amount = amount & 0b00000111;
+    }
+
+    pub fn output(&self) -> u8 {
+        (self.register >> (8 - self.amount)) as u8
+    }
+
+    /// the last two bytes written, the newest in the high byte
+    pub fn register(&self) -> u16 {
+        self.register
+    }
+
+    pub fn amount(&self) -> u8 {
+        self.amount
+    }
+}
+
+/// A set of I/O ports, one bit for each of the 256
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortSet([u64; 4]);
+
+impl PortSet {
+    /// Adds a port, returning true if it wasn't already in the set
+    pub fn insert(&mut self, port: u8) -> bool {
+        let (word, bit) = (port as usize / 64, 1u64 << (port % 64));
+        let new = self.0[word] & bit == 0;
+        self.0[word] |= bit;
+        new
+    }
+
+    pub fn contains(&self, port: u8) -> bool {
+        self.0[port as usize / 64] & (1u64 << (port % 64)) != 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..=u8::MAX).filter(|&port| self.contains(port))
+    }
+}
+
+impl fmt::Display for PortSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ports: Vec<String> = self.iter().map(|port| port.to_string()).collect();
+        if ports.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", ports.join(", "))
+        }
+    }
+}
+
+/// A button press made on the player's behalf, counted in emulated frames so the game
+/// sees the same timing no matter how fast the host runs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputPulse {
+    pub port: u8,
+    pub mask: u8,
+    pub frames: u32,
+}
+
+impl InputPulse {
+    /// long enough for the game's once-per-frame switch polling to see both edges
+    pub const DEFAULT_FRAMES: u32 = 4;
+
+    pub fn credit() -> Self {
+        InputPulse {
+            port: 1,
+            mask: SpaceInvadersInput1::new().with_credit(true).into_bytes()[0],
+            frames: Self::DEFAULT_FRAMES,
+        }
+    }
+
+    pub fn start_1p() -> Self {
+        InputPulse {
+            port: 1,
+            mask: SpaceInvadersInput1::new().with_start_1p(true).into_bytes()[0],
+            frames: Self::DEFAULT_FRAMES,
+        }
+    }
+}
+
+/// Plays back queued pulses one at a time, each held for its length and then
+/// released for the same number of frames before the next one starts
+#[derive(Default)]
+pub struct InputQueue {
+    pending: VecDeque<InputPulse>,
+    active: Option<InputPulse>,
+    frames_left: u32,
+    releasing: bool,
+}
+
+impl InputQueue {
+    pub fn new() -> Self {
+        InputQueue {
+            pending: VecDeque::new(),
+            active: None,
+            frames_left: 0,
+            releasing: false,
+        }
+    }
+
+    pub fn push(&mut self, pulse: InputPulse) {
+        self.pending.push_back(pulse);
+        if self.active.is_none() {
+            self.start_next();
+        }
+    }
+
+    fn start_next(&mut self) {
+        self.active = self.pending.pop_front();
+        self.frames_left = self.active.map_or(0, |pulse| pulse.frames);
+        self.releasing = false;
+    }
+
+    /// Advances the queue by one emulated frame
+    pub fn next_frame(&mut self) {
+        let Some(pulse) = self.active else {
+            return;
+        };
+
+        self.frames_left = self.frames_left.saturating_sub(1);
+        if self.frames_left == 0 {
+            if self.releasing {
+                self.start_next();
+            } else {
+                self.releasing = true;
+                self.frames_left = pulse.frames;
+            }
+        }
+    }
+
+    /// Whether every pulse pushed has been pressed and released
+    pub fn is_idle(&self) -> bool {
+        self.active.is_none()
+    }
+
+    /// Bits the queue is currently holding down on the given input port
+    pub fn held(&self, port: u8) -> u8 {
+        match self.active {
+            Some(pulse) if pulse.port == port && !self.releasing => pulse.mask,
+            _ => 0,
+        }
+    }
+}
+
+/// Turns the coin key into one credit per press, however long it's held. Holding the
+/// coin switch itself would leave the credit to the game's edge detection, which can
+/// count one coin or several.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CoinSwitch {
+    held: bool,
+}
+
+impl CoinSwitch {
+    /// The pulse to queue, only for the first press after a release
+    pub fn press(&mut self) -> Option<InputPulse> {
+        if self.held {
+            return None;
+        }
+        self.held = true;
+        Some(InputPulse::credit())
+    }
+
+    pub fn release(&mut self) {
+        self.held = false;
+    }
+}
+
+/// Turns a held fire button into a stream of presses, since the game only fires on
+/// the press and ignores the button while it stays down
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Autofire {
+    period_frames: u32,
+}
+
+impl Autofire {
+    /// Presses per second, up to half the frame rate
+    pub fn from_hz(hz: f64) -> Result<Self, String> {
+        if !(hz > 0.0 && hz <= 30.0) {
+            return Err(format!(
+                "Autofire rate must be above 0 and at most 30 Hz, got {}",
+                hz
+            ));
+        }
+
+        let frames_per_sec = 1e9 / DISPLAY_TIME_NANO_SEC as f64;
+        Ok(Autofire {
+            period_frames: ((frames_per_sec / hz).round() as u32).max(2),
+        })
+    }
+
+    /// Whether the button reads as down on the given frame of being held,
+    /// down for the first half of each period and up for the rest
+    pub fn pressed(self, held_frames: u32) -> bool {
+        held_frames % self.period_frames < self.period_frames / 2
+    }
+}
+
+/// Rising and falling edges on the sound output ports, in the order the game made them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundEvent {
+    UfoStart,
+    UfoStop,
+    Shot,
+    Flash,
+    InvaderDie,
+    ExtendedPlay,
+    /// one of the four fleet movement notes, numbered 1 to 4
+    FleetMovement(u8),
+    UfoHit,
+    AmpEnable(bool),
+}
+
+impl fmt::Display for SoundEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoundEvent::UfoStart => write!(f, "ufo on"),
+            SoundEvent::UfoStop => write!(f, "ufo off"),
+            SoundEvent::Shot => write!(f, "shot"),
+            SoundEvent::Flash => write!(f, "flash"),
+            SoundEvent::InvaderDie => write!(f, "invader_die"),
+            SoundEvent::ExtendedPlay => write!(f, "extended_play"),
+            SoundEvent::FleetMovement(note) => write!(f, "fleet_movement_{}", note),
+            SoundEvent::UfoHit => write!(f, "ufo_hit"),
+            SoundEvent::AmpEnable(true) => write!(f, "amp on"),
+            SoundEvent::AmpEnable(false) => write!(f, "amp off"),
+        }
+    }
+}
+
+impl SoundEvent {
+    /// The group the event's sound is muted with, if it makes one
+    pub fn category(self) -> Option<SoundCategory> {
+        match self {
+            SoundEvent::UfoStart | SoundEvent::UfoStop => Some(SoundCategory::Ufo),
+            SoundEvent::Shot => Some(SoundCategory::Shots),
+            SoundEvent::FleetMovement(_) => Some(SoundCategory::Fleet),
+            SoundEvent::Flash | SoundEvent::InvaderDie | SoundEvent::UfoHit => {
+                Some(SoundCategory::Explosions)
+            }
+            SoundEvent::ExtendedPlay | SoundEvent::AmpEnable(_) => None,
+        }
+    }
+}
+
+/// Groups of sounds that can be muted on their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SoundCategory {
+    /// the drone while the mystery ship crosses
+    Ufo,
+    Shots,
+    /// all four notes of the invaders' march
+    Fleet,
+    /// the player, an invader or the mystery ship being hit
+    Explosions,
+}
+
+impl SoundCategory {
+    pub const ALL: [SoundCategory; 4] = [
+        SoundCategory::Ufo,
+        SoundCategory::Shots,
+        SoundCategory::Fleet,
+        SoundCategory::Explosions,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SoundCategory::Ufo => "ufo",
+            SoundCategory::Shots => "shots",
+            SoundCategory::Fleet => "fleet",
+            SoundCategory::Explosions => "explosions",
+        }
+    }
+}
+
+impl std::str::FromStr for SoundCategory {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SoundCategory::ALL
+            .into_iter()
+            .find(|category| category.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "Unknown sound category '{}' (expected ufo, shots, fleet or explosions)",
+                    s
+                )
+            })
+    }
+}
+
+/// Records sound edges one per line as `<frame> <event>`. Emulation only depends on
+/// the inputs seen each frame, so replaying the same inputs gives an identical log
+/// that can be compared with `diff`.
+pub struct AudioLog<W: Write> {
+    out: W,
+}
+
+impl<W: Write> AudioLog<W> {
+    pub fn new(out: W) -> Self {
+        AudioLog { out }
+    }
+
+    pub fn record(&mut self, frame: u64, event: SoundEvent) -> Result<(), std::io::Error> {
+        writeln!(self.out, "{} {}", frame, event)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// How the machine is advanced, see [`Machine::ready_to_step`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StepMode {
+    /// continuously, in real time
+    #[default]
+    Free,
+    /// one frame each time a live input is pressed or released, for counting the frames
+    /// between an input and its effect. Holding an input doesn't advance. During a
+    /// replay the game sees the recorded inputs as usual, so any key press or release
+    /// steps through the replay a frame at a time.
+    OnInput,
+}
+
+/// Machine state for a debugger to show, taken with [`Machine::debug_snapshot`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DebugSnapshot {
+    pub frame: u64,
+    pub pc: u16,
+    pub shift_register: ShiftRegister,
+    /// only with the `access-counters` feature
+    pub access: Option<AccessStats>,
+}
+
+impl fmt::Display for DebugSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame {} pc ${:04X} shift ${:04X} by {} = ${:02X}",
+            self.frame,
+            self.pc,
+            self.shift_register.register(),
+            self.shift_register.amount(),
+            self.shift_register.output()
+        )?;
+        if let Some(ref access) = self.access {
+            write!(f, " | {}", access)?;
+        }
+        Ok(())
+    }
+}
+
+/// An instruction that has just run, as passed to [`Machine::step_with`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InstrInfo {
+    /// address the instruction was fetched from
+    pub pc: u16,
+    pub cycles: u64,
+    pub frame: u64,
+}
+
+/// What happened during a single [`Machine::step`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Step {
+    pub cycles: u64,
+    /// the end of frame interrupt was raised
+    pub vblank: bool,
+}
+
+/// Raised by the video hardware as the beam reaches line 224 and vblank begins. The
+/// game's handler is at 0x0010.
+pub const VBLANK_INTERRUPT: Instruction = Instruction::RST_3;
+/// Raised as the beam passes line 96, about halfway down. The game's handler is at
+/// 0x0008 and draws whatever is in the top half of the screen while the beam is out
+/// of the way.
+pub const MID_SCREEN_INTERRUPT: Instruction = Instruction::RST_2;
+
+/// The line the beam is on when [`VBLANK_INTERRUPT`] is raised, the first below the
+/// 224 visible ones
+pub const VBLANK_SCANLINE: u64 = 224;
+/// The line the beam is on when [`MID_SCREEN_INTERRUPT`] is raised
+pub const MID_SCREEN_SCANLINE: u64 = 96;
+
+/// How long after vblank the mid-screen interrupt comes by default: 96/224 of the
+/// frame, 7,142,857ns, placing it at line 96 as if the 224 visible lines took the whole
+/// frame. The real monitor scans 262 lines, 38 of them hidden, which would put it 134
+/// lines after vblank at 134/262 (about 0.511) of the frame; see
+/// [`Machine::set_mid_screen_fraction`] to try that.
+pub const MID_SCREEN_OFFSET_NANO_SEC: u64 =
+    DISPLAY_TIME_NANO_SEC * MID_SCREEN_SCANLINE / VBLANK_SCANLINE;
+
+/// The CPU and the board around it, with no window or audio attached
+pub struct Machine {
+    pub cpu: Intel8080,
+    pub memory: SpaceInvadersMemory,
+    pub inputs: Arc<Inputs>,
+    pub input_queue: InputQueue,
+    pub cabinet: CabinetType,
+    /// per player, off unless set
+    pub autofire: [Option<Autofire>; 2],
+    step_mode: StepMode,
+    fire_held_frames: [u32; 2],
+    shift_register: ShiftRegister,
+    last_audio1: SpaceInvadersAudioOutput1,
+    last_audio2: SpaceInvadersAudioOutput2,
+    sound_events: Vec<SoundEvent>,
+    latched_inputs: [u8; 3],
+    /// the inputs left unsampled at the last vblank, until the next step
+    latch_pending: bool,
+    last_live_inputs: [u8; 3],
+    input_recording: Option<Vec<[u8; 3]>>,
+    input_replay: Option<std::vec::IntoIter<[u8; 3]>>,
+    /// polled after the shared inputs and the queue, see [`crate::input`]
+    input_sources: Vec<Box<dyn InputSource>>,
+    next_display_time: u64,
+    next_screen_int_time: u64,
+    /// time from each vblank to the mid-screen interrupt after it
+    mid_screen_offset: u64,
+    emu_clock: u64,
+    frame: u64,
+    /// VRAM and the flip as they were at the last vblank, see [`Machine::current_vram`]
+    last_vram: [u8; VRAM_SIZE],
+    last_flipped: bool,
+    /// `last_vram` expanded the first time [`Machine::current_frame`] asks for it
+    last_frame: OnceCell<Vec<u8>>,
+    access_stats: AccessStats,
+    ports_read: PortSet,
+    ports_written: PortSet,
+}
+
+impl Machine {
+    pub fn new(memory: SpaceInvadersMemory, inputs: Arc<Inputs>, cabinet: CabinetType) -> Self {
+        let mut machine = Machine {
+            cpu: Intel8080::new(),
+            memory,
+            inputs,
+            input_queue: InputQueue::new(),
+            cabinet,
+            autofire: [None; 2],
+            step_mode: StepMode::Free,
+            fire_held_frames: [0; 2],
+            shift_register: ShiftRegister::new(),
+            last_audio1: SpaceInvadersAudioOutput1::new(),
+            last_audio2: SpaceInvadersAudioOutput2::new(),
+            sound_events: Vec::new(),
+            latched_inputs: [0; 3],
+            latch_pending: false,
+            last_live_inputs: [0; 3],
+            input_recording: None,
+            input_replay: None,
+            input_sources: Vec::new(),
+            next_display_time: 0,
+            next_screen_int_time: MID_SCREEN_OFFSET_NANO_SEC,
+            mid_screen_offset: MID_SCREEN_OFFSET_NANO_SEC,
+            emu_clock: 0,
+            frame: 0,
+            last_vram: [0; VRAM_SIZE],
+            last_flipped: false,
+            last_frame: OnceCell::new(),
+            access_stats: AccessStats::default(),
+            ports_read: PortSet::default(),
+            ports_written: PortSet::default(),
+        };
+        machine.last_vram = *machine.memory.vram();
+        machine.last_live_inputs = machine.live_inputs();
+        machine.latch_inputs();
+        machine
+    }
+
+    /// An upright machine with `program` at 0x0000 and zeros after it, for running
+    /// code without the game ROM
+    pub fn with_program(program: &[u8]) -> Result<Self, String> {
+        let memory = SpaceInvadersMemory::from_rom_slice(program, RotationMode::default())?;
+        Ok(Machine::new(memory, new_inputs(), CabinetType::Upright))
+    }
+
+    /// The line the beam has reached in emulated time, counting from 0 at the last
+    /// vblank with the visible lines taking the whole frame, as the default
+    /// [`MID_SCREEN_OFFSET_NANO_SEC`] does
+    pub fn beam_line(&self) -> u64 {
+        let elapsed = DISPLAY_TIME_NANO_SEC
+            .saturating_sub(self.next_display_time.saturating_sub(self.emu_clock));
+        elapsed * VBLANK_SCANLINE / DISPLAY_TIME_NANO_SEC
+    }
+
+    /// The line the mid-screen interrupt comes on, counted as [`Machine::beam_line`]
+    /// is. [`MID_SCREEN_SCANLINE`] unless moved with
+    /// [`Machine::set_mid_screen_fraction`].
+    pub fn mid_screen_line(&self) -> u64 {
+        (self.mid_screen_offset * VBLANK_SCANLINE + DISPLAY_TIME_NANO_SEC / 2)
+            / DISPLAY_TIME_NANO_SEC
+    }
+
+    /// A 64-bit FNV-1a hash of VRAM as the game wrote it, one bit per pixel, so it
+    /// changes with what the game draws and not with rotation, palettes or overlays.
+    /// Meant for spotting changes, a match doesn't prove two screens are the same.
+    pub fn frame_hash(&self) -> u64 {
+        let mut hash = 0xCBF2_9CE4_8422_2325u64;
+        for addr in VRAM_START..VRAM_END {
+            hash ^= self.memory.read_byte(addr as u16) as u64;
+            hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+        }
+        hash
+    }
+
+    /// Number of frames (VBlank interrupts) since power on
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Emulated time since power on in nanoseconds
+    pub fn emu_clock(&self) -> u64 {
+        self.emu_clock
+    }
+
+    /// Every port the game has read from since power on, handled or not
+    pub fn ports_read(&self) -> PortSet {
+        self.ports_read
+    }
+
+    /// Every port the game has written to since power on, handled or not
+    pub fn ports_written(&self) -> PortSet {
+        self.ports_written
+    }
+
+    /// Holds `button` down in the shared inputs until [`Machine::release`]
+    pub fn press(&self, button: Button) {
+        button.set(&self.inputs, true);
+    }
+
+    pub fn release(&self, button: Button) {
+        button.set(&self.inputs, false);
+    }
+
+    /// Input port values the game sees this frame
+    pub fn latched_inputs(&self) -> [u8; 3] {
+        self.latched_inputs
+    }
+
+    /// Starts keeping the inputs of every frame from this one on
+    pub fn start_recording(&mut self) {
+        // inputs still to be sampled are recorded when they are
+        self.input_recording = Some(if self.latch_pending {
+            Vec::new()
+        } else {
+            vec![self.latched_inputs]
+        });
+    }
+
+    /// The inputs kept since [`Machine::start_recording`], one entry per frame
+    pub fn take_recording(&mut self) -> Option<Vec<[u8; 3]>> {
+        self.input_recording.take()
+    }
+
+    /// Adds a source polled each frame along with the shared inputs, see
+    /// [`crate::input`] for how they're combined
+    pub fn add_input_source(&mut self, source: impl InputSource + 'static) {
+        self.input_sources.push(Box::new(source));
+    }
+
+    /// Feeds recorded inputs to the game, one entry per frame starting with this one,
+    /// in place of the shared inputs until they run out
+    pub fn start_replay(&mut self, inputs: Vec<[u8; 3]>) {
+        self.input_replay = Some(inputs.into_iter());
+        self.latch_pending = false;
+        self.latch_inputs();
+    }
+
+    pub fn replaying(&self) -> bool {
+        self.input_replay.is_some()
+    }
+
+    pub fn debug_snapshot(&self) -> DebugSnapshot {
+        DebugSnapshot {
+            frame: self.frame,
+            pc: self.cpu.pc(),
+            shift_register: self.shift_register,
+            access: cfg!(feature = "access-counters").then_some(self.access_stats),
+        }
+    }
+
+    pub fn step_mode(&self) -> StepMode {
+        self.step_mode
+    }
+
+    /// Stepping on input starts with the next frame, or the first if none has run yet
+    pub fn set_step_mode(&mut self, step_mode: StepMode) {
+        self.step_mode = step_mode;
+        if step_mode == StepMode::OnInput && self.emu_clock == 0 {
+            self.latch_pending = true;
+        }
+    }
+
+    /// Moves the mid-screen interrupt to `fraction` of the way from one vblank to the
+    /// next, from the frame being run. A capture only replays with the split it was
+    /// recorded with.
+    pub fn set_mid_screen_fraction(&mut self, fraction: f64) -> Result<(), String> {
+        if !(fraction > 0.0 && fraction < 1.0) {
+            return Err(format!(
+                "Mid-screen interrupt must come between 0 and 1 of the way through the frame, got {}",
+                fraction
+            ));
+        }
+
+        self.mid_screen_offset = (DISPLAY_TIME_NANO_SEC as f64 * fraction).round() as u64;
+        let last_vblank = self.next_display_time.saturating_sub(DISPLAY_TIME_NANO_SEC);
+        self.next_screen_int_time = last_vblank + self.mid_screen_offset;
+        // already past this frame's, so the next comes in the next frame
+        if self.next_screen_int_time <= self.emu_clock {
+            self.next_screen_int_time += DISPLAY_TIME_NANO_SEC;
+        }
+        Ok(())
+    }
+
+    /// Whether to run the next frame. Always true with [`StepMode::Free`]. With
+    /// [`StepMode::OnInput`] it's true once for each change in the live inputs, and the
+    /// inputs for the frame are sampled then rather than at the vblank before it, so the
+    /// game sees the change in the frame that runs.
+    pub fn ready_to_step(&mut self) -> bool {
+        if self.step_mode == StepMode::OnInput {
+            let live = self.live_inputs();
+            if live == self.last_live_inputs {
+                return false;
+            }
+            self.last_live_inputs = live;
+        }
+
+        if self.latch_pending {
+            self.latch_pending = false;
+            self.latch_inputs();
+        }
+        true
+    }
+
+    fn live_inputs(&self) -> [u8; 3] {
+        [
+            self.inputs.0.load(Ordering::Relaxed),
+            self.inputs.1.load(Ordering::Relaxed),
+            self.inputs.2.load(Ordering::Relaxed),
+        ]
+    }
+
+    // the inputs are sampled once per frame so a recording of them reproduces a
+    // session exactly, however the host's timing lined up with the emulator's
+    fn latch_inputs(&mut self) {
+        let replayed = self
+            .input_replay
+            .as_mut()
+            .map_or(InputState::Idle, |replay| replay.poll());
+        if replayed == InputState::Idle {
+            self.input_replay = None;
+        }
+
+        let mut states = vec![replayed, self.inputs.poll(), self.input_queue.poll()];
+        states.extend(self.input_sources.iter_mut().map(|source| source.poll()));
+        self.latched_inputs = match merge(states) {
+            // recorded after autofire, so it's not applied again
+            InputState::Exclusive(ports) => ports,
+            InputState::Held(mut ports) => {
+                self.apply_autofire(&mut ports);
+                ports
+            }
+            // the shared inputs are always held
+            InputState::Idle => unreachable!(),
+        };
+
+        if let Some(ref mut recording) = self.input_recording {
+            recording.push(self.latched_inputs);
+        }
+    }
+
+    fn apply_autofire(&mut self, ports: &mut [u8; 3]) {
+        let shots = [
+            (
+                1,
+                SpaceInvadersInput1::new().with_p1_shot(true).into_bytes()[0],
+            ),
+            (
+                2,
+                SpaceInvadersInput2::new().with_p2_shot(true).into_bytes()[0],
+            ),
+        ];
+
+        for (player, (port, mask)) in shots.into_iter().enumerate() {
+            let Some(autofire) = self.autofire[player] else {
+                continue;
+            };
+
+            if ports[port] & mask == 0 {
+                self.fire_held_frames[player] = 0;
+                continue;
+            }
+
+            if !autofire.pressed(self.fire_held_frames[player]) {
+                ports[port] &= !mask;
+            }
+            self.fire_held_frames[player] = self.fire_held_frames[player].wrapping_add(1);
+        }
+    }
+
+    /// Sound edges produced since the last call
+    pub fn drain_sound_events(&mut self) -> std::vec::Drain<'_, SoundEvent> {
+        self.sound_events.drain(..)
+    }
+
+    /// The last complete frame, copied at vblank so it's never half drawn, as 32-bit
+    /// RGBA pixels in rows from the top. It's
+    /// [`display_width`](RotationMode::display_width) by
+    /// [`display_height`](RotationMode::display_height) pixels for the memory's
+    /// rotation, 224x256 unless it was built with another. Only expanded from VRAM when
+    /// asked for, once a frame.
+    pub fn current_frame(&self) -> &[u8] {
+        self.last_frame.get_or_init(|| {
+            let mut frame = vec![0; DISPLAY_BUFFER_SIZE];
+            render_vram(
+                &self.last_vram,
+                self.memory.rotation(),
+                self.last_flipped,
+                &mut frame,
+            );
+            frame
+        })
+    }
+
+    /// The last complete frame as VRAM, laid out as [`SpaceInvadersMemory::vram`]
+    pub fn current_vram(&self) -> &[u8; VRAM_SIZE] {
+        &self.last_vram
+    }
+
+    /// Runs a single instruction, servicing any I/O and interrupts it leads to
+    pub fn step(&mut self) -> Step {
+        self.step_with(|_| {})
+    }
+
+    /// As [`Machine::step`], calling `hook` once the instruction has run and before any
+    /// I/O or interrupt it leads to is handled. The hook is a generic parameter, so
+    /// plain [`Machine::step`] compiles to the same code it would without one.
+    pub fn step_with(&mut self, mut hook: impl FnMut(InstrInfo)) -> Step {
+        let pc = self.cpu.pc();
+        let cycles = self.cpu.step(&mut self.memory);
+        hook(InstrInfo {
+            pc,
+            cycles,
+            frame: self.frame,
+        });
+
+        if self.cpu.output_ready() {
+            let output = self.cpu.read_output();
+            let port = self.cpu.active_io_port();
+            let new = self.ports_written.insert(port);
+            match port {
+                2 => self.shift_register.input_amount(output), // shift amount
+                3 => self.write_audio1(output),
+                4 => self.shift_register.input_data(output), // shift data
+                5 => self.write_audio2(output),
+                6 => { /* do nothing */ } // watch dog
+                _ if new => warn!("Write to unhandled port {} at ${:04X}", port, pc),
+                _ => {}
+            }
+        } else if self.cpu.awaiting_input() {
+            let port = self.cpu.active_io_port();
+            let new = self.ports_read.insert(port);
+            let input: u8 = match port {
+                0 => self.latched_inputs[0],       // INPUTS 0
+                1 => self.latched_inputs[1],       // INPUTS 1
+                2 => self.latched_inputs[2],       // INPUTS 2
+                3 => self.shift_register.output(), // bit shift in
+                _ if new => {
+                    warn!("Read from unhandled port {} at ${:04X}", port, pc);
+                    0
+                }
+                _ => 0,
+            };
+
+            self.cpu.write_input(input);
+        }
+
+        // set interrupts if needed
+
+        let emu_time_nano_sec: u64 = cycles * CYCLE_TIME_NANO_SECS;
+
+        self.emu_clock = self.emu_clock.wrapping_add(emu_time_nano_sec);
+
+        let mut vblank = false;
+        if self.next_display_time <= self.emu_clock {
+            self.next_display_time = self.next_display_time.wrapping_add(DISPLAY_TIME_NANO_SEC);
+            self.cpu.interrupt(VBLANK_INTERRUPT);
+            self.frame += 1;
+            self.last_vram = *self.memory.vram();
+            self.last_flipped = self.memory.flipped();
+            self.last_frame.take();
+            if cfg!(feature = "access-counters") {
+                self.access_stats
+                    .add_frame(self.memory.take_access_counts());
+            }
+            self.input_queue.next_frame();
+            if self.step_mode == StepMode::OnInput {
+                self.latch_pending = true;
+            } else {
+                self.latch_inputs();
+            }
+            vblank = true;
+        } else if self.next_screen_int_time <= self.emu_clock {
+            self.next_screen_int_time = self
+                .next_screen_int_time
+                .wrapping_add(DISPLAY_TIME_NANO_SEC);
+            self.cpu.interrupt(MID_SCREEN_INTERRUPT);
+        }
+
+        Step { cycles, vblank }
+    }
+
+    /// Raises an interrupt now, outside the video timing, which still raises its own.
+    /// As with those it's ignored if the CPU has interrupts disabled.
+    pub fn interrupt(&mut self, instruction: Instruction) {
+        self.cpu.interrupt(instruction);
+    }
+
+    /// Presses the reset button: the CPU starts again from 0x0000 and the board's
+    /// latches are cleared, silencing any sound left playing. Memory is kept, as the
+    /// game clears it itself, and so is the frame count and video timing.
+    pub fn reset(&mut self) {
+        self.cpu = Intel8080::new();
+        self.shift_register = ShiftRegister::new();
+        self.write_audio1(0);
+        self.write_audio2(0);
+        self.input_queue = InputQueue::new();
+        self.fire_held_frames = [0; 2];
+    }
+
+    /// As [`Machine::reset`], but with RAM and VRAM cleared too, for a game that has
+    /// wedged itself in a way the reset button won't fix. The ROM is kept.
+    pub fn power_cycle(&mut self) {
+        self.reset();
+        self.memory.clear();
+    }
+
+    /// Runs at most `max_instructions` instructions, returning the number of cycles executed
+    pub fn run_instructions(&mut self, max_instructions: u64) -> u64 {
+        let mut cycles = 0;
+        for _ in 0..max_instructions {
+            cycles += self.step().cycles;
+        }
+        cycles
+    }
+
+    /// Runs until the next VBlank, returning the number of cycles executed
+    pub fn run_frame(&mut self) -> u64 {
+        let mut cycles = 0;
+        loop {
+            let step = self.step();
+            cycles += step.cycles;
+            if step.vblank {
+                return cycles;
+            }
+        }
+    }
+
+    /// Runs whole frames until the display has gone `stable_frames` frames without
+    /// changing, returning the frame it settled on. Gives up and returns `None` after
+    /// `max_frames` frames if the screen keeps animating.
+    pub fn run_until_quiescent(&mut self, max_frames: u64, stable_frames: u64) -> Option<u64> {
+        let mut last = self.frame_hash();
+        let mut unchanged = 0;
+
+        for _ in 0..max_frames {
+            self.run_frame();
+
+            let hash = self.frame_hash();
+            if hash == last {
+                unchanged += 1;
+                if unchanged >= stable_frames {
+                    return Some(self.frame);
+                }
+            } else {
+                unchanged = 0;
+                last = hash;
+            }
+        }
+
+        None
+    }
+
+    /// Inserts a coin and presses 1 player start, then runs whole frames until a game
+    /// is being played, returning the frame it started on. Playing means the flag at
+    /// [`addr::GAME_MODE`] is set, which the game does as it starts a game and undoes
+    /// once `GAME OVER` has been shown. The presses are made again whenever the last
+    /// ones are done and the game still hasn't started, so it doesn't matter if the
+    /// game was still booting and missed them. Gives up and returns `None` after
+    /// `max_frames` frames.
+    pub fn run_until_playing(&mut self, max_frames: u64) -> Option<u64> {
+        let playing = |machine: &Self| machine.memory.read_byte(addr::GAME_MODE) != 0;
+        if playing(self) {
+            return Some(self.frame);
+        }
+
+        for _ in 0..max_frames {
+            if self.input_queue.is_idle() {
+                self.input_queue.push(InputPulse::credit());
+                self.input_queue.push(InputPulse::start_1p());
+            }
+            self.run_frame();
+
+            if playing(self) {
+                return Some(self.frame);
+            }
+        }
+
+        None
+    }
+
+    fn write_audio1(&mut self, output: u8) {
+        let audio1 = SpaceInvadersAudioOutput1::from_bytes([output]);
+        let last = &self.last_audio1;
+
+        if audio1.amp_enable() != last.amp_enable() {
+            self.sound_events
+                .push(SoundEvent::AmpEnable(audio1.amp_enable()));
+        }
+
+        if audio1.ufo() && !last.ufo() {
+            self.sound_events.push(SoundEvent::UfoStart);
+        } else if !audio1.ufo() && last.ufo() {
+            self.sound_events.push(SoundEvent::UfoStop);
+        }
+
+        if audio1.shot() && !last.shot() {
+            self.sound_events.push(SoundEvent::Shot);
+        }
+
+        if audio1.flash() && !last.flash() {
+            self.sound_events.push(SoundEvent::Flash);
+        }
+
+        if audio1.invader_die() && !last.invader_die() {
+            self.sound_events.push(SoundEvent::InvaderDie);
+        }
+
+        if audio1.extended_play() && !last.extended_play() {
+            self.sound_events.push(SoundEvent::ExtendedPlay);
+        }
+
+        self.last_audio1 = audio1;
+    }
+
+    fn write_audio2(&mut self, output: u8) {
+        let audio2 = SpaceInvadersAudioOutput2::from_bytes([output]);
+        let last = &self.last_audio2;
+
+        let fleet_movement = [
+            (audio2.fleet_movement_1(), last.fleet_movement_1()),
+            (audio2.fleet_movement_2(), last.fleet_movement_2()),
+            (audio2.fleet_movement_3(), last.fleet_movement_3()),
+            (audio2.fleet_movement_4(), last.fleet_movement_4()),
+        ];
+        for (i, (now, before)) in fleet_movement.into_iter().enumerate() {
+            if now && !before {
+                self.sound_events
+                    .push(SoundEvent::FleetMovement(i as u8 + 1));
+            }
+        }
+
+        if audio2.ufo_hit() && !last.ufo_hit() {
+            self.sound_events.push(SoundEvent::UfoHit);
+        }
+
+        // the game flips the screen on player 2's turn, which only
+        // matters when both players are sat on opposite sides
+        if self.cabinet == CabinetType::Cocktail {
+            self.memory.set_flipped(audio2.flip_screen());
+        }
+
+        self.last_audio2 = audio2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::addr;
+    use crate::input::{InputSource, InputState};
+    use crate::machine::{
+        new_inputs, AudioLog, Autofire, Button, CabinetType, CoinSwitch, DebugSnapshot,
+        DipSwitches, InputPulse, InputQueue, Inputs, Machine, PortSet, ShiftRegister,
+        SoundCategory, SoundEvent, SpaceInvadersInput1, StepMode, MID_SCREEN_INTERRUPT,
+        MID_SCREEN_SCANLINE, VBLANK_INTERRUPT,
+    };
+    use crate::memory::{RotationMode, SpaceInvadersMemory};
+    use crate::{test_rom, DISPLAY_BUFFER_SIZE, DISPLAY_TIME_NANO_SEC, ROM_SIZE};
+    use emu8080::MemoryAccess;
+    use std::sync::atomic::Ordering;
+
+    /// Runs a one player game for `frames` frames, firing a second at a time once
+    /// it's started, and logs the sounds
+    fn audio_log(rom: [u8; ROM_SIZE], frames: u64) -> String {
+        let memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+        let mut machine = Machine::new(memory, new_inputs(), CabinetType::Upright);
+        machine.input_queue.push(InputPulse::credit());
+        machine.input_queue.push(InputPulse::start_1p());
+        for _ in 0..4 {
+            machine.input_queue.push(InputPulse {
+                port: 1,
+                mask: SpaceInvadersInput1::new().with_p1_shot(true).into_bytes()[0],
+                frames: 60,
+            });
+        }
+
+        let mut log = AudioLog::new(Vec::new());
+        while machine.frame() < frames {
+            machine.step();
+            let frame = machine.frame();
+            for event in machine.drain_sound_events() {
+                log.record(frame, event).unwrap();
+            }
+        }
+        String::from_utf8(log.into_inner()).unwrap()
+    }
+
+    #[test]
+    fn test_sound_categories() {
+        for note in 1..=4 {
+            assert_eq!(
+                SoundEvent::FleetMovement(note).category(),
+                Some(SoundCategory::Fleet)
+            );
+        }
+        assert_eq!(SoundEvent::UfoStop.category(), Some(SoundCategory::Ufo));
+        assert_eq!(
+            SoundEvent::UfoHit.category(),
+            Some(SoundCategory::Explosions)
+        );
+        assert_eq!(SoundEvent::AmpEnable(true).category(), None);
+
+        for category in SoundCategory::ALL {
+            assert_eq!(category.name().parse(), Ok(category));
+        }
+        assert!("drums".parse::<SoundCategory>().is_err());
+    }
+
+    #[test]
+    fn test_with_program() {
+        let mut machine = Machine::with_program(&[
+            0x3E, 0x42, // MVI A, 0x42
+            0x32, 0x00, 0x20, // STA 0x2000
+            0x3E, 0x01, // MVI A, 0x01
+            0xD3, 0x05, // OUT 5
+            0xC3, 0x09, 0x00, // JMP 0x0009
+        ])
+        .unwrap();
+
+        machine.run_instructions(4);
+        assert_eq!(machine.memory.read_byte(0x2000), 0x42);
+        assert_eq!(
+            machine.drain_sound_events().collect::<Vec<_>>(),
+            [SoundEvent::FleetMovement(1)]
+        );
+
+        assert!(Machine::with_program(&[0; ROM_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_current_frame() {
+        let mut machine = Machine::with_program(&[
+            0x3E, 0x01, // MVI A, 0x01
+            0x32, 0x00, 0x24, // STA 0x2400
+            0xC3, 0x05, 0x00, // JMP 0x0005
+        ])
+        .unwrap();
+        let lit = |frame: &[u8]| frame.chunks(4).filter(|pixel| pixel[0] != 0).count();
+
+        // drawn, but the frame isn't finished
+        machine.run_instructions(2);
+        assert_eq!(machine.memory.vram()[0], 0x01);
+        assert_eq!(lit(machine.current_frame()), 0);
+        assert_eq!(machine.current_vram()[0], 0x00);
+
+        machine.run_frame();
+        let mut live = vec![0; DISPLAY_BUFFER_SIZE];
+        machine.memory.render_frame(&mut live);
+        assert_eq!(machine.current_frame(), live);
+        assert_eq!(lit(machine.current_frame()), 1);
+        assert_eq!(machine.current_vram(), machine.memory.vram());
+        assert_eq!(
+            machine.current_frame().len(),
+            RotationMode::default().display_width() * RotationMode::default().display_height() * 4
+        );
+    }
+
+    #[test]
+    fn test_ports_seen() {
+        let mut ports = PortSet::default();
+        assert!(ports.insert(3));
+        assert!(!ports.insert(3));
+        assert!(ports.insert(255));
+        assert!(ports.contains(255) && !ports.contains(4));
+        assert_eq!(ports.to_string(), "3, 255");
+        assert_eq!(PortSet::default().to_string(), "none");
+
+        let program = [
+            0xDB, 0x01, // IN 1
+            0xD3, 0x06, // OUT 6
+            0xD3, 0x07, // OUT 7
+            0xDB, 0x09, // IN 9
+            0xD3, 0x07, // OUT 7
+            0x76, // HLT
+        ];
+        let mut machine = Machine::with_program(&program).unwrap();
+        machine.run_instructions(6);
+        assert_eq!(machine.ports_read().iter().collect::<Vec<_>>(), [1, 9]);
+        assert_eq!(machine.ports_written().iter().collect::<Vec<_>>(), [6, 7]);
+    }
+
+    #[test]
+    fn test_interrupt() {
+        // the handlers set the flag the way the game's own do
+        let mut program = vec![0; 0x44];
+        program[0x00..0x03].copy_from_slice(&[0xC3, 0x40, 0x00]); // JMP 0x0040
+        program[0x08..0x0F].copy_from_slice(&[
+            0xAF, // XRA A
+            0x32, 0x72, 0x20, // STA 0x2072
+            0xC3, 0x40, 0x00, // JMP 0x0040
+        ]);
+        program[0x10..0x18].copy_from_slice(&[
+            0x3E, 0x80, // MVI A, 0x80
+            0x32, 0x72, 0x20, // STA 0x2072
+            0xC3, 0x40, 0x00, // JMP 0x0040
+        ]);
+        program[0x40..0x44].copy_from_slice(&[
+            0xFB, // EI
+            0xC3, 0x41, 0x00, // JMP 0x0041
+        ]);
+        let mut machine = Machine::with_program(&program).unwrap();
+
+        // long enough to enable interrupts, well short of the mid-screen one
+        machine.run_instructions(10);
+        machine.memory.write_byte(addr::VBLANK_STATUS, 0x00);
+
+        // through the handler and back round to re-enable interrupts
+        machine.interrupt(VBLANK_INTERRUPT);
+        machine.run_instructions(6);
+        assert_eq!(machine.memory.read_byte(addr::VBLANK_STATUS), 0x80);
+
+        machine.interrupt(MID_SCREEN_INTERRUPT);
+        machine.run_instructions(6);
+        assert_eq!(machine.memory.read_byte(addr::VBLANK_STATUS), 0x00);
+    }
+
+    #[test]
+    fn test_reset() {
+        let program = [
+            0x3E, 0x01, // MVI A, 0x01
+            0xD3, 0x03, // OUT 3, the UFO sound on
+            0xC3, 0x04, 0x00, // JMP 0x0004
+        ];
+        let mut machine = Machine::with_program(&program).unwrap();
+        machine.run_instructions(3);
+        assert_eq!(
+            machine.drain_sound_events().collect::<Vec<_>>(),
+            [SoundEvent::UfoStart]
+        );
+
+        machine.reset();
+        assert_eq!(machine.cpu.pc(), 0x0000);
+        assert_eq!(
+            machine.drain_sound_events().collect::<Vec<_>>(),
+            [SoundEvent::UfoStop]
+        );
+        // the frame count carries on
+        let frame = machine.frame();
+        machine.run_frame();
+        assert_eq!(machine.frame(), frame + 1);
+
+        // memory is only cleared by a power cycle, and never the ROM
+        machine.memory.write_byte(0x2010, 0x42);
+        machine.memory.write_byte(0x3000, 0xFF);
+        machine.reset();
+        assert_eq!(machine.memory.read_byte(0x2010), 0x42);
+        machine.power_cycle();
+        assert_eq!(machine.cpu.pc(), 0x0000);
+        assert_eq!(machine.memory.read_byte(0x2010), 0x00);
+        assert_eq!(machine.memory.read_byte(0x3000), 0x00);
+        assert_eq!(machine.memory.read_byte(0x0001), 0x01);
+    }
+
+    #[test]
+    fn test_dip_switches() {
+        let inputs = new_inputs();
+        assert_eq!(
+            DipSwitches::read(&inputs),
+            DipSwitches {
+                lives: 3,
+                bonus_at_1000: false,
+                coin_info: true,
+            }
+        );
+
+        // player 2 holding left is left alone
+        inputs.2.store(0b0010_0000, Ordering::Relaxed);
+        let dips = DipSwitches {
+            lives: 6,
+            bonus_at_1000: true,
+            coin_info: false,
+        };
+        dips.write(&inputs);
+        assert_eq!(inputs.2.load(Ordering::Relaxed), 0b1010_1011);
+        assert_eq!(DipSwitches::read(&inputs), dips);
+
+        assert_eq!(dips.next_lives().lives, 3);
+        assert_eq!(DipSwitches::default().next_lives().lives, 4);
+    }
+
+    #[test]
+    fn test_buttons() {
+        let wiring = [
+            (Button::Coin, 1, 0x01, None),
+            (Button::Start2P, 1, 0x02, None),
+            (Button::Start1P, 1, 0x04, None),
+            (Button::P1Fire, 1, 0x10, Some(0x10)),
+            (Button::P1Left, 1, 0x20, Some(0x20)),
+            (Button::P1Right, 1, 0x40, Some(0x40)),
+            (Button::Tilt, 2, 0x04, None),
+            (Button::P2Fire, 2, 0x10, None),
+            (Button::P2Left, 2, 0x20, None),
+            (Button::P2Right, 2, 0x40, None),
+        ];
+        assert_eq!(wiring.len(), Button::ALL.len());
+        for button in Button::ALL {
+            assert_eq!(button.name().parse(), Ok(button));
+        }
+        assert!("p1-fire".parse::<Button>().is_err());
+
+        let machine = Machine::with_program(&[]).unwrap();
+        let ports = |inputs: &Inputs| {
+            [
+                inputs.0.load(Ordering::Relaxed),
+                inputs.1.load(Ordering::Relaxed),
+                inputs.2.load(Ordering::Relaxed),
+            ]
+        };
+        let idle = ports(&machine.inputs);
+        for (button, port, mask, port0) in wiring {
+            assert_eq!(button.port_mask(), (port, mask), "{:?}", button);
+            assert_eq!(button.port0_mask(), port0, "{:?}", button);
+
+            // only the button's own bit changes, and back again
+            machine.press(button);
+            let mut pressed = idle;
+            pressed[port as usize] |= mask;
+            assert_eq!(ports(&machine.inputs), pressed, "{:?}", button);
+            machine.release(button);
+            assert_eq!(ports(&machine.inputs), idle, "{:?}", button);
+
+            button.set_port0(&machine.inputs, true);
+            assert_eq!(ports(&machine.inputs)[0], idle[0] | port0.unwrap_or(0));
+            button.set_port0(&machine.inputs, false);
+            assert_eq!(ports(&machine.inputs), idle);
+        }
+    }
+
+    #[test]
+    fn test_mid_screen_fraction() {
+        let mut machine = Machine::with_program(&[]).unwrap();
+        assert_eq!(machine.next_screen_int_time, 7_142_857);
+        assert!(machine.set_mid_screen_fraction(0.0).is_err());
+        assert!(machine.set_mid_screen_fraction(1.0).is_err());
+
+        machine.set_mid_screen_fraction(0.5).unwrap();
+        assert_eq!(machine.next_screen_int_time, 8_333_334);
+
+        // 4ms into the second frame, which started at one frame's time, so a quarter of
+        // the way through is still to come
+        machine.run_frame();
+        machine.run_frame();
+        machine.run_instructions(2000); // NOPs, 2us each
+        machine.set_mid_screen_fraction(0.25).unwrap();
+        assert_eq!(
+            machine.next_screen_int_time,
+            DISPLAY_TIME_NANO_SEC + 4_166_667
+        );
+
+        // but a tenth has gone, so that waits for the third
+        machine.set_mid_screen_fraction(0.1).unwrap();
+        assert_eq!(
+            machine.next_screen_int_time,
+            2 * DISPLAY_TIME_NANO_SEC + 1_666_667
+        );
+    }
+
+    #[test]
+    fn test_beam_line() {
+        let mut machine = Machine::with_program(&[]).unwrap();
+        assert_eq!(machine.mid_screen_line(), MID_SCREEN_SCANLINE);
+
+        // 4ms into the second frame
+        machine.run_frame();
+        machine.run_frame();
+        assert_eq!(machine.beam_line(), 0);
+        machine.run_instructions(2000);
+        assert_eq!(machine.beam_line(), 53);
+
+        machine.set_mid_screen_fraction(0.5).unwrap();
+        assert_eq!(machine.mid_screen_line(), 112);
+    }
+
+    #[test]
+    fn test_step_with() {
+        let mut machine = Machine::with_program(&[
+            0x3E, 0x42, // MVI A, 0x42
+            0xC3, 0x00, 0x00, // JMP 0x0000
+        ])
+        .unwrap();
+
+        let mut trace = Vec::new();
+        for _ in 0..4 {
+            machine.step_with(|info| trace.push((info.pc, info.cycles)));
+        }
+        assert_eq!(
+            trace,
+            [(0x0000, 7), (0x0002, 10), (0x0000, 7), (0x0002, 10)]
+        );
+    }
+
+    #[test]
+    fn test_run_until_quiescent() {
+        // fills VRAM for a while then sits in a loop
+        let mut program = vec![0x3E, 0xFF]; // MVI A, 0xFF
+        for addr in 0x2400u16..0x2800 {
+            let [lo, hi] = addr.to_le_bytes();
+            program.extend([0x32, lo, hi]); // STA addr
+        }
+        let end = program.len() as u16;
+        let [lo, hi] = end.to_le_bytes();
+        program.extend([0xC3, lo, hi]); // JMP end
+
+        let mut machine = Machine::with_program(&program).unwrap();
+        let settled = machine.run_until_quiescent(100, 5).unwrap();
+        assert_eq!(machine.memory.read_byte(0x27FF), 0xFF);
+        assert_eq!(settled, machine.frame());
+
+        // never settles if it isn't given long enough
+        let mut machine = Machine::with_program(&program).unwrap();
+        assert_eq!(machine.run_until_quiescent(3, 5), None);
+    }
+
+    #[test]
+    fn test_frame_hash() {
+        let mut a = Machine::with_program(&[]).unwrap();
+        let mut b = Machine::with_program(&[]).unwrap();
+        assert_eq!(a.frame_hash(), b.frame_hash());
+
+        a.memory.write_byte(0x3000, 0x10);
+        assert_ne!(a.frame_hash(), b.frame_hash());
+        b.memory.write_byte(0x3000, 0x10);
+        assert_eq!(a.frame_hash(), b.frame_hash());
+
+        // the same pixels laid out for another rotation
+        let memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::None);
+        let mut c = Machine::new(memory, new_inputs(), CabinetType::Upright);
+        c.memory.write_byte(0x3000, 0x10);
+        assert_eq!(a.frame_hash(), c.frame_hash());
+    }
+
+    #[test]
+    fn test_run_until_playing() {
+        let mut machine = Machine::with_program(&[0xC3, 0x00, 0x00]).unwrap(); // JMP 0x0000
+        assert_eq!(machine.run_until_playing(20), None);
+        assert_eq!(machine.frame(), 20);
+
+        let rom = test_rom();
+        let start = |rom: [u8; ROM_SIZE]| {
+            let memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+            let mut machine = Machine::new(memory, new_inputs(), CabinetType::Upright);
+            machine.run_until_playing(600)
+        };
+        let frame = start(rom).expect("game never started");
+        assert_eq!(start(rom), Some(frame));
+    }
+
+    #[test]
+    fn test_autofire() {
+        assert!(Autofire::from_hz(0.0).is_err());
+        assert!(Autofire::from_hz(31.0).is_err());
+
+        let mut machine = Machine::with_program(&[]).unwrap();
+        let inputs = machine.inputs.clone();
+        machine.autofire[0] = Some(Autofire::from_hz(15.0).unwrap());
+
+        let shot = SpaceInvadersInput1::new().with_p1_shot(true).into_bytes()[0];
+        inputs
+            .1
+            .fetch_or(shot, std::sync::atomic::Ordering::Relaxed);
+
+        let mut fired = Vec::new();
+        for _ in 0..8 {
+            machine.run_frame();
+            fired.push(machine.latched_inputs()[1] & shot != 0);
+        }
+        assert_eq!(fired, [true, true, false, false, true, true, false, false]);
+
+        // player 2 was left alone
+        assert_eq!(machine.autofire[1], None);
+    }
+
+    #[test]
+    fn test_step_on_input() {
+        let mut machine = Machine::with_program(&[]).unwrap();
+        let inputs = machine.inputs.clone();
+        machine.set_step_mode(StepMode::OnInput);
+        assert!(!machine.ready_to_step());
+
+        let shot = SpaceInvadersInput1::new().with_p1_shot(true).into_bytes()[0];
+        inputs
+            .1
+            .fetch_or(shot, std::sync::atomic::Ordering::Relaxed);
+        assert!(machine.ready_to_step());
+        // seen straight away, not a frame later
+        assert_ne!(machine.latched_inputs()[1] & shot, 0);
+        machine.run_frame();
+
+        // holding it doesn't keep going, letting go does
+        assert!(!machine.ready_to_step());
+        inputs
+            .1
+            .fetch_and(!shot, std::sync::atomic::Ordering::Relaxed);
+        assert!(machine.ready_to_step());
+        assert_eq!(machine.latched_inputs()[1] & shot, 0);
+    }
+
+    #[test]
+    fn test_audio_log_is_deterministic() {
+        let rom = test_rom();
+
+        let log = audio_log(rom, 600);
+        assert!(!log.is_empty());
+        assert_eq!(log, audio_log(rom, 600));
+
+        let mut last_frame = 0;
+        let mut notes = Vec::new();
+        for line in log.lines() {
+            let (frame, event) = line.split_once(' ').unwrap();
+            let frame: u64 = frame.parse().unwrap();
+            assert!(frame >= last_frame, "out of order: {}", line);
+            last_frame = frame;
+            if let Some(note) = event.strip_prefix("fleet_movement_") {
+                notes.push(note.parse::<u8>().unwrap());
+            }
+        }
+
+        // the player's shots are heard, and the fleet marches through its four notes
+        // in turn
+        assert!(log.lines().any(|line| line.ends_with(" shot")), "{}", log);
+        assert!(notes.len() >= 4, "{}", log);
+        for pair in notes.windows(2) {
+            assert_eq!(pair[1], pair[0] % 4 + 1, "{}", log);
+        }
+    }
+
+    #[test]
+    fn test_coin_switch() {
+        let mut coin = CoinSwitch::default();
+        let mut queue = InputQueue::new();
+        let credit = InputPulse::credit();
+
+        // a long hold, with the key repeating the whole time
+        let mut presses = 0;
+        let mut held = Vec::new();
+        for _ in 0..100 {
+            if let Some(pulse) = coin.press() {
+                presses += 1;
+                queue.push(pulse);
+            }
+            held.push(queue.held(1) & credit.mask != 0);
+            queue.next_frame();
+        }
+        assert_eq!(presses, 1);
+        let edges = held.windows(2).filter(|pair| !pair[0] && pair[1]).count();
+        assert_eq!(edges + held[0] as usize, 1);
+
+        coin.release();
+        assert_eq!(coin.press(), Some(credit));
+    }
+
+    #[test]
+    fn test_coin_switch_credits_once() {
+        let rom = test_rom();
+
+        let memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+        let mut machine = Machine::new(memory, new_inputs(), CabinetType::Upright);
+        for _ in 0..120 {
+            machine.run_frame();
+        }
+
+        let mut coin = CoinSwitch::default();
+        for _ in 0..120 {
+            if let Some(pulse) = coin.press() {
+                machine.input_queue.push(pulse);
+            }
+            machine.run_frame();
+        }
+        assert_eq!(machine.memory.read_byte(addr::CREDITS), 0x01);
+    }
+
+    #[test]
+    fn test_input_queue() {
+        let mut queue = InputQueue::new();
+        assert_eq!(queue.held(1), 0);
+
+        let credit = InputPulse::credit();
+        let start = InputPulse::start_1p();
+        queue.push(credit);
+        queue.push(start);
+
+        let mut held = Vec::new();
+        for _ in 0..(InputPulse::DEFAULT_FRAMES * 4 + 1) {
+            held.push(queue.held(1));
+            queue.next_frame();
+        }
+
+        let frames = InputPulse::DEFAULT_FRAMES as usize;
+        let expected = [
+            vec![credit.mask; frames],
+            vec![0; frames],
+            vec![start.mask; frames],
+            vec![0; frames + 1],
+        ]
+        .concat();
+        assert_eq!(held, expected);
+        assert_eq!(queue.held(2), 0);
+    }
+
+    struct HoldLeft;
+
+    impl InputSource for HoldLeft {
+        fn poll(&mut self) -> InputState {
+            let left = SpaceInvadersInput1::new().with_p1_left(true).into_bytes()[0];
+            InputState::Held([0, left, 0])
+        }
+    }
+
+    #[test]
+    fn test_input_sources() {
+        let mut machine = Machine::with_program(&[]).unwrap();
+        machine.add_input_source(HoldLeft);
+        let shot = SpaceInvadersInput1::new().with_p1_shot(true).into_bytes()[0];
+        machine.inputs.1.fetch_or(shot, Ordering::Relaxed);
+
+        machine.run_frame();
+        let port1 = SpaceInvadersInput1::from_bytes([machine.latched_inputs()[1]]);
+        assert!(port1.p1_left() && port1.p1_shot() && port1.always_one());
+
+        // a replay takes over from every other source
+        machine.start_replay(vec![[0, 0, 0]; 2]);
+        assert_eq!(machine.latched_inputs(), [0, 0, 0]);
+        machine.run_frame();
+        machine.run_frame();
+        assert!(SpaceInvadersInput1::from_bytes([machine.latched_inputs()[1]]).p1_left());
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut sr = ShiftRegister::new();
+        assert_eq!(sr.amount, 0);
+        assert_eq!(sr.register, 0);
+
+        sr.input_data(0xAA);
+        assert_eq!(sr.register, 0xAA00);
+
+        sr.input_data(0xFF); // 0b11111111
+        assert_eq!(sr.register, 0xFFAA);
+
+        sr.input_data(0x12); // 0b00010010
+        assert_eq!(sr.register, 0x12FF);
+
+        sr.input_amount(0);
+        assert_eq!(sr.output(), 0x12);
+
+        sr.input_amount(2);
+        assert_eq!(sr.output(), 0b01001011);
+
+        let snapshot = DebugSnapshot {
+            shift_register: sr,
+            ..Default::default()
+        };
+        assert_eq!(
+            snapshot.to_string(),
+            "frame 0 pc $0000 shift $12FF by 2 = $4B"
+        );
+
+        sr.input_amount(7);
+        assert_eq!(sr.output(), 0b01111111);
+    }
+}
@@ -3,709 +3,1842 @@
 use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
-use std::path::Path;
+use std::io::{BufReader, BufWriter, Cursor};
+use std::path::{Path, PathBuf};
 
 use std::sync::{
-    atomic::{AtomicBool, AtomicU8, Ordering},
+    atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    mpsc::{channel, Receiver, RecvTimeoutError, Sender},
     Arc, Mutex,
 };
+use std::time::{Duration, Instant};
 
-use log::{debug, error, warn};
+use log::{debug, error, info, warn, LevelFilter};
 
-use modular_bitfield::prelude::*;
+use pixels::{wgpu, Pixels, SurfaceTexture};
 
-use pixels::{Pixels, SurfaceTexture};
-
-use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::event::{ElementState, KeyEvent, MouseButton, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::keyboard::{Key, NamedKey};
-use winit::platform::wayland::EventLoopBuilderExtWayland;
-use winit::window::Window;
+use winit::window::{Fullscreen, Window, WindowLevel};
 
-use awedio::Sound;
+use awedio::backends::{CpalBackend, CpalBufferSize};
+use awedio::sounds::wrappers::{Controller, Pausable};
+use awedio::sounds::MemorySound;
+use awedio::{Manager, Sound};
 
-use emu8080::Intel8080;
-use emu8080::MemoryAccess;
-use emu8080::CYCLE_TIME_NANO_SECS;
+use cpal::traits::{DeviceTrait, HostTrait};
 
-#[allow(non_camel_case_types)]
+use emu8080::CYCLE_TIME_NANO_SECS;
 
-const SCREEN_WIDTH_PIXELS: usize = 256;
-const SCREEN_HEIGHT_PIXELS: usize = 224;
+use space_invaders::audio::{buffer_frames, volume_gain, Gain, SoundExt, VOLUME_STEP};
+use space_invaders::capture::{crc32, Capture, CaptureRecorder, CaptureReplay};
+use space_invaders::compare::{compare_roms, COMPARE_FRAMES};
+use space_invaders::config::{Config, ENV_PREFIX};
+use space_invaders::crash::{CrashReporter, LogTail};
+use space_invaders::disasm::disassemble;
+use space_invaders::events::{Event, EventWriter, RamWatcher};
+use space_invaders::filter::{integer_fit, Filter, LinearRenderer};
+use space_invaders::gamepad::{Gamepads, DEFAULT_RUMBLE_STRENGTH};
+use space_invaders::joystick::{AxisConfig, AxisDirection, MouseAxis};
+use space_invaders::keys::KeyBindings;
+use space_invaders::kiosk::{Activity, AutoRestart, Idle, RestartReason};
+use space_invaders::machine::{
+    new_inputs, AudioLog, Autofire, Button, CabinetType, CoinSwitch, DebugSnapshot, DipSwitches,
+    InputPulse, Inputs, Machine, SoundCategory, SoundEvent, StepMode,
+};
+use space_invaders::memory::{RotationMode, Scoreboard, SpaceInvadersMemory};
+use space_invaders::render::{
+    draw_paused_badge, draw_scaled_text_box, draw_scanline, draw_text_box, scaled_text_box_size,
+    text_box_size, Blend, ColorOverlay, Corner, Palette, Persistence, PixelGrid, Thumbnail,
+    CLASSIC_OVERLAY, DEFAULT_PERSISTENCE,
+};
+use space_invaders::rominfo::{load_rom, load_rom_parts, rom_image, rom_parts_in, RomInfo};
+use space_invaders::selftest::{draw_test_pattern, pixel_map, run_self_test};
+use space_invaders::software::SoftwareSurface;
+use space_invaders::timing::{Catchup, Pacer, TimingLog};
+use space_invaders::trace::{verify_trace, write_trace, TRACE_FRAMES};
+use space_invaders::watch::FileWatcher;
+use space_invaders::{
+    EmulatorThread, DISPLAY_BUFFER_SIZE, DISPLAY_TIME_NANO_SEC, SCREEN_HEIGHT_PIXELS,
+    SCREEN_WIDTH_PIXELS,
+};
 
-const DISPLAY_WIDTH_PIXELS: usize = SCREEN_HEIGHT_PIXELS;
-const DISPLAY_HEIGHT_PIXELS: usize = SCREEN_WIDTH_PIXELS;
+/// Delay before the first attempt to reopen a failed audio device, doubled after each
+/// failed attempt up to [`AUDIO_RETRY_MAX`]
+const AUDIO_RETRY_MIN: Duration = Duration::from_millis(250);
+const AUDIO_RETRY_MAX: Duration = Duration::from_secs(8);
 
-const SCREEN_SIZE_PIXELS: usize = SCREEN_WIDTH_PIXELS * SCREEN_HEIGHT_PIXELS;
-const DISPLAY_BUFFER_SIZE: usize = SCREEN_SIZE_PIXELS * 4;
+/// How often the audio thread checks on the device when the game is quiet
+const AUDIO_POLL_TIME: Duration = Duration::from_millis(100);
 
-const DISPLAY_TIME_NANO_SEC: u64 = 16_666_667;
+/// An open audio device with the looping UFO sound already playing on it
+struct AudioOutput {
+    manager: Manager,
+    _backend: CpalBackend,
+    failed: Arc<AtomicBool>,
+    ufo_controller: Controller<Pausable<MemorySound>>,
+}
 
-const ROM_SIZE: usize = 0x2000;
-const RAM_SIZE: usize = 0x400;
-const VRAM_SIZE: usize = 0x1C00;
+impl AudioOutput {
+    fn start(
+        ufo_sound: &MemorySound,
+        ufo_playing: bool,
+        amp_gain: &Arc<Gain>,
+        ufo_gain: &Arc<Gain>,
+        latency: Option<Duration>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut backend = match latency {
+            Some(latency) => open_backend_with_latency(latency)?,
+            None => {
+                info!("Using the audio device's default buffer size");
+                CpalBackend::with_defaults().ok_or("no default audio device")?
+            }
+        };
+
+        // the stream reports errors from its own thread, so they are picked up on the next event
+        let failed = Arc::new(AtomicBool::new(false));
+        let failed_stream = failed.clone();
+        let mut manager = backend.start(move |e| {
+            error!("Audio stream error: {}", e);
+            failed_stream.store(true, Ordering::Relaxed);
+        })?;
+
+        let (ufo_sound, mut ufo_controller) = ufo_sound.clone().pausable().controllable();
+        ufo_controller.set_paused(!ufo_playing);
+        manager.play(Box::new(ufo_sound.faded(amp_gain).faded(ufo_gain)));
+
+        Ok(AudioOutput {
+            manager,
+            _backend: backend,
+            failed,
+            ufo_controller,
+        })
+    }
+}
 
-const ROM_START: usize = 0;
-const RAM_START: usize = 0x2000;
-const VRAM_START: usize = 0x2400;
+/// Opens the default device with a buffer of about `latency`, as close as the device
+/// allows
+fn open_backend_with_latency(latency: Duration) -> Result<CpalBackend, Box<dyn Error>> {
+    let device = cpal::default_host()
+        .default_output_device()
+        .ok_or("no default audio device")?;
+    let config = device.default_output_config()?;
+    let sample_rate = config.sample_rate().0;
+
+    let supported = match *config.buffer_size() {
+        cpal::SupportedBufferSize::Range { min, max } => Some((min, max)),
+        cpal::SupportedBufferSize::Unknown => None,
+    };
+    let (frames, fits) = buffer_frames(latency, sample_rate, supported);
+    let effective = Duration::from_secs_f64(frames as f64 / sample_rate as f64);
+    if !fits {
+        warn!(
+            "Audio latency of {:?} isn't supported by the device, using {:?}",
+            latency, effective
+        );
+    }
+    info!(
+        "Audio buffer is {} frames at {} Hz, {:?}",
+        frames, sample_rate, effective
+    );
+
+    CpalBackend::with_default_host_and_device(
+        config.channels(),
+        sample_rate,
+        CpalBufferSize::Fixed(frames),
+    )
+    .ok_or_else(|| "no default audio device".into())
+}
 
-const ROM_END: usize = ROM_START + ROM_SIZE;
-const RAM_END: usize = RAM_START + RAM_SIZE;
-const VRAM_END: usize = VRAM_START + VRAM_SIZE;
+/// The default sound samples, built in so the binary runs from anywhere
+const BUILT_IN_SAMPLES: [(&str, &[u8]); 9] = [
+    (
+        "ufo_lowpitch.wav",
+        include_bytes!("assets/ufo_lowpitch.wav"),
+    ),
+    ("shoot.wav", include_bytes!("assets/shoot.wav")),
+    (
+        "ufo_highpitch.wav",
+        include_bytes!("assets/ufo_highpitch.wav"),
+    ),
+    (
+        "invaderkilled.wav",
+        include_bytes!("assets/invaderkilled.wav"),
+    ),
+    (
+        "fastinvader1.wav",
+        include_bytes!("assets/fastinvader1.wav"),
+    ),
+    (
+        "fastinvader2.wav",
+        include_bytes!("assets/fastinvader2.wav"),
+    ),
+    (
+        "fastinvader3.wav",
+        include_bytes!("assets/fastinvader3.wav"),
+    ),
+    (
+        "fastinvader4.wav",
+        include_bytes!("assets/fastinvader4.wav"),
+    ),
+    ("explosion.wav", include_bytes!("assets/explosion.wav")),
+];
+
+/// Loads the WAV file `name` from the `--samples` directory, or the built-in one if
+/// there's no directory or the file there is missing or can't be decoded. `looping`
+/// sounds repeat until stopped.
+fn load_sample(samples: Option<&Path>, name: &str, looping: bool) -> MemorySound {
+    let decode = |sound: Box<dyn Sound>| -> Result<MemorySound, Box<dyn Error>> {
+        Ok(if looping {
+            sound.loop_from_memory()?
+        } else {
+            sound.into_memory_sound()?
+        })
+    };
 
-const RAM_MASK: usize = 0x3FFF;
+    if let Some(dir) = samples {
+        let path = dir.join(name);
+        if !path.exists() {
+            warn!("No {} in {}, using the default", name, dir.display());
+        } else {
+            match awedio::sounds::open_file(&path)
+                .map_err(Into::into)
+                .and_then(decode)
+            {
+                Ok(sound) => return sound,
+                Err(e) => warn!(
+                    "Could not load {}, using the default: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+    }
 
-struct SpaceInvadersMemory {
-    rom: [u8; ROM_SIZE],
-    ram: [u8; RAM_SIZE],
-    vram: std::vec::Vec<u8>,
+    let (_, bytes) = BUILT_IN_SAMPLES
+        .iter()
+        .find(|(built_in, _)| *built_in == name)
+        .unwrap_or_else(|| panic!("No built-in sample {}", name));
+    awedio::sounds::open_file_with_reader(Cursor::new(*bytes), "wav")
+        .map_err(Into::into)
+        .and_then(decode)
+        .unwrap_or_else(|e| panic!("Could not load the built-in {}: {}", name, e))
 }
 
-impl MemoryAccess for SpaceInvadersMemory {
-    fn read_byte(&self, addr: u16) -> u8 {
-        let addr: usize = addr as usize & RAM_MASK;
+/// Plays the sounds triggered by the emulator. If the audio device fails it keeps
+/// reopening the default one with a growing delay, dropping one-shot sounds until it
+/// succeeds, so emulation carries on uninterrupted.
+fn audio_loop(
+    sound_events: Receiver<SoundEvent>,
+    run_state: Arc<RunState>,
+    latency: Option<Duration>,
+    samples: Option<PathBuf>,
+) {
+    let samples = samples.as_deref();
+    let ufo_sound = load_sample(samples, "ufo_lowpitch.wav", true);
+    let shot_sound = load_sample(samples, "shoot.wav", false);
+    let flash_sound = load_sample(samples, "ufo_highpitch.wav", false);
+    let invader_die_sound = load_sample(samples, "invaderkilled.wav", false);
+    let fleet_movement_1_sound = load_sample(samples, "fastinvader1.wav", false);
+    let fleet_movement_2_sound = load_sample(samples, "fastinvader2.wav", false);
+    let fleet_movement_3_sound = load_sample(samples, "fastinvader3.wav", false);
+    let fleet_movement_4_sound = load_sample(samples, "fastinvader4.wav", false);
+    let ufo_hit_sound = load_sample(samples, "explosion.wav", true);
+
+    let fleet_movement_sounds = [
+        fleet_movement_1_sound,
+        fleet_movement_2_sound,
+        fleet_movement_3_sound,
+        fleet_movement_4_sound,
+    ];
+
+    // the board's amplifier stays off until the game enables it
+    let amp_gain = Gain::new(0.0);
+    let mut amp_enabled = false;
+    let category_gains: [Arc<Gain>; SoundCategory::ALL.len()] = std::array::from_fn(|i| {
+        Gain::new(if run_state.muted[i].load(Ordering::Relaxed) {
+            0.0
+        } else {
+            1.0
+        })
+    });
+    let mut ufo_playing = false;
+
+    let mut output: Option<AudioOutput> = None;
+    let mut retry_delay = AUDIO_RETRY_MIN;
+    let mut next_attempt = Instant::now();
+
+    loop {
+        if output
+            .as_ref()
+            .is_some_and(|output| output.failed.load(Ordering::Relaxed))
+        {
+            warn!("Audio device lost, reconnecting");
+            output = None;
+        }
 
-        if addr < ROM_END {
-            return self.rom[addr];
-        } else if addr < RAM_END {
-            return self.ram[addr - RAM_START];
-        } else if addr < VRAM_END {
-            return self.read_vram(addr - VRAM_START);
+        if output.is_none() && Instant::now() >= next_attempt {
+            match AudioOutput::start(
+                &ufo_sound,
+                ufo_playing,
+                &amp_gain,
+                &category_gains[SoundCategory::Ufo as usize],
+                latency,
+            ) {
+                Ok(new_output) => {
+                    output = Some(new_output);
+                    retry_delay = AUDIO_RETRY_MIN;
+                }
+                Err(e) => {
+                    error!(
+                        "Error starting audio backend: {}, retrying in {:?}",
+                        e, retry_delay
+                    );
+                    next_attempt = Instant::now() + retry_delay;
+                    retry_delay = (retry_delay * 2).min(AUDIO_RETRY_MAX);
+                }
+            }
         }
 
-        return 0;
-    }
+        // silence the machine while it's paused or racing ahead, without the game knowing
+        let audible = amp_enabled
+            && !run_state.paused.load(Ordering::Relaxed)
+            && !run_state.fast_forwarding.load(Ordering::Relaxed)
+            && !run_state.volume_muted.load(Ordering::Relaxed);
+        amp_gain.set(if audible {
+            volume_gain(run_state.volume.load(Ordering::Relaxed))
+        } else {
+            0.0
+        });
+        for (gain, muted) in category_gains.iter().zip(&run_state.muted) {
+            gain.set(if muted.load(Ordering::Relaxed) {
+                0.0
+            } else {
+                1.0
+            });
+        }
 
-    fn write_byte(&mut self, addr: u16, val: u8) {
-        let addr: usize = addr as usize & RAM_MASK;
+        let sound_event = match sound_events.recv_timeout(AUDIO_POLL_TIME) {
+            Ok(sound_event) => sound_event,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
 
-        if RAM_START <= addr && addr < RAM_END {
-            self.ram[addr - RAM_START] = val;
-        } else if VRAM_START <= addr && addr < VRAM_END {
-            self.write_vram(addr - VRAM_START, val);
+        let sound = match sound_event {
+            SoundEvent::AmpEnable(enabled) => {
+                amp_enabled = enabled;
+                continue;
+            }
+            SoundEvent::UfoStart | SoundEvent::UfoStop => {
+                ufo_playing = sound_event == SoundEvent::UfoStart;
+                if let Some(ref mut output) = output {
+                    output.ufo_controller.set_paused(!ufo_playing);
+                }
+                continue;
+            }
+            SoundEvent::ExtendedPlay => continue,
+            SoundEvent::Shot => shot_sound.clone(),
+            SoundEvent::Flash => flash_sound.clone(),
+            SoundEvent::InvaderDie => invader_die_sound.clone(),
+            SoundEvent::FleetMovement(note) => fleet_movement_sounds[note as usize - 1].clone(),
+            SoundEvent::UfoHit => ufo_hit_sound.clone(),
+        };
+
+        if let Some(ref mut output) = output {
+            let sound = sound.faded(&amp_gain);
+            match sound_event.category() {
+                Some(category) => output
+                    .manager
+                    .play(Box::new(sound.faded(&category_gains[category as usize]))),
+                None => output.manager.play(Box::new(sound)),
+            }
         }
     }
+}
+
+/// Frames `--start-paused` waits for the game to draw something before pausing anyway
+const START_PAUSED_MAX_FRAMES: u64 = 120;
+
+/// Requests from the window, carried out by the emulator thread between frames
+enum Command {
+    Input(InputPulse),
+    /// keep a copy of work RAM, not VRAM or the CPU registers
+    SaveRam,
+    LoadRam,
+    /// the reset button, which applies DIP switches the game only reads at boot
+    Reset,
+    /// a reset with RAM and VRAM cleared
+    PowerCycle,
+}
+
+/// Run control shared between the window and the emulator thread
+#[derive(Default)]
+struct RunState {
+    /// shared with the [`EmulatorThread`], which clears it to stop the emulator
+    running: Arc<AtomicBool>,
+    paused: AtomicBool,
+    /// set until `--fast-forward-to` reaches its frame
+    fast_forwarding: AtomicBool,
+    /// indexed by [`SoundCategory`]
+    muted: [AtomicBool; SoundCategory::ALL.len()],
+    /// out of 100, `--volume` and then + and -
+    volume: AtomicU8,
+    /// everything silenced with M, leaving the volume as it was
+    volume_muted: AtomicBool,
+    /// taken every frame with `--debug-overlay`
+    debug: Mutex<DebugSnapshot>,
+    /// read every frame with `--accessible-hud`
+    scoreboard: Mutex<Scoreboard>,
+    /// asks for VRAM in the game's own layout instead of the display, toggled with F6
+    raw_vram: AtomicBool,
+    /// set when a ship is lost with `--rumble`, until the gamepads have rumbled
+    rumble: AtomicBool,
+    /// the layout last copied into the VRAM mirror, set with the mirror locked
+    raw_vram_shown: AtomicBool,
+    /// `--show-beam`, toggled with F7
+    show_beam: AtomicBool,
+    /// where the beam and the mid-screen interrupt were after the last batch of
+    /// instructions, only kept up to date with `show_beam`
+    beam_line: AtomicU64,
+    mid_screen_line: AtomicU64,
+}
 
-    fn read_bytes<const C: usize>(&self, addr: u16) -> [u8; C] {
-        let addr: usize = addr as usize & RAM_MASK;
+/// Counted by the emulator thread and logged when it stops
+#[derive(Default)]
+struct Stats {
+    frames: u64,
+    instructions: u64,
+    /// time spent running, leaving out pauses
+    running_time: Duration,
+    /// batches of instructions that took longer than they would on the real machine
+    overruns: u64,
+    /// the most any one batch fell behind by
+    peak_behind: Duration,
+}
 
-        if addr < ROM_END {
-            let start = addr;
-            let end = start + C;
-            unsafe { self.rom[start..end].try_into().unwrap_unchecked() }
-        } else if RAM_START <= addr && addr < RAM_END {
-            let start = addr - RAM_START;
-            let end = start + C;
-            unsafe { self.ram[start..end].try_into().unwrap_unchecked() }
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let seconds = self.running_time.as_secs_f64();
+        let fps = if seconds > 0.0 {
+            self.frames as f64 / seconds
         } else {
-            let mut ret = [0x00u8; C];
-            let start = addr - VRAM_START;
-            for i in 0..C {
-                ret[i] = self.read_vram(start + i);
-            }
-            ret
-        }
+            0.0
+        };
+        write!(
+            f,
+            "{} frames, {} instructions in {:.1}s ({:.2} fps), {} cycle time overruns, at most {:?} behind",
+            self.frames, self.instructions, seconds, fps, self.overruns, self.peak_behind
+        )
     }
+}
+
+/// `--show-beam`'s line for where the beam is
+const BEAM_COLOR: [u8; 3] = [0xFF, 0xFF, 0x00];
+/// `--show-beam`'s line for where the mid-screen interrupt comes, which the game
+/// waits for before drawing in the top half of the screen
+const BEAM_MID_SCREEN_COLOR: [u8; 3] = [0x80, 0x00, 0x00];
+
+/// Largest `--scale`; bigger than this and few screens would fit the window anyway
+const MAX_WINDOW_SCALE: u32 = 6;
+
+/// How often `--debug-overlay` rewrites the window title, fast enough to follow but
+/// not so fast the title bar can't keep up
+const DEBUG_TITLE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Optional records of the session the emulator thread writes, or checks, as it runs
+#[derive(Default)]
+struct Recorders {
+    event_writer: Option<EventWriter>,
+    audio_log: Option<AudioLog<BufWriter<File>>>,
+    capture: Option<CaptureRecorder>,
+    capture_replay: Option<CaptureReplay>,
+    memory_dump: Option<MemoryDump>,
+    timing_log: Option<TimingLog<BufWriter<File>>>,
+    crash_reporter: Option<CrashReporter>,
+    /// `--watch-rom`, with whether to pad the ROM when it's reloaded
+    rom_watcher: Option<(FileWatcher, bool)>,
+    auto_restart: Option<AutoRestart>,
+}
+
+/// Dumps memory for `--dump-on-exit`, from the machine when the emulator stops or from
+/// a copy taken every frame if something panics first
+struct MemoryDump {
+    path: PathBuf,
+    vram: bool,
+    last_frame: Arc<Mutex<Vec<u8>>>,
+}
 
-    fn write_bytes(&mut self, addr: u16, val: &[u8]) {
-        let addr: usize = addr as usize & RAM_MASK;
-        if addr < ROM_END {
-            let start = addr;
-            let end = start + val.len();
-            self.rom[start..end].copy_from_slice(val);
-        } else if RAM_START <= addr && addr < RAM_END {
-            let start = addr - RAM_START;
-            let end = start + val.len();
-            self.ram[start..end].copy_from_slice(val);
-        } else if VRAM_START <= addr && VRAM_END <= addr {
-            for i in 0..val.len() {
-                self.write_vram(addr + i, val[i]);
+impl MemoryDump {
+    /// Also installs a panic hook that writes the last frame's copy before unwinding
+    fn new(path: PathBuf, vram: bool) -> Self {
+        let last_frame = Arc::new(Mutex::new(Vec::new()));
+
+        let hook_path = path.clone();
+        let hook_last_frame = last_frame.clone();
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // the panic may have come while the copy was being taken
+            if let Ok(dump) = hook_last_frame.try_lock() {
+                if !dump.is_empty() {
+                    match std::fs::write(&hook_path, dump.as_slice()) {
+                        Ok(()) => eprintln!(
+                            "Dumped memory as of the last frame to {}",
+                            hook_path.display()
+                        ),
+                        Err(e) => eprintln!("Error dumping memory: {}", e),
+                    }
+                }
             }
+            default_hook(info);
+        }));
+
+        Self {
+            path,
+            vram,
+            last_frame,
         }
     }
-}
 
-impl SpaceInvadersMemory {
-    fn new(rom: [u8; ROM_SIZE]) -> Self {
-        SpaceInvadersMemory {
-            rom,
-            ram: [0 as u8; RAM_SIZE],
-            vram: vec![0 as u8; DISPLAY_BUFFER_SIZE],
+    fn update(&self, memory: &SpaceInvadersMemory) {
+        if let Ok(mut dump) = self.last_frame.lock() {
+            dump.clear();
+            // writing to a Vec can't fail
+            let _ = memory.write_dump(&mut *dump, self.vram);
         }
     }
 
-    fn get_display_pixel_address(&self, address: usize, pixel: u8) -> usize {
-        let pixel_address = (address * 8) + pixel as usize;
-        let display_col = pixel_address / SCREEN_WIDTH_PIXELS;
-        let display_row = SCREEN_WIDTH_PIXELS - 1 - (pixel_address % SCREEN_WIDTH_PIXELS);
+    fn finish(self, memory: &SpaceInvadersMemory) {
+        let result = File::create(&self.path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            memory.write_dump(&mut out, self.vram)?;
+            out.flush()
+        });
+        match result {
+            Ok(()) => info!("Dumped memory to {}", self.path.display()),
+            Err(e) => error!("Error dumping memory: {}", e),
+        }
+    }
+}
 
-        return (display_row * DISPLAY_WIDTH_PIXELS) + display_col;
+fn emulator_loop(
+    mut machine: Machine,
+    vram_mirror: Arc<Mutex<Vec<u8>>>,
+    run_state: Arc<RunState>,
+    window: Arc<Window>,
+    mut recorders: Recorders,
+    commands: Receiver<Command>,
+    options: Options,
+) -> Machine {
+    let (sound_sender, sound_events) = channel();
+    let run_state_audio = run_state.clone();
+    let audio_latency = options.audio_latency;
+    let samples = options.samples.clone();
+    // with no audio thread the sounds go nowhere, as they do when there's no device
+    let audio_thread = (!options.no_audio).then(|| {
+        std::thread::spawn(move || {
+            audio_loop(sound_events, run_state_audio, audio_latency, samples)
+        })
+    });
+
+    let mut ram_watcher = RamWatcher::default();
+    let mut ram_slot = None;
+    let mut pause_once_drawn = options.start_paused;
+    let mut stats = Stats::default();
+    let mut pacer = Pacer::new(options.catchup);
+
+    // no sleeping between batches until the frame comes round
+    let mut fast_forward_to = options
+        .fast_forward_to
+        .filter(|&frame| frame > machine.frame());
+    if let Some(frame) = fast_forward_to {
+        info!("Fast forwarding to frame {}", frame);
+        run_state.fast_forwarding.store(true, Ordering::Relaxed);
     }
 
-    fn write_vram(&mut self, address: usize, val: u8) {
-        const DISPLAY_PIXEL_SIZE: usize = 4;
-        const WHITE_PIXEL: [u8; DISPLAY_PIXEL_SIZE] = [0xFF, 0xFF, 0xFF, 0xFF];
-        const BLACK_PIXEL: [u8; DISPLAY_PIXEL_SIZE] = [0x00, 0x00, 0x00, 0xFF];
+    // run main loop
+    while run_state.running.load(Ordering::Relaxed) {
+        // switch layouts straight away, even while paused
+        if run_state.raw_vram.load(Ordering::Relaxed)
+            != run_state.raw_vram_shown.load(Ordering::Relaxed)
+        {
+            if let Ok(ref mut vram_mirror) = vram_mirror.lock() {
+                mirror_display(&machine, &run_state, vram_mirror);
+                window.request_redraw();
+            }
+        }
+
+        if run_state.paused.load(Ordering::Relaxed) {
+            if let Some(ref mut auto_restart) = recorders.auto_restart {
+                if let Some(reason) = auto_restart.update_paused(Instant::now()) {
+                    restart(&mut machine, auto_restart.timeout(), reason);
+                    run_state.paused.store(false, Ordering::Relaxed);
+                }
+            }
+            std::thread::sleep(Duration::from_nanos(DISPLAY_TIME_NANO_SEC));
+            continue;
+        }
+
+        if !machine.ready_to_step() {
+            std::thread::sleep(Duration::from_nanos(DISPLAY_TIME_NANO_SEC));
+            continue;
+        }
+
+        let mut total_cpu_cycles = 0;
+        let now = std::time::Instant::now();
+
+        // frames run while catching up with --catchup drop aren't shown
+        let dropping = pacer.dropping();
+        let stepping = machine.step_mode() == StepMode::OnInput;
+        let mut steps = 0;
+        let mut batch_vblank = false;
+        loop {
+            let step = machine.step();
+            total_cpu_cycles += step.cycles;
+            stats.instructions += 1;
+
+            let frame = machine.frame();
+            for sound_event in machine.drain_sound_events() {
+                if let Some(ref mut audio_log) = recorders.audio_log {
+                    if let Err(e) = audio_log.record(frame, sound_event) {
+                        error!("Error writing audio log: {}", e);
+                        recorders.audio_log = None;
+                    }
+                }
+                if let Some(ref mut capture) = recorders.capture {
+                    capture.record_sound(frame, sound_event);
+                }
+                if let Some(ref mut replay) = recorders.capture_replay {
+                    if let Err(e) = replay.check_sound(frame, sound_event) {
+                        error!("{}", e);
+                        run_state.running.store(false, Ordering::Relaxed);
+                    }
+                }
+
+                if sound_event == SoundEvent::UfoHit {
+                    if let Some(ref event_writer) = recorders.event_writer {
+                        event_writer.emit(frame, Event::UfoDestroyed);
+                    }
+                }
+
+                // the audio thread only goes away if the sounds failed to load
+                let _ = sound_sender.send(sound_event);
+            }
+
+            // draw screen if needed
+            if step.vblank {
+                stats.frames += 1;
+                batch_vblank = true;
+
+                if let Some(ref mut capture) = recorders.capture {
+                    capture.record_frame(&machine);
+                }
+
+                if let Some(ref memory_dump) = recorders.memory_dump {
+                    memory_dump.update(&machine.memory);
+                }
 
-        // the screen is rotated 90 degrees counter-clockwise
-        // so pixel address 0 is at (FRAME_HEIGHT, 0) or FRAME_HEIGHT * FRAME_WIDTH * PIXEL_DEPTH
-        // pixel address 1 is at (1, FRAME_HEIGHT) or FRAME_HEIGHT * (FRAME_WIDTH - 1) * PIXEL_DEPTH
-        let starting_pixel_address: usize = self.get_display_pixel_address(address, 0);
+                if let Some(ref crash_reporter) = recorders.crash_reporter {
+                    crash_reporter.update(&machine);
+                }
+
+                if let Some(ref mut auto_restart) = recorders.auto_restart {
+                    if let Some(reason) =
+                        auto_restart.update(Instant::now(), Activity::of(&machine))
+                    {
+                        restart(&mut machine, auto_restart.timeout(), reason);
+                    }
+                }
+
+                if let Some((ref mut rom_watcher, pad)) = recorders.rom_watcher {
+                    if rom_watcher.poll(Instant::now()) {
+                        let path = rom_watcher.path();
+                        match load_rom(path, pad) {
+                            Ok(rom) => {
+                                machine.memory.set_rom(rom);
+                                machine.reset();
+                                info!("Reloaded {} and reset", path.display());
+                            }
+                            Err(e) => warn!(
+                                "Could not reload {}, keeping the old ROM: {}",
+                                path.display(),
+                                e
+                            ),
+                        }
+                    }
+                }
+
+                if options.debug_overlay {
+                    if let Ok(mut debug) = run_state.debug.lock() {
+                        *debug = machine.debug_snapshot();
+                    }
+                }
+
+                if options.accessible_hud {
+                    if let Ok(mut scoreboard) = run_state.scoreboard.lock() {
+                        *scoreboard = machine.memory.scoreboard();
+                    }
+                }
 
-        for i in 0..8 {
-            let pixel_display_address = starting_pixel_address - (i * DISPLAY_WIDTH_PIXELS);
-            let byte_address = pixel_display_address * DISPLAY_PIXEL_SIZE;
+                if let Some(ref mut replay) = recorders.capture_replay {
+                    if let Err(e) = replay.check_frame(&machine) {
+                        // stop on the frame that differs so it stays on screen
+                        error!("{}", e);
+                        run_state.running.store(false, Ordering::Relaxed);
+                    } else if replay.finished() {
+                        info!("Capture replay matched every snapshot");
+                        recorders.capture_replay = None;
+                    }
+                }
+
+                while let Ok(command) = commands.try_recv() {
+                    match command {
+                        Command::Input(pulse) => machine.input_queue.push(pulse),
+                        Command::SaveRam => {
+                            ram_slot = Some(machine.memory.save_ram());
+                            info!("Saved RAM (CPU registers are not saved)");
+                        }
+                        Command::LoadRam => match ram_slot {
+                            Some(ref ram) => {
+                                machine.memory.load_ram(ram);
+                                info!("Loaded RAM (CPU registers are not restored, so the game may misbehave)");
+                            }
+                            None => warn!("No RAM saved to load"),
+                        },
+                        Command::Reset => {
+                            machine.reset();
+                            info!("Reset");
+                        }
+                        Command::PowerCycle => {
+                            machine.power_cycle();
+                            info!("Reset with RAM and VRAM cleared");
+                        }
+                    }
+                }
+
+                for event in ram_watcher.update(&machine.memory) {
+                    if let Some(ref event_writer) = recorders.event_writer {
+                        event_writer.emit(machine.frame(), event);
+                    }
 
-            let mask: u8 = 0x1 << i;
+                    if options.rumble && matches!(event, Event::LifeLost { .. }) {
+                        run_state.rumble.store(true, Ordering::Relaxed);
+                    }
+
+                    if options.freeze_on_game_over && event == Event::GameOver {
+                        info!("Game over, press any key to continue");
+                        run_state.paused.store(true, Ordering::Relaxed);
+                    }
+                }
 
-            // pixel is white
-            if mask & val != 0 {
-                self.vram[byte_address..byte_address + 4].copy_from_slice(&WHITE_PIXEL);
+                // boot far enough to have something on screen before stopping
+                if pause_once_drawn
+                    && (machine.memory.vram().iter().any(|&byte| byte != 0)
+                        || machine.frame() >= START_PAUSED_MAX_FRAMES)
+                {
+                    pause_once_drawn = false;
+                    info!("Paused, press P to start");
+                    // paused first so the redraw shows it
+                    run_state.paused.store(true, Ordering::Relaxed);
+                    if let Ok(ref mut vram_mirror) = vram_mirror.lock() {
+                        mirror_display(&machine, &run_state, vram_mirror);
+                        window.request_redraw();
+                    }
+                } else if !dropping {
+                    if let Ok(ref mut vram_mirror) = vram_mirror.try_lock() {
+                        mirror_display(&machine, &run_state, vram_mirror);
+                        window.request_redraw();
+                    }
+                }
             }
-            // pixel is black
-            else {
-                self.vram[byte_address..byte_address + 4].copy_from_slice(&BLACK_PIXEL);
+
+            // a few instructions at a time, or a whole frame when stepping
+            steps += 1;
+            if (stepping && step.vblank) || (!stepping && steps == 5) {
+                break;
             }
         }
-    }
 
-    fn read_vram(&self, address: usize) -> u8 {
-        let mut byte = 0;
-        for i in 0..8 {
-            let byte_address = self.get_display_pixel_address(address, i) * 4;
-            let pixel = self.vram[byte_address];
+        // redrawn between vblanks too, so the line can be seen moving down the frame
+        if run_state.show_beam.load(Ordering::Relaxed) {
+            run_state
+                .beam_line
+                .store(machine.beam_line(), Ordering::Relaxed);
+            run_state
+                .mid_screen_line
+                .store(machine.mid_screen_line(), Ordering::Relaxed);
+            window.request_redraw();
+        }
 
-            if pixel != 0 {
-                byte |= 0x1 << i;
+        let exec_time = now.elapsed();
+        let emu_time_nano_sec: u64 = total_cpu_cycles * CYCLE_TIME_NANO_SECS;
+        let emu_time = std::time::Duration::from_nanos(emu_time_nano_sec);
+
+        let mut overran = false;
+        if let Some(frame) = fast_forward_to {
+            if machine.frame() >= frame {
+                info!("Reached frame {}, back to normal speed", frame);
+                fast_forward_to = None;
+                run_state.fast_forwarding.store(false, Ordering::Relaxed);
+            }
+        } else {
+            let sleep = pacer.pace(exec_time, emu_time);
+            if emu_time > exec_time {
+                std::thread::sleep(sleep);
+            } else {
+                warn!(
+                    "Failed to meet cycle time!
+                   Emulator: {emu_time:?}, Execution: {exec_time:?}, behind: {:?}",
+                    pacer.behind()
+                );
+                overran = true;
+                stats.overruns += 1;
+                stats.peak_behind = stats.peak_behind.max(exec_time - emu_time);
+            }
+        }
+        let wall_time = now.elapsed();
+        stats.running_time += wall_time;
+
+        if let Some(ref mut timing_log) = recorders.timing_log {
+            let slept = if overran {
+                Duration::ZERO
+            } else {
+                wall_time.saturating_sub(exec_time)
+            };
+            timing_log.record_batch(wall_time, emu_time, slept, overran);
+            if batch_vblank {
+                if let Err(e) = timing_log.end_frame(machine.frame()) {
+                    error!("Error writing timing CSV: {}", e);
+                    recorders.timing_log = None;
+                }
             }
         }
-        byte
     }
 
-    fn get_p1_score(&self) -> u16 {
-        u16::from_le_bytes(self.read_bytes::<2>(0x20F8).try_into().unwrap())
+    info!("Session: {}", stats);
+
+    if let Some(capture) = recorders.capture {
+        match capture.finish(&mut machine) {
+            Ok(path) => info!("Saved session capture to {}", path.display()),
+            Err(e) => error!("Error saving session capture: {}", e),
+        }
     }
 
-    fn get_p2_score(&self) -> u16 {
-        u16::from_le_bytes(self.read_bytes::<2>(0x20FC).try_into().unwrap())
+    if let Some(memory_dump) = recorders.memory_dump {
+        memory_dump.finish(&machine.memory);
     }
-}
 
-#[bitfield]
-struct SpaceInvadersInput0 {
-    dip_4: bool,
-    #[skip]
-    __: B3,
-    fire: bool,
-    left: bool,
-    right: bool,
-    #[skip]
-    __: B1,
-}
+    if let Some(timing_log) = recorders.timing_log {
+        if let Err(e) = timing_log.finish() {
+            error!("Error writing timing CSV: {}", e);
+        }
+    }
 
-#[bitfield]
-struct SpaceInvadersInput1 {
-    credit: bool,
-    start_2p: bool,
-    start_1p: bool,
-    #[skip(setters)]
-    always_one: bool,
-    p1_shot: bool,
-    p1_left: bool,
-    p1_right: bool,
-    #[skip]
-    __: B1,
-}
+    // closing the channel stops the audio thread, which closes the device
+    drop(sound_sender);
+    if let Some(Err(e)) = audio_thread.map(|thread| thread.join()) {
+        warn!("Error joining audio thread: {:?}", e);
+    }
 
-#[bitfield]
-struct SpaceInvadersInput2 {
-    #[skip(setters)]
-    dip_3: bool,
-    #[skip(setters)]
-    dip_5: bool,
-    tilt: bool,
-    #[skip(setters)]
-    dip_6: bool,
-    p2_shot: bool,
-    p2_left: bool,
-    p2_right: bool,
-    #[skip(setters)]
-    dip_7: bool,
+    machine
 }
 
-#[bitfield]
-#[derive(Debug)]
-#[allow(dead_code)]
-struct SpaceInvadersAudioOutput1 {
-    #[skip(setters)]
-    ufo: bool,
-    #[skip(setters)]
-    shot: bool,
-    #[skip(setters)]
-    flash: bool,
-    #[skip(setters)]
-    invader_die: bool,
-    #[skip(setters)]
-    extended_play: bool,
-    #[skip(setters)]
-    _amp_enable: bool,
-    #[skip]
-    __: B2,
+/// Resets for `--auto-restart`, logged so whoever looks after the machine can see
+/// when and why
+fn restart(machine: &mut Machine, timeout: Duration, reason: RestartReason) {
+    info!("Auto-restart, {} for {:?}", reason, timeout);
+    machine.reset();
 }
 
-#[bitfield]
-#[derive(Debug)]
-#[allow(dead_code)]
-struct SpaceInvadersAudioOutput2 {
-    #[skip(setters)]
-    fleet_movement_1: bool,
-    #[skip(setters)]
-    fleet_movement_2: bool,
-    #[skip(setters)]
-    fleet_movement_3: bool,
-    #[skip(setters)]
-    fleet_movement_4: bool,
-    #[skip(setters)]
-    ufo_hit: bool,
-    #[skip]
-    __: B3,
+/// Draws the F10 menu over the middle of an RGBA buffer `width` pixels across, showing
+/// `dips` and the key that changes each
+fn draw_dip_menu(frame: &mut [u8], width: usize, dips: DipSwitches) {
+    let lives = format!("L LIVES {}", dips.lives);
+    let bonus = format!(
+        "B BONUS AT {}",
+        if dips.bonus_at_1000 { 1000 } else { 1500 }
+    );
+    let coin_info = format!("I COIN INFO {}", if dips.coin_info { "ON" } else { "OFF" });
+    let lines = [
+        "DIP SWITCHES",
+        lives.as_str(),
+        bonus.as_str(),
+        coin_info.as_str(),
+        "R RESET TO APPLY",
+    ];
+
+    let (menu_width, _) = text_box_size(&lines);
+    let left = width.saturating_sub(menu_width) / 2;
+    // under the score, clear of the paused badge
+    draw_text_box(frame, width, (left, 40), &lines);
 }
 
-struct ShiftRegister {
-    register: u16,
-    amount: u8,
+/// Where frames are drawn: through the GPU, or on the CPU when there isn't one to use
+enum Presenter<'a> {
+    Gpu(Pixels<'a>),
+    Cpu(SoftwareSurface),
 }
 
-impl ShiftRegister {
-    fn new() -> Self {
-        ShiftRegister {
-            register: 0,
-            amount: 0,
+impl Presenter<'_> {
+    /// Sets up a `buffer_size` frame buffer drawn to a `surface_size` window. It goes
+    /// through the GPU unless `software` is set or that fails, then on the CPU, scaled
+    /// by whole multiples if `integer` is set.
+    fn new(
+        buffer_size: (u32, u32),
+        surface_size: (u32, u32),
+        window: &Arc<Window>,
+        software: bool,
+        integer: bool,
+    ) -> Result<Self, String> {
+        if !software {
+            let (width, height) = buffer_size;
+            let surface_texture =
+                SurfaceTexture::new(surface_size.0, surface_size.1, window.clone());
+            match Pixels::new(width, height, surface_texture) {
+                Ok(pixels) => return Ok(Presenter::Gpu(pixels)),
+                Err(e) => warn!(
+                    "Could not set up GPU rendering, falling back to software: {}",
+                    e
+                ),
+            }
         }
+
+        let surface = SoftwareSurface::new(window.clone(), buffer_size, surface_size, integer)?;
+        info!("Rendering on the CPU");
+        Ok(Presenter::Cpu(surface))
     }
 
-    fn input_data(&mut self, input: u8) {
-        self.register = ((input as u16) << 8) | (self.register >> 8);
+    fn frame_mut(&mut self) -> &mut [u8] {
+        match self {
+            Presenter::Gpu(pixels) => pixels.frame_mut(),
+            Presenter::Cpu(software) => software.frame_mut(),
+        }
     }
 
-    fn input_amount(&mut self, amount: u8) {
-        self.amount = amount & 0b00000111;
+    fn resize_buffer(&mut self, width: u32, height: u32) -> Result<(), String> {
+        match self {
+            Presenter::Gpu(pixels) => pixels
+                .resize_buffer(width, height)
+                .map_err(|e| e.to_string()),
+            Presenter::Cpu(software) => {
+                software.resize_buffer(width, height);
+                Ok(())
+            }
+        }
     }
 
-    fn output(&self) -> u8 {
-        (self.register >> (8 - self.amount)) as u8
+    fn resize_surface(&mut self, width: u32, height: u32) -> Result<(), String> {
+        match self {
+            Presenter::Gpu(pixels) => pixels
+                .resize_surface(width, height)
+                .map_err(|e| e.to_string()),
+            Presenter::Cpu(software) => software.resize_surface(width, height),
+        }
     }
 }
 
-fn load_rom(file_path: &Path) -> Result<[u8; ROM_SIZE], std::io::Error> {
-    let mut file = match File::open(&file_path) {
-        Ok(file) => file,
-        Err(e) => return Err(e),
-    };
-
-    let mut buffer = [0 as u8; ROM_SIZE];
-    match file.read(&mut buffer) {
-        Ok(_) => {}
-        Err(e) => return Err(e),
+/// Copies the display into the window's mirror, or raw VRAM if that's been asked for
+fn mirror_display(machine: &Machine, run_state: &RunState, vram_mirror: &mut [u8]) {
+    let raw = run_state.raw_vram.load(Ordering::Relaxed);
+    if raw {
+        machine.memory.raw_vram(vram_mirror);
+    } else {
+        machine.memory.render_frame(vram_mirror);
     }
-    return Ok(buffer);
+    run_state.raw_vram_shown.store(raw, Ordering::Relaxed);
 }
 
-fn emulator_loop(
-    memory: &mut SpaceInvadersMemory,
+struct SpaceInvaders<'a> {
+    /// held here until the emulator thread starts and again once it has stopped
+    machine: Option<Machine>,
     vram_mirror: Arc<Mutex<Vec<u8>>>,
-    running: Arc<AtomicBool>,
-    inputs: Arc<(AtomicU8, AtomicU8, AtomicU8)>,
-    window: Arc<Window>,
-) {
-    let (ufo_sound, mut ufo_sound_controller) =
-        awedio::sounds::open_file("src/assets/ufo_lowpitch.wav")
-            .expect("Could not find ufo_lowpitch.wav")
-            .loop_from_memory()
-            .unwrap()
-            .pausable()
-            .controllable();
-    ufo_sound_controller.set_paused(true);
-
-    let shot_sound = awedio::sounds::open_file("src/assets/shoot.wav")
-        .expect("Could not find shoot.wav")
-        .into_memory_sound()
-        .unwrap();
-
-    let flash_sound = awedio::sounds::open_file("src/assets/ufo_highpitch.wav")
-        .expect("Could not find ufo_highpitch.wav")
-        .into_memory_sound()
-        .unwrap();
-
-    let invader_die_sound = awedio::sounds::open_file("src/assets/invaderkilled.wav")
-        .expect("Could not find invaderkilled.wav")
-        .into_memory_sound()
-        .unwrap();
-
-    let fleet_movement_1_sound = awedio::sounds::open_file("src/assets/fastinvader1.wav")
-        .expect("Could not find fastinvader1.wav")
-        .into_memory_sound()
-        .unwrap();
-
-    let fleet_movement_2_sound = awedio::sounds::open_file("src/assets/fastinvader2.wav")
-        .expect("Could not find fastinvader2.wav")
-        .into_memory_sound()
-        .unwrap();
-
-    let fleet_movement_3_sound = awedio::sounds::open_file("src/assets/fastinvader3.wav")
-        .expect("Could not find fastinvader3.wav")
-        .into_memory_sound()
-        .unwrap();
-
-    let fleet_movement_4_sound = awedio::sounds::open_file("src/assets/fastinvader4.wav")
-        .expect("Could not find fastinvader4.wav")
-        .into_memory_sound()
-        .unwrap();
-
-    let ufo_hit_sound = awedio::sounds::open_file("src/assets/explosion.wav")
-        .expect("Could not find explosion.wav")
-        .loop_from_memory()
-        .unwrap();
-
-    let (mut audio_manager, _audio_backend) = match awedio::start() {
-        Ok(val) => val,
-        Err(e) => {
-            error!("Error starting audio backend: {}", e);
-            running.store(false, Ordering::Relaxed);
+    run_state: Arc<RunState>,
+    inputs: Arc<Inputs>,
+    window: Option<Arc<Window>>,
+    rendered_pixels: Option<Presenter<'a>>,
+    /// replaces the default scaling with `--filter linear`
+    linear_renderer: Option<LinearRenderer>,
+    emulator_thread: Option<EmulatorThread>,
+    options: Options,
+    recorders: Option<Recorders>,
+    commands: Sender<Command>,
+    commands_emu: Option<Receiver<Command>>,
+    /// `--overlay`, or the classic gel for F12 to turn on
+    overlay: Option<ColorOverlay>,
+    overlay_on: bool,
+    /// `--thumbnail`, scaled from each frame and shown in a window of its own
+    thumbnail: Option<Thumbnail>,
+    thumbnail_window: Option<Arc<Window>>,
+    thumbnail_pixels: Option<Presenter<'a>>,
+    /// the display with the palette, overlay, persistence and pause badge applied, kept
+    /// to save allocating every frame
+    tinted: Vec<u8>,
+    /// why the event loop was stopped early, reported once it returns
+    error: Option<String>,
+    /// when `--run-for` shuts everything down
+    deadline: Option<Instant>,
+    debug_title_updated: Option<Instant>,
+    /// whether the frame buffer is laid out for raw VRAM rather than the display
+    raw_vram_buffer: bool,
+    coin: CoinSwitch,
+    /// the DIP switch menu, opened and closed with F10
+    dip_menu: bool,
+    /// `--mouse-control`, moving player 1 with the mouse
+    mouse: Option<MouseAxis>,
+    /// left out if gilrs couldn't be set up
+    gamepads: Option<Gamepads>,
+    /// `--persistence`, or the default amount for F8 to turn on
+    persistence: Persistence,
+    persistence_on: bool,
+    /// `--fullscreen`, then toggled with F11
+    fullscreen: bool,
+}
+
+impl<'a> SpaceInvaders<'a> {
+    fn new(machine: Machine, options: Options, recorders: Recorders) -> Self {
+        let inputs = machine.inputs.clone();
+        let run_state = Arc::new(RunState::default());
+        for category in &options.muted {
+            run_state.muted[*category as usize].store(true, Ordering::Relaxed);
+        }
+        run_state.volume.store(options.volume, Ordering::Relaxed);
+        run_state
+            .show_beam
+            .store(options.show_beam, Ordering::Relaxed);
+        let vram_mirror = Arc::new(Mutex::new(vec![0u8; DISPLAY_BUFFER_SIZE]));
+        let (commands, commands_emu) = channel();
+        let mouse = options
+            .mouse_control
+            .then(|| MouseAxis::new(options.mouse_x));
+        let gamepads = match Gamepads::new(options.stick_x) {
+            Ok(gamepads) => Some(gamepads),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        };
+        let persistence_on = options.persistence.is_some();
+        let overlay_on = options.overlay.is_some();
+        let fullscreen = options.fullscreen;
+        let persistence = options.persistence.clone().unwrap_or_else(|| {
+            Persistence::new(DEFAULT_PERSISTENCE, options.blend)
+                .expect("the default persistence is in range")
+        });
+
+        Self {
+            machine: Some(machine),
+            vram_mirror,
+            run_state,
+            inputs,
+            emulator_thread: None,
+            rendered_pixels: None,
+            linear_renderer: None,
+            window: None,
+            options,
+            recorders: Some(recorders),
+            commands,
+            commands_emu: Some(commands_emu),
+            overlay: None,
+            overlay_on,
+            thumbnail: None,
+            thumbnail_window: None,
+            thumbnail_pixels: None,
+            tinted: Vec::new(),
+            error: None,
+            deadline: None,
+            debug_title_updated: None,
+            raw_vram_buffer: false,
+            coin: CoinSwitch::default(),
+            dip_menu: false,
+            mouse,
+            gamepads,
+            persistence,
+            persistence_on,
+            fullscreen,
+        }
+    }
+
+    /// Shows the latest [`DebugSnapshot`] in the title bar
+    fn update_debug_title(&mut self) {
+        if self
+            .debug_title_updated
+            .is_some_and(|updated| updated.elapsed() < DEBUG_TITLE_INTERVAL)
+        {
             return;
         }
-    };
+        self.debug_title_updated = Some(Instant::now());
 
-    let mut cpu = Intel8080::new();
-    let mut shift_register = ShiftRegister::new();
+        if let (Some(ref window), Ok(debug)) = (&self.window, self.run_state.debug.lock()) {
+            window.set_title(&format!("{} | {}", self.title(), *debug));
+        }
+    }
 
-    let mut next_display_time: u64 = 0;
-    let mut next_screen_int_time: u64 = 7_142_857;
-    let mut emu_clock: u64 = 0;
+    /// Presses or releases a button, copying player 1's controls to port 0 as well with
+    /// `--port0-controls`
+    fn set_button(&self, button: Button, pressed: bool) {
+        button.set(&self.inputs, pressed);
+        if self.options.port0_controls {
+            button.set_port0(&self.inputs, pressed);
+        }
+    }
 
-    let mut last_audio1 = SpaceInvadersAudioOutput1::new();
-    let mut last_audio2 = SpaceInvadersAudioOutput2::new();
+    /// Runs `update` on the mouse axis with `--mouse-control`, moving player 1 when the
+    /// direction held changes. Otherwise the switches are left alone so the arrow keys
+    /// still work.
+    fn update_mouse(&mut self, update: impl FnOnce(&mut MouseAxis) -> AxisDirection) {
+        let Some(mouse) = self.mouse.as_mut() else {
+            return;
+        };
+        let held = mouse.direction();
+        let direction = update(mouse);
+        if direction != held {
+            self.set_p1_direction(direction);
+        }
+    }
 
-    audio_manager.play(Box::new(ufo_sound));
+    /// The control `key` works with the `--bind` key bindings
+    fn bound_button(&self, key: &Key) -> Option<Button> {
+        match key.as_ref() {
+            Key::Character(character) => self.options.key_bindings.button(character),
+            Key::Named(named) => self.options.key_bindings.button(&format!("{:?}", named)),
+            _ => None,
+        }
+    }
 
-    // run main loop
-    while running.load(Ordering::Relaxed) {
-        let mut total_cpu_cycles = 0;
-        let now = std::time::Instant::now();
+    /// Presses or releases a control from the keyboard or a gamepad, the coin through
+    /// its switch so holding the button only counts once
+    fn key_button(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::Coin if pressed => {
+                if let Some(pulse) = self.coin.press() {
+                    let _ = self.commands.send(Command::Input(pulse));
+                }
+            }
+            Button::Coin => self.coin.release(),
+            _ => self.set_button(button, pressed),
+        }
+    }
 
-        for _ in 0..5 {
-            let cpu_cycles = cpu.step(memory);
-            total_cpu_cycles += cpu_cycles;
-
-            if cpu.output_ready() {
-                let output = cpu.read_output();
-                match cpu.active_io_port() {
-                    2 => shift_register.input_amount(output), // shift amount
-                    3 => {
-                        let audio1 = SpaceInvadersAudioOutput1::from_bytes([cpu.read_output()]);
-
-                        if audio1.ufo() && !last_audio1.ufo() {
-                            ufo_sound_controller.set_paused(false);
-                        } else if !audio1.ufo() && last_audio1.ufo() {
-                            ufo_sound_controller.set_paused(true);
-                        }
+    /// Holds player 1's left or right switch, or neither
+    fn set_p1_direction(&self, direction: AxisDirection) {
+        self.set_button(Button::P1Left, direction == AxisDirection::Negative);
+        self.set_button(Button::P1Right, direction == AxisDirection::Positive);
+    }
 
-                        if audio1.shot() && !last_audio1.shot() {
-                            audio_manager.play(Box::new(shot_sound.clone()));
-                        }
+    /// Handles a key pressed with the DIP switch menu open, returning false for keys
+    /// the menu doesn't use so they still reach the game. Switches are flipped in the
+    /// shared inputs straight away, but most only take effect once R resets the game.
+    fn dip_menu_key(&mut self, key: Key<&str>, repeat: bool) -> bool {
+        if repeat {
+            return false;
+        }
 
-                        if audio1.flash() && !last_audio1.flash() {
-                            audio_manager.play(Box::new(flash_sound.clone()));
-                        }
+        let dips = DipSwitches::read(&self.inputs);
+        let dips = match key {
+            Key::Named(NamedKey::F10) => {
+                self.dip_menu = false;
+                self.request_redraw();
+                return true;
+            }
+            Key::Character("l") => dips.next_lives(),
+            Key::Character("b") => DipSwitches {
+                bonus_at_1000: !dips.bonus_at_1000,
+                ..dips
+            },
+            Key::Character("i") => DipSwitches {
+                coin_info: !dips.coin_info,
+                ..dips
+            },
+            Key::Character("r") => {
+                let _ = self.commands.send(Command::Reset);
+                return true;
+            }
+            _ => return false,
+        };
 
-                        if audio1.invader_die() && !last_audio1.invader_die() {
-                            audio_manager.play(Box::new(invader_die_sound.clone()));
-                        }
+        dips.write(&self.inputs);
+        info!("DIP switches now {:?}, reset with R to apply", dips);
+        self.request_redraw();
+        true
+    }
 
-                        last_audio1 = audio1;
-                    }
-                    4 => shift_register.input_data(output), // shift data
-                    5 => {
-                        let audio2 = SpaceInvadersAudioOutput2::from_bytes([cpu.read_output()]);
+    /// Whether the frame is scaled by whole multiples only, as it is with the nearest
+    /// filter and always in fullscreen
+    fn integer_scaling(&self) -> bool {
+        self.fullscreen || self.options.filter == Filter::Nearest
+    }
 
-                        if audio2.fleet_movement_1() && !last_audio2.fleet_movement_1() {
-                            audio_manager.play(Box::new(fleet_movement_1_sound.clone()));
-                        }
+    /// Goes in or out of borderless fullscreen. The surface follows when the window
+    /// reports its new size.
+    fn toggle_fullscreen(&mut self) {
+        let Some(ref window) = self.window else {
+            return;
+        };
+        self.fullscreen = !self.fullscreen;
+        window.set_fullscreen(self.fullscreen.then_some(Fullscreen::Borderless(None)));
+        if let Some(ref mut renderer) = self.linear_renderer {
+            renderer.set_integer(self.fullscreen);
+        }
+        let integer = self.integer_scaling();
+        if let Some(Presenter::Cpu(ref mut software)) = self.rendered_pixels {
+            software.set_integer(integer);
+        }
+        info!("Fullscreen {}", if self.fullscreen { "on" } else { "off" });
+    }
 
-                        if audio2.fleet_movement_2() && !last_audio2.fleet_movement_2() {
-                            audio_manager.play(Box::new(fleet_movement_2_sound.clone()));
-                        }
+    fn request_redraw(&self) {
+        if let Some(ref window) = self.window {
+            window.request_redraw();
+        }
+    }
 
-                        if audio2.fleet_movement_3() && !last_audio2.fleet_movement_3() {
-                            audio_manager.play(Box::new(fleet_movement_3_sound.clone()));
-                        }
+    /// Draws the latest copy of VRAM from the emulator thread
+    fn redraw(&mut self) {
+        if self.options.debug_overlay {
+            self.update_debug_title();
+        }
 
-                        if audio2.fleet_movement_4() && !last_audio2.fleet_movement_4() {
-                            audio_manager.play(Box::new(fleet_movement_4_sound.clone()));
-                        }
+        let mirror = self.vram_mirror.clone();
+        let Ok(vram_mirror) = mirror.lock() else {
+            return;
+        };
+        let raw = self.run_state.raw_vram_shown.load(Ordering::Relaxed);
+        if raw != self.raw_vram_buffer {
+            self.set_raw_vram_buffer(raw);
+        }
+        let (frame_width, frame_height) = self.frame_size();
+        // the overlay's bands only line up with the display
+        let overlay = self.overlay.as_ref().filter(|_| self.overlay_on && !raw);
+
+        if let Some(ref mut rendered_pixels) = self.rendered_pixels {
+            let mut source = vram_mirror.as_slice();
+            let paused = self.run_state.paused.load(Ordering::Relaxed);
+            let show_beam = self.run_state.show_beam.load(Ordering::Relaxed);
+            if self.options.palette.is_some()
+                || overlay.is_some()
+                || paused
+                || self.dip_menu
+                || show_beam
+                || self.options.accessible_hud
+                || self.persistence_on
+            {
+                // raw VRAM is the same size as the display, so this only allocates once
+                self.tinted.resize(source.len(), 0);
+                self.tinted.copy_from_slice(source);
+                if let Some(palette) = self.options.palette {
+                    palette.apply(&mut self.tinted);
+                }
+                if let Some(overlay) = overlay {
+                    overlay.apply(&mut self.tinted);
+                }
+                // the game only, nothing drawn over it leaves a trail
+                if self.persistence_on {
+                    self.persistence.apply(&mut self.tinted, Instant::now());
+                }
+                if show_beam {
+                    let rotation = if raw {
+                        RotationMode::None
+                    } else {
+                        self.options.rotation
+                    };
+                    let mid_screen_line = self.run_state.mid_screen_line.load(Ordering::Relaxed);
+                    let beam_line = self.run_state.beam_line.load(Ordering::Relaxed);
+                    draw_scanline(
+                        &mut self.tinted,
+                        rotation,
+                        mid_screen_line as usize,
+                        BEAM_MID_SCREEN_COLOR,
+                    );
+                    draw_scanline(&mut self.tinted, rotation, beam_line as usize, BEAM_COLOR);
+                }
+                if self.options.accessible_hud {
+                    let lines = match self.run_state.scoreboard.lock() {
+                        Ok(scoreboard) => scoreboard.lines(),
+                        Err(_) => Default::default(),
+                    };
+                    let lines = [lines[0].as_str(), lines[1].as_str()];
+                    let scale = self.options.hud_scale;
+                    let size = scaled_text_box_size(&lines, scale);
+                    let position = self
+                        .options
+                        .hud_corner
+                        .place(size, (frame_width, frame_height));
+                    draw_scaled_text_box(&mut self.tinted, frame_width, position, &lines, scale);
+                }
+                if paused {
+                    draw_paused_badge(&mut self.tinted, frame_width);
+                }
+                if self.dip_menu {
+                    draw_dip_menu(
+                        &mut self.tinted,
+                        frame_width,
+                        DipSwitches::read(&self.inputs),
+                    );
+                }
+                source = &self.tinted;
+            }
 
-                        if audio2.ufo_hit() && !last_audio2.ufo_hit() {
-                            audio_manager.play(Box::new(ufo_hit_sound.clone()));
+            match self.options.pixel_grid {
+                Some(grid) => grid.expand(source, frame_width, rendered_pixels.frame_mut()),
+                None => rendered_pixels.frame_mut().copy_from_slice(source),
+            }
+            let rendered = match (&mut *rendered_pixels, &self.linear_renderer) {
+                (Presenter::Cpu(software), _) => {
+                    if let Err(e) = software.render() {
+                        error!("Could not draw the frame: {}", e);
+                    }
+                    Ok(())
+                }
+                (Presenter::Gpu(pixels), Some(renderer)) => {
+                    pixels.render_with(|encoder, target, context| {
+                        renderer.render(encoder, target, context);
+                        Ok(())
+                    })
+                }
+                (Presenter::Gpu(pixels), None) => pixels.render(),
+            };
+            match rendered {
+                Ok(()) => {}
+                // lost to a GPU reset or a change of display, and back once the surface
+                // is set up again; this frame is dropped and the next one drawn
+                Err(pixels::Error::Surface(
+                    e @ (wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated),
+                )) => {
+                    warn!(
+                        "Could not draw the frame ({}), setting the surface up again",
+                        e
+                    );
+                    if let Some(ref window) = self.window {
+                        let size = window.inner_size();
+                        if size.width > 0 && size.height > 0 {
+                            if let Err(e) = rendered_pixels.resize_surface(size.width, size.height)
+                            {
+                                error!("Could not set the surface up again: {}", e);
+                            }
                         }
-
-                        last_audio2 = audio2;
                     }
-                    6 => { /* do nothing */ } // watch dog
-                    _ => {}
                 }
-            } else if cpu.awaiting_input() {
-                let input: u8 = match cpu.active_io_port() {
-                    0 => inputs.0.load(Ordering::Relaxed), // INPUTS 0
-                    1 => inputs.1.load(Ordering::Relaxed), // INPUTS 1
-                    2 => inputs.2.load(Ordering::Relaxed), // INPUTS 2
-                    3 => shift_register.output(),          // bit shift in
-                    _ => 0,
+                Err(pixels::Error::Surface(wgpu::SurfaceError::Timeout)) => {
+                    debug!("Timed out waiting for the surface, skipping a frame");
+                }
+                Err(e) => error!("Could not draw the frame: {}", e),
+            }
+
+            if let (Some(ref mut thumbnail), Some(ref mut thumbnail_pixels)) =
+                (&mut self.thumbnail, &mut self.thumbnail_pixels)
+            {
+                thumbnail.update(source, frame_width);
+                thumbnail_pixels
+                    .frame_mut()
+                    .copy_from_slice(thumbnail.pixels());
+                let rendered = match thumbnail_pixels {
+                    Presenter::Gpu(pixels) => pixels.render().map_err(|e| e.to_string()),
+                    Presenter::Cpu(software) => software.render(),
                 };
+                if let Err(e) = rendered {
+                    error!("Could not draw the thumbnail: {}", e);
+                }
+            }
+        }
+    }
 
-                cpu.write_input(input);
+    /// Opens the `--thumbnail` window, carrying on without it if that fails
+    fn open_thumbnail(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let Some((width, height)) = self.options.thumbnail else {
+            return;
+        };
+        let thumbnail = match Thumbnail::new(width, height) {
+            Ok(thumbnail) => thumbnail,
+            Err(e) => {
+                warn!("{}", e);
+                return;
             }
+        };
 
-            // draw screen + set interrupts if needed
+        let (width, height) = (width as u32, height as u32);
+        let mut window_attributes = winit::window::WindowAttributes::default();
+        window_attributes.inner_size = Some(winit::dpi::Size::Physical(
+            winit::dpi::PhysicalSize::new(width, height),
+        ));
+        window_attributes.title = "Space Invaders | thumbnail".to_string();
+        let window = match event_loop.create_window(window_attributes) {
+            Ok(window) => Arc::new(window),
+            Err(e) => {
+                warn!("Could not open the thumbnail window: {}", e);
+                return;
+            }
+        };
+        // drawn at its own size, so there's nothing to scale
+        match Presenter::new(
+            (width, height),
+            (width, height),
+            &window,
+            self.options.software_render,
+            true,
+        ) {
+            Ok(presenter) => {
+                self.thumbnail = Some(thumbnail);
+                self.thumbnail_window = Some(window);
+                self.thumbnail_pixels = Some(presenter);
+            }
+            Err(e) => warn!("Could not set up the thumbnail: {}", e),
+        }
+    }
 
-            let emu_time_nano_sec: u64 = cpu_cycles * CYCLE_TIME_NANO_SECS;
+    fn close_thumbnail(&mut self) {
+        self.thumbnail_pixels = None;
+        self.thumbnail_window = None;
+        self.thumbnail = None;
+    }
 
-            emu_clock = emu_clock.wrapping_add(emu_time_nano_sec);
+    /// Says when raw VRAM is shown, so it isn't mistaken for a broken rotation
+    fn title(&self) -> &'static str {
+        if self.raw_vram_buffer {
+            "Space Invaders | raw VRAM, unrotated 256x224"
+        } else {
+            "Space Invaders"
+        }
+    }
 
-            if next_display_time <= emu_clock {
-                next_display_time = next_display_time.wrapping_add(DISPLAY_TIME_NANO_SEC);
-                cpu.interrupt(emu8080::Instruction::RST_3);
+    /// The frame in game pixels, for raw VRAM or the display
+    fn frame_size(&self) -> (usize, usize) {
+        if self.raw_vram_buffer {
+            (SCREEN_WIDTH_PIXELS, SCREEN_HEIGHT_PIXELS)
+        } else {
+            (
+                self.options.rotation.display_width(),
+                self.options.rotation.display_height(),
+            )
+        }
+    }
 
-                if let Ok(ref mut vram_mirror) = vram_mirror.try_lock() {
-                    vram_mirror.copy_from_slice(memory.vram.as_slice());
-                    window.request_redraw();
-                }
-            } else if next_screen_int_time <= emu_clock {
-                next_screen_int_time = next_screen_int_time.wrapping_add(DISPLAY_TIME_NANO_SEC);
-                cpu.interrupt(emu8080::Instruction::RST_2);
+    /// Lays the frame buffer out for raw VRAM or for the display
+    fn set_raw_vram_buffer(&mut self, raw: bool) {
+        self.raw_vram_buffer = raw;
+        let (width, height) = self.frame_size();
+        let (buffer_width, buffer_height) = match self.options.pixel_grid {
+            Some(_) => PixelGrid::buffer_size(width, height),
+            None => (width, height),
+        };
+
+        if let (Some(ref mut presenter), Some(ref window)) =
+            (&mut self.rendered_pixels, &self.window)
+        {
+            if let Err(e) = presenter.resize_buffer(buffer_width as u32, buffer_height as u32) {
+                error!("Could not resize the frame buffer: {}", e);
             }
+            // the old one samples the texture that was just replaced
+            if let (true, Presenter::Gpu(pixels)) = (self.linear_renderer.is_some(), presenter) {
+                let size = window.inner_size();
+                self.linear_renderer = Some(LinearRenderer::new(
+                    pixels,
+                    size.width,
+                    size.height,
+                    self.fullscreen,
+                ));
+            }
+            window.set_title(self.title());
         }
+    }
 
-        let exec_time = now.elapsed();
-        let emu_time_nano_sec: u64 = total_cpu_cycles * CYCLE_TIME_NANO_SECS;
-        let emu_time = std::time::Duration::from_nanos(emu_time_nano_sec);
-
-        if emu_time > exec_time {
-            std::thread::sleep(emu_time - exec_time);
-        } else {
-            warn!(
-                "Failed to meet cycle time!
-                   Emulator: {emu_time:?}, Execution: {exec_time:?}"
-            );
+    /// Stops the emulator thread, which finishes its recordings and audio on the way
+    /// out, and takes the machine back from it. Does nothing if it isn't running, so
+    /// it's safe to call more than once.
+    fn stop_emulator(&mut self) {
+        if let Some(machine) = self
+            .emulator_thread
+            .take()
+            .and_then(|mut thread| thread.shutdown())
+        {
+            self.machine = Some(machine);
         }
     }
-}
 
-struct SpaceInvaders<'a> {
-    memory: Option<SpaceInvadersMemory>,
-    vram_mirror: Arc<Mutex<Vec<u8>>>,
-    running: Arc<AtomicBool>,
-    inputs: Arc<(AtomicU8, AtomicU8, AtomicU8)>,
-    window: Option<Arc<Window>>,
-    rendered_pixels: Option<Pixels<'a>>,
-    emulator_thread: Option<std::thread::JoinHandle<()>>,
-}
+    /// The machine as it was left when the emulator stopped, stopping it first if
+    /// need be. None if the emulator thread panicked.
+    fn into_machine(mut self) -> Option<Machine> {
+        self.stop_emulator();
+        self.machine.take()
+    }
 
-impl<'a> SpaceInvaders<'a> {
-    fn new(memory: SpaceInvadersMemory) -> Self {
-        let inputs = Arc::new((
-            AtomicU8::new(0b1000_1111),
-            AtomicU8::new(0b0000_1000),
-            AtomicU8::new(0b0000_0000),
-        ));
-        let running = Arc::new(AtomicBool::new(false));
-        let vram_mirror = Arc::new(Mutex::new(vec![0u8; DISPLAY_BUFFER_SIZE]));
+    /// Stops the emulator thread, then lets go of the window and its GPU surface
+    fn shutdown(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        self.stop_emulator();
+        self.close_thumbnail();
+        self.linear_renderer = None;
+        self.rendered_pixels = None;
+        self.window = None;
+        event_loop.exit();
+    }
 
-        Self {
-            memory: Some(memory),
-            vram_mirror,
-            running,
-            inputs,
-            emulator_thread: None,
-            rendered_pixels: None,
-            window: None,
-        }
+    /// Stops the event loop from a handler, which can't return the error itself
+    fn fail(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, error: String) {
+        self.error = Some(error);
+        self.shutdown(event_loop);
     }
 }
 
 impl winit::application::ApplicationHandler for SpaceInvaders<'_> {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        self.running.store(true, Ordering::Relaxed);
+        self.run_state.running.store(true, Ordering::Relaxed);
 
         if self.window.is_none() {
-            let window_size = winit::dpi::LogicalSize::new(
-                SCREEN_WIDTH_PIXELS as f64,
-                SCREEN_HEIGHT_PIXELS as f64,
-            );
+            let rotation = self.options.rotation;
+            let (buffer_width, buffer_height) = match self.options.pixel_grid {
+                Some(_) => {
+                    PixelGrid::buffer_size(rotation.display_width(), rotation.display_height())
+                }
+                None => (rotation.display_width(), rotation.display_height()),
+            };
+            let buffer_size = (buffer_width as u32, buffer_height as u32);
+            let mut scale = self.options.scale;
+            if let Some(monitor) = event_loop.primary_monitor() {
+                let size = monitor.size().to_logical::<u32>(monitor.scale_factor());
+                let (fits, _, _) = integer_fit(buffer_size, (size.width, size.height));
+                if fits < scale {
+                    info!("Scaled {}x rather than {}x to fit the screen", fits, scale);
+                    scale = fits;
+                }
+            }
+            let window_size =
+                winit::dpi::LogicalSize::new(buffer_size.0 * scale, buffer_size.1 * scale);
             let mut window_attributes = winit::window::WindowAttributes::default();
             window_attributes.blur = false;
-            window_attributes.inner_size = Some(winit::dpi::Size::Logical(window_size));
+            window_attributes.inner_size = Some(winit::dpi::Size::Logical(window_size.cast()));
+            // resized a whole multiple at a time where the platform allows, as that's
+            // all nearest scaling shows anyway; elsewhere the frame is letterboxed
+            if self.options.filter == Filter::Nearest {
+                window_attributes.resize_increments = Some(winit::dpi::Size::Logical(
+                    winit::dpi::LogicalSize::new(buffer_size.0, buffer_size.1).cast(),
+                ));
+            }
             window_attributes.title = "Space Invaders".to_string();
+            window_attributes.decorations = !self.options.borderless;
+            if self.options.always_on_top {
+                // not every platform supports this, Wayland ignores it
+                window_attributes.window_level = WindowLevel::AlwaysOnTop;
+            }
+            if self.options.fullscreen {
+                window_attributes.fullscreen = Some(Fullscreen::Borderless(None));
+            }
 
-            let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
+            let window = match event_loop.create_window(window_attributes) {
+                Ok(window) => Arc::new(window),
+                Err(e) => {
+                    self.fail(event_loop, format!("Could not create a window: {}", e));
+                    return;
+                }
+            };
             self.window = Some(window.clone());
-            let surface_texture = SurfaceTexture::new(
-                window_size.width as u32,
-                window_size.height as u32,
-                window.clone(),
-            );
-            self.rendered_pixels = Some(
-                Pixels::new(
-                    DISPLAY_WIDTH_PIXELS as u32,
-                    DISPLAY_HEIGHT_PIXELS as u32,
-                    surface_texture,
-                )
-                .unwrap(),
-            );
+            self.deadline = self.options.run_for.map(|run_for| Instant::now() + run_for);
+            let mut machine = self.machine.take().unwrap();
+            if self.options.fullscreen {
+                if let Some(monitor) = window.current_monitor() {
+                    let size = monitor.size();
+                    let (scale, _, _) = integer_fit(
+                        (buffer_width as u32, buffer_height as u32),
+                        (size.width, size.height),
+                    );
+                    info!(
+                        "Fullscreen on a {}x{} monitor, scaled {}x",
+                        size.width, size.height, scale
+                    );
+                }
+            }
+            // the window may not be the size asked for, when fullscreen or tiled
+            let surface_size = window.inner_size();
+            match Presenter::new(
+                (buffer_width as u32, buffer_height as u32),
+                (surface_size.width, surface_size.height),
+                &window,
+                self.options.software_render,
+                self.integer_scaling(),
+            ) {
+                Ok(presenter) => {
+                    // the CPU path scales for itself
+                    if let (Filter::Linear, Presenter::Gpu(ref pixels)) =
+                        (self.options.filter, &presenter)
+                    {
+                        self.linear_renderer = Some(LinearRenderer::new(
+                            pixels,
+                            surface_size.width,
+                            surface_size.height,
+                            self.fullscreen,
+                        ));
+                    }
+                    self.rendered_pixels = Some(presenter);
+                }
+                Err(e) => {
+                    self.fail(event_loop, format!("Could not set up rendering: {}", e));
+                    return;
+                }
+            }
+
+            let png = match self.options.overlay {
+                Some(ref path) if path.as_os_str() != CLASSIC_OVERLAY => {
+                    match ColorOverlay::from_png(
+                        path,
+                        rotation.display_width(),
+                        rotation.display_height(),
+                    ) {
+                        Ok(overlay) => Some(overlay),
+                        Err(e) => {
+                            warn!(
+                                "Could not load overlay {}, staying monochrome: {}",
+                                path.display(),
+                                e
+                            );
+                            self.overlay_on = false;
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            };
+            self.overlay = Some(png.unwrap_or_else(|| ColorOverlay::classic(rotation)));
 
-            let inputs_emu = self.inputs.clone();
-            let running_emu = self.running.clone();
+            self.open_thumbnail(event_loop);
+
+            // show the pattern and leave the CPU switched off
+            if self.options.test_pattern {
+                draw_test_pattern(&mut machine.memory);
+                if let Ok(mut vram_mirror) = self.vram_mirror.lock() {
+                    machine.memory.render_frame(&mut vram_mirror);
+                }
+                window.request_redraw();
+                self.machine = Some(machine);
+                return;
+            }
+
+            let run_state_emu = self.run_state.clone();
+            let options = self.options.clone();
             let vram_mirror_emu = self.vram_mirror.clone();
             let window_emu = window.clone();
-            let mut memory = self.memory.take().unwrap();
-            self.emulator_thread = Some(std::thread::spawn(move || {
+            let recorders = self.recorders.take().unwrap();
+            let commands = self.commands_emu.take().unwrap();
+            let running = self.run_state.running.clone();
+            self.emulator_thread = Some(EmulatorThread::spawn(running, move || {
                 emulator_loop(
-                    &mut memory,
+                    machine,
                     vram_mirror_emu,
-                    running_emu,
-                    inputs_emu,
+                    run_state_emu,
                     window_emu,
+                    recorders,
+                    commands,
+                    options,
                 )
             }));
         }
     }
 
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            info!(
+                "Run time of {:?} is up, exiting",
+                self.options.run_for.unwrap_or_default()
+            );
+            self.deadline = None;
+            self.shutdown(event_loop);
+        }
+
+        self.update_mouse(|mouse| mouse.idle(Instant::now()));
+
+        let changes = match self.gamepads {
+            Some(ref mut gamepads) => gamepads.poll(),
+            None => Vec::new(),
+        };
+        for (button, pressed) in changes {
+            self.key_button(button, pressed);
+        }
+        if self.run_state.rumble.swap(false, Ordering::Relaxed) {
+            if let Some(ref mut gamepads) = self.gamepads {
+                gamepads.rumble(self.options.rumble_strength);
+            }
+        }
+    }
+
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
-        _window_id: winit::window::WindowId,
+        window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
+        let thumbnail_id = self.thumbnail_window.as_ref().map(|window| window.id());
         match event {
             WindowEvent::RedrawRequested => {
-                if let Some(ref mut rendered_pixels) = self.rendered_pixels {
-                    if let Ok(vram_mirror) = self.vram_mirror.lock() {
-                        rendered_pixels
-                            .frame_mut()
-                            .copy_from_slice(vram_mirror.as_slice());
-                        rendered_pixels.render().unwrap();
+                self.redraw();
+            }
+            // minimising resizes to nothing, which the surface can't be
+            WindowEvent::Resized(size)
+                if Some(window_id) != thumbnail_id && size.width > 0 && size.height > 0 =>
+            {
+                if let Some(ref mut presenter) = self.rendered_pixels {
+                    if let Err(e) = presenter.resize_surface(size.width, size.height) {
+                        error!("Could not resize the surface: {}", e);
                     }
                 }
+                if let Some(ref mut renderer) = self.linear_renderer {
+                    renderer.resize(size.width, size.height);
+                }
+            }
+            WindowEvent::CursorMoved { position, .. }
+                if self.mouse.is_some() && Some(window_id) != thumbnail_id =>
+            {
+                self.update_mouse(|mouse| mouse.moved(position.x, Instant::now()));
+            }
+            WindowEvent::CursorLeft { .. } => self.update_mouse(MouseAxis::leave),
+            // with mouse control the left button fires rather than dragging the window
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } if self.mouse.is_some() => {
+                self.set_button(Button::P1Fire, state == ElementState::Pressed);
             }
-            WindowEvent::CloseRequested => {
-                self.running
-                    .store(false, std::sync::atomic::Ordering::Relaxed);
-                if let Some(thread) = self.emulator_thread.take() {
-                    if let Err(e) = thread.join() {
-                        warn!("Error joining thread: {:?}", e);
+            // without a title bar the window is moved by dragging it anywhere
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if self.options.borderless => {
+                if let Some(ref window) = self.window {
+                    if let Err(e) = window.drag_window() {
+                        debug!("Could not drag window: {}", e);
                     }
                 }
-                event_loop.exit();
             }
+            // closing the thumbnail leaves the game running
+            WindowEvent::CloseRequested if thumbnail_id == Some(window_id) => {
+                self.close_thumbnail()
+            }
+            WindowEvent::CloseRequested => self.shutdown(event_loop),
             WindowEvent::KeyboardInput {
                 event:
                     KeyEvent {
                         logical_key: key,
                         state: ElementState::Pressed,
+                        repeat,
                         ..
                     },
                 ..
             } => {
                 debug!("{:?} key pressed", key);
-                match key.as_ref() {
-                    Key::Named(NamedKey::ArrowRight) => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
 
-                        val.set_p1_right(true);
+                // a freeze on game over waits for any key, which the game doesn't see
+                if self.options.freeze_on_game_over
+                    && self.run_state.paused.swap(false, Ordering::Relaxed)
+                {
+                    self.request_redraw();
+                    return;
+                }
 
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    Key::Named(NamedKey::ArrowLeft) => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
+                if self.dip_menu && self.dip_menu_key(key.as_ref(), repeat) {
+                    return;
+                }
 
-                        val.set_p1_left(true);
+                // bound keys go to the game ahead of any hotkey
+                if let Some(button) = self.bound_button(&key) {
+                    self.key_button(button, true);
+                    return;
+                }
 
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
+                match key.as_ref() {
+                    // insert a coin and start a one player game in one go
+                    Key::Named(NamedKey::Space) if self.options.quick_start && !repeat => {
+                        let _ = self.commands.send(Command::Input(InputPulse::credit()));
+                        let _ = self.commands.send(Command::Input(InputPulse::start_1p()));
                     }
-                    Key::Named(NamedKey::ArrowUp) => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_p1_shot(true);
-
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
+                    Key::Character("p") if !repeat => {
+                        let paused = !self.run_state.paused.fetch_xor(true, Ordering::Relaxed);
+                        info!("{}", if paused { "Paused" } else { "Resumed" });
+                        // the emulator thread stops redrawing while paused
+                        self.request_redraw();
                     }
-                    Key::Character("c") => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_credit(true);
-
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
+                    Key::Named(
+                        key @ (NamedKey::F1 | NamedKey::F2 | NamedKey::F3 | NamedKey::F4),
+                    ) if !repeat => {
+                        let category = match key {
+                            NamedKey::F1 => SoundCategory::Ufo,
+                            NamedKey::F2 => SoundCategory::Shots,
+                            NamedKey::F3 => SoundCategory::Fleet,
+                            _ => SoundCategory::Explosions,
+                        };
+                        let muted = !self.run_state.muted[category as usize]
+                            .fetch_xor(true, Ordering::Relaxed);
+                        info!(
+                            "{} {} sounds",
+                            if muted { "Muted" } else { "Unmuted" },
+                            category.name()
+                        );
                     }
-                    Key::Character("1") => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_start_1p(true);
-
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
+                    Key::Character("m") if !repeat => {
+                        let muted = !self
+                            .run_state
+                            .volume_muted
+                            .fetch_xor(true, Ordering::Relaxed);
+                        info!("{}", if muted { "Muted" } else { "Unmuted" });
                     }
-                    Key::Character("2") => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_start_2p(true);
-
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
+                    Key::Character(key @ ("+" | "=" | "-")) => {
+                        let volume = self.run_state.volume.load(Ordering::Relaxed);
+                        let volume = match key {
+                            "-" => volume.saturating_sub(VOLUME_STEP),
+                            _ => (volume + VOLUME_STEP).min(100),
+                        };
+                        self.run_state.volume.store(volume, Ordering::Relaxed);
+                        info!("Volume {}%", volume);
                     }
-                    Key::Character("w") => {
-                        let mut val = SpaceInvadersInput2::from_bytes([self
-                            .inputs
-                            .2
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_p2_shot(true);
-
-                        self.inputs.2.store(val.into_bytes()[0], Ordering::Relaxed);
+                    Key::Character("r") if !repeat => {
+                        let _ = self.commands.send(Command::PowerCycle);
                     }
-                    Key::Character("a") => {
-                        let mut val = SpaceInvadersInput2::from_bytes([self
-                            .inputs
-                            .2
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_p2_left(true);
-
-                        self.inputs.2.store(val.into_bytes()[0], Ordering::Relaxed);
+                    Key::Named(NamedKey::F5) if !repeat => {
+                        let _ = self.commands.send(Command::SaveRam);
                     }
-                    Key::Character("d") => {
-                        let mut val = SpaceInvadersInput2::from_bytes([self
-                            .inputs
-                            .2
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_p2_right(true);
-
-                        self.inputs.2.store(val.into_bytes()[0], Ordering::Relaxed);
+                    Key::Named(NamedKey::F9) if !repeat => {
+                        let _ = self.commands.send(Command::LoadRam);
+                    }
+                    Key::Named(NamedKey::F10) if !repeat => {
+                        self.dip_menu = true;
+                        self.request_redraw();
+                    }
+                    Key::Named(NamedKey::F7) if !repeat => {
+                        let show = !self.run_state.show_beam.fetch_xor(true, Ordering::Relaxed);
+                        info!(
+                            "{} the beam position",
+                            if show { "Showing" } else { "Hiding" }
+                        );
+                        self.request_redraw();
+                    }
+                    Key::Named(NamedKey::F8) if !repeat => {
+                        self.persistence_on = !self.persistence_on;
+                        self.persistence.clear();
+                        info!(
+                            "Phosphor persistence {}",
+                            if self.persistence_on { "on" } else { "off" }
+                        );
+                        self.request_redraw();
+                    }
+                    Key::Named(NamedKey::F11) if !repeat => self.toggle_fullscreen(),
+                    Key::Named(NamedKey::F12) if !repeat => {
+                        self.overlay_on = !self.overlay_on;
+                        info!(
+                            "Colour overlay {}",
+                            if self.overlay_on { "on" } else { "off" }
+                        );
+                        self.request_redraw();
+                    }
+                    Key::Named(NamedKey::F6) if !repeat => {
+                        let raw = !self.run_state.raw_vram.fetch_xor(true, Ordering::Relaxed);
+                        info!(
+                            "Showing {}",
+                            if raw {
+                                "raw VRAM, unrotated 256x224"
+                            } else {
+                                "the display"
+                            }
+                        );
                     }
                     _ => {}
                 }
@@ -720,148 +1853,781 @@ impl winit::application::ApplicationHandler for SpaceInvaders<'_> {
                 ..
             } => {
                 debug!("{:?} key released", key);
-                match key.as_ref() {
-                    Key::Named(NamedKey::ArrowRight) => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
-                        val.set_p1_right(false);
+                if let Some(button) = self.bound_button(&key) {
+                    self.key_button(button, false);
+                }
+            }
+            _ => {}
+        }
+    }
+}
 
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    Key::Named(NamedKey::ArrowLeft) => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
+#[derive(Clone)]
+struct Options {
+    rotation: RotationMode,
+    cabinet: CabinetType,
+    events: Option<PathBuf>,
+    audio_log: Option<PathBuf>,
+    timing_csv: Option<PathBuf>,
+    capture: Option<PathBuf>,
+    replay_capture: Option<PathBuf>,
+    /// a second ROM to run headless alongside the first and compare against
+    compare_rom: Option<PathBuf>,
+    /// how long to compare for, or the length of the replay
+    compare_frames: Option<u64>,
+    /// record a golden instruction trace of a trusted build, or check this one against it
+    write_trace: Option<PathBuf>,
+    verify_trace: Option<PathBuf>,
+    trace_frames: Option<u64>,
+    quick_start: bool,
+    self_test: bool,
+    disasm: bool,
+    info: bool,
+    rom: PathBuf,
+    /// accept a ROM shorter than 8K, filling the rest with zeros
+    pad_rom: bool,
+    /// reload the ROM and reset whenever the file changes
+    watch_rom: bool,
+    /// player 1's controls on port 0 too, which some bootlegs read instead of port 1
+    port0_controls: bool,
+    /// as set before the game boots, F10 changes them while it runs
+    dip_switches: DipSwitches,
+    key_bindings: KeyBindings,
+    freeze_on_game_over: bool,
+    start_paused: bool,
+    test_pattern: bool,
+    /// print where a few VRAM pixels land on the display
+    pixel_map: bool,
+    /// width and height of a scaled down copy of the game in a second window
+    thumbnail: Option<(usize, usize)>,
+    /// the window's size in multiples of the game's, less if that won't fit the screen
+    scale: u32,
+    borderless: bool,
+    always_on_top: bool,
+    /// borderless fullscreen on the current monitor, scaled by whole multiples; F11
+    /// toggles it
+    fullscreen: bool,
+    /// skip the GPU and draw on the CPU, as happens anyway if the GPU can't be set up
+    software_render: bool,
+    autofire: [Option<Autofire>; 2],
+    pixel_grid: Option<PixelGrid>,
+    /// phosphor persistence from the start, F8 toggles it
+    persistence: Option<Persistence>,
+    blend: Blend,
+    filter: Filter,
+    /// a PNG, or `classic` for the cabinet's red and green gel; F12 toggles it
+    overlay: Option<PathBuf>,
+    /// left out for plain white on black, so that costs nothing
+    palette: Option<Palette>,
+    run_for: Option<Duration>,
+    /// reset after this long idle, see [`space_invaders::kiosk`]
+    auto_restart: Option<Duration>,
+    restart_when: Idle,
+    /// runs unthrottled and silent until this frame
+    fast_forward_to: Option<u64>,
+    /// what to do about time lost when the host can't keep up
+    catchup: Catchup,
+    dump_on_exit: Option<PathBuf>,
+    dump_vram: bool,
+    /// the output buffer size, or the device's default
+    audio_latency: Option<Duration>,
+    /// a directory of WAVs to use in place of the default sounds, file by file
+    samples: Option<PathBuf>,
+    /// run without opening an audio device at all
+    no_audio: bool,
+    step_mode: StepMode,
+    /// where the mid-screen interrupt comes in the frame, or the default
+    mid_screen_fraction: Option<f64>,
+    debug_overlay: bool,
+    /// mark the line the emulated beam is on and where the mid-screen interrupt comes
+    show_beam: bool,
+    /// the current player's score and lives, large in a corner
+    accessible_hud: bool,
+    hud_corner: Corner,
+    /// how many times the size of the pause badge's text
+    hud_scale: usize,
+    /// sound categories muted from the start, F1 to F4 toggle them
+    muted: Vec<SoundCategory>,
+    /// out of 100
+    volume: u8,
+    /// how many `-v`s were given, see [`log_level`]
+    verbosity: usize,
+    /// applied to the horizontal axis of a gamepad stick
+    stick_x: AxisConfig,
+    /// rumble gamepads that can when a ship is lost
+    rumble: bool,
+    /// out of 100
+    rumble_strength: u8,
+    mouse_control: bool,
+    /// applied to sideways mouse movement with `mouse_control`
+    mouse_x: AxisConfig,
+}
 
-                        val.set_p1_left(false);
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            rotation: RotationMode::default(),
+            cabinet: CabinetType::default(),
+            events: None,
+            audio_log: None,
+            timing_csv: None,
+            capture: None,
+            replay_capture: None,
+            compare_rom: None,
+            rom: PathBuf::from(DEFAULT_ROM),
+            compare_frames: None,
+            write_trace: None,
+            verify_trace: None,
+            trace_frames: None,
+            quick_start: true,
+            self_test: false,
+            disasm: false,
+            info: false,
+            pad_rom: false,
+            watch_rom: false,
+            port0_controls: false,
+            dip_switches: DipSwitches::default(),
+            key_bindings: KeyBindings::default(),
+            freeze_on_game_over: false,
+            start_paused: false,
+            test_pattern: false,
+            pixel_map: false,
+            thumbnail: None,
+            scale: 1,
+            borderless: false,
+            always_on_top: false,
+            fullscreen: false,
+            software_render: false,
+            autofire: [None; 2],
+            pixel_grid: None,
+            persistence: None,
+            blend: Blend::default(),
+            filter: Filter::default(),
+            overlay: None,
+            palette: None,
+            run_for: None,
+            auto_restart: None,
+            restart_when: Idle::default(),
+            fast_forward_to: None,
+            catchup: Catchup::default(),
+            dump_on_exit: None,
+            dump_vram: false,
+            audio_latency: None,
+            samples: None,
+            no_audio: false,
+            muted: Vec::new(),
+            volume: 100,
+            step_mode: StepMode::default(),
+            mid_screen_fraction: None,
+            debug_overlay: false,
+            show_beam: false,
+            accessible_hud: false,
+            hud_corner: Corner::default(),
+            hud_scale: 2,
+            verbosity: 0,
+            stick_x: AxisConfig::default(),
+            rumble: false,
+            rumble_strength: DEFAULT_RUMBLE_STRENGTH,
+            mouse_control: false,
+            mouse_x: AxisConfig::default(),
+        }
+    }
+}
 
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
+impl Options {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut grid_intensity = None;
+        let mut persistence = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--rotate" => options.rotation = next_value(&mut args, &arg)?.parse()?,
+                "--cabinet" => options.cabinet = next_value(&mut args, &arg)?.parse()?,
+                "--events" => options.events = Some(next_value(&mut args, &arg)?.into()),
+                "--audio-log" => options.audio_log = Some(next_value(&mut args, &arg)?.into()),
+                "--timing-csv" => options.timing_csv = Some(next_value(&mut args, &arg)?.into()),
+                "--capture" => options.capture = Some(next_value(&mut args, &arg)?.into()),
+                "--replay-capture" => {
+                    options.replay_capture = Some(next_value(&mut args, &arg)?.into())
+                }
+                "--rom" => options.rom = next_value(&mut args, &arg)?.into(),
+                "--compare-rom" => options.compare_rom = Some(next_value(&mut args, &arg)?.into()),
+                "--compare-frames" => {
+                    let frames = next_value(&mut args, &arg)?;
+                    let frames = frames
+                        .parse()
+                        .ok()
+                        .filter(|&frames| frames > 0)
+                        .ok_or_else(|| format!("Invalid frame count '{}'", frames))?;
+                    options.compare_frames = Some(frames);
+                }
+                "--write-trace" => options.write_trace = Some(next_value(&mut args, &arg)?.into()),
+                "--verify-trace" => {
+                    options.verify_trace = Some(next_value(&mut args, &arg)?.into())
+                }
+                "--trace-frames" => {
+                    let frames = next_value(&mut args, &arg)?;
+                    let frames = frames
+                        .parse()
+                        .ok()
+                        .filter(|&frames| frames > 0)
+                        .ok_or_else(|| format!("Invalid frame count '{}'", frames))?;
+                    options.trace_frames = Some(frames);
+                }
+                "--no-quick-start" => options.quick_start = false,
+                "--pixel-grid" => {
+                    let intensity = next_value(&mut args, &arg)?;
+                    let intensity = intensity
+                        .parse()
+                        .map_err(|_| format!("Invalid pixel grid intensity '{}'", intensity))?;
+                    grid_intensity = Some(intensity);
+                }
+                "--persistence" => {
+                    let amount = next_value(&mut args, &arg)?;
+                    let amount = amount
+                        .parse()
+                        .map_err(|_| format!("Invalid persistence '{}'", amount))?;
+                    persistence = Some(amount);
+                }
+                "--linear-light" => options.blend = Blend::Linear,
+                "--filter" => options.filter = next_value(&mut args, &arg)?.parse()?,
+                "--phosphor" => {
+                    let palette: Palette = next_value(&mut args, &arg)?.parse()?;
+                    options.palette = (palette != Palette::WHITE).then_some(palette);
+                }
+                "--overlay" => options.overlay = Some(next_value(&mut args, &arg)?.into()),
+                "--autofire" | "--autofire-p1" | "--autofire-p2" => {
+                    let hz = next_value(&mut args, &arg)?;
+                    let hz = hz
+                        .parse()
+                        .map_err(|_| format!("Invalid autofire rate '{}'", hz))?;
+                    let autofire = Some(Autofire::from_hz(hz)?);
+                    match arg.as_str() {
+                        "--autofire-p1" => options.autofire[0] = autofire,
+                        "--autofire-p2" => options.autofire[1] = autofire,
+                        _ => options.autofire = [autofire; 2],
                     }
-                    Key::Named(NamedKey::ArrowUp) => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_p1_shot(false);
-
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
+                }
+                "--run-for" => {
+                    let seconds = next_value(&mut args, &arg)?;
+                    let run_for = seconds
+                        .parse()
+                        .ok()
+                        .filter(|&seconds: &f64| seconds > 0.0)
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| format!("Invalid run time '{}'", seconds))?;
+                    options.run_for = Some(run_for);
+                }
+                "--auto-restart" => {
+                    let seconds = next_value(&mut args, &arg)?;
+                    let timeout = seconds
+                        .parse()
+                        .ok()
+                        .filter(|&seconds: &f64| seconds > 0.0)
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or_else(|| format!("Invalid auto-restart time '{}'", seconds))?;
+                    options.auto_restart = Some(timeout);
+                }
+                "--restart-when" => {
+                    options.restart_when = next_value(&mut args, &arg)?.parse()?;
+                }
+                "--fast-forward-to" => {
+                    let frame = next_value(&mut args, &arg)?;
+                    let frame = frame
+                        .parse()
+                        .map_err(|_| format!("Invalid frame '{}'", frame))?;
+                    options.fast_forward_to = Some(frame);
+                }
+                "--dump-on-exit" => {
+                    options.dump_on_exit = Some(next_value(&mut args, &arg)?.into())
+                }
+                "--dump-vram" => options.dump_vram = true,
+                "--audio-latency" => {
+                    let ms = next_value(&mut args, &arg)?;
+                    let ms: u64 = ms
+                        .parse()
+                        .ok()
+                        .filter(|&ms| ms > 0)
+                        .ok_or_else(|| format!("Invalid audio latency '{}'", ms))?;
+                    options.audio_latency = Some(Duration::from_millis(ms));
+                }
+                "--samples" => options.samples = Some(next_value(&mut args, &arg)?.into()),
+                "--no-audio" => options.no_audio = true,
+                "--mute" => {
+                    for category in next_value(&mut args, &arg)?.split(',') {
+                        options.muted.push(category.parse()?);
                     }
-                    Key::Character("c") => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
-
-                        val.set_credit(false);
+                }
+                "--volume" => {
+                    let volume = next_value(&mut args, &arg)?;
+                    options.volume = volume
+                        .parse()
+                        .ok()
+                        .filter(|volume| *volume <= 100)
+                        .ok_or_else(|| {
+                            format!("Invalid volume '{}' (expected 0 to 100)", volume)
+                        })?;
+                }
+                "--catchup" => options.catchup = next_value(&mut args, &arg)?.parse()?,
+                "--step-on-input" => options.step_mode = StepMode::OnInput,
+                "--mid-screen-at" => {
+                    let fraction = next_value(&mut args, &arg)?;
+                    let fraction = fraction
+                        .parse()
+                        .map_err(|_| format!("Invalid frame fraction '{}'", fraction))?;
+                    options.mid_screen_fraction = Some(fraction);
+                }
+                "--debug-overlay" => options.debug_overlay = true,
+                "--show-beam" => options.show_beam = true,
+                "--accessible-hud" => options.accessible_hud = true,
+                "--hud-corner" => options.hud_corner = next_value(&mut args, &arg)?.parse()?,
+                "--scale" => {
+                    let scale = next_value(&mut args, &arg)?;
+                    options.scale = scale
+                        .parse()
+                        .ok()
+                        .filter(|scale| (1..=MAX_WINDOW_SCALE).contains(scale))
+                        .ok_or_else(|| {
+                            format!(
+                                "Invalid scale '{}' (expected 1 to {})",
+                                scale, MAX_WINDOW_SCALE
+                            )
+                        })?;
+                }
+                "--hud-scale" => {
+                    let scale = next_value(&mut args, &arg)?;
+                    options.hud_scale = scale
+                        .parse()
+                        .ok()
+                        .filter(|scale| (1..=4).contains(scale))
+                        .ok_or_else(|| {
+                            format!("Invalid HUD scale '{}' (expected 1 to 4)", scale)
+                        })?;
+                }
+                "--bind" => options.key_bindings.bind(&next_value(&mut args, &arg)?)?,
+                "--lives" => {
+                    let lives = next_value(&mut args, &arg)?;
+                    options.dip_switches.lives = lives
+                        .parse()
+                        .ok()
+                        .filter(|lives| (3..=6).contains(lives))
+                        .ok_or_else(|| format!("Invalid lives '{}' (expected 3 to 6)", lives))?;
+                }
+                "--selftest" => options.self_test = true,
+                "--disasm" => options.disasm = true,
+                "--info" => options.info = true,
+                "--pad-rom" => options.pad_rom = true,
+                "--watch-rom" => options.watch_rom = true,
+                "--port0-controls" => options.port0_controls = true,
+                "--freeze-on-game-over" => options.freeze_on_game_over = true,
+                "--start-paused" => options.start_paused = true,
+                "--test-pattern" => options.test_pattern = true,
+                "--pixel-map" => options.pixel_map = true,
+                "--thumbnail" => {
+                    let size = next_value(&mut args, &arg)?;
+                    options.thumbnail = Some(parse_size(&size).ok_or_else(|| {
+                        format!(
+                            "Invalid thumbnail size '{}' (expected WxH, like 56x64)",
+                            size
+                        )
+                    })?);
+                }
+                "--borderless" => options.borderless = true,
+                "--always-on-top" => options.always_on_top = true,
+                "--fullscreen" => options.fullscreen = true,
+                "--software-render" => options.software_render = true,
+                "--deadzone" | "--sensitivity" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let value: f32 = value
+                        .parse()
+                        .map_err(|_| format!("Invalid value '{}' for '{}'", value, arg))?;
+                    let stick = options.stick_x;
+                    options.stick_x = match arg.as_str() {
+                        "--deadzone" => AxisConfig::new(value, stick.sensitivity, stick.inverted)?,
+                        _ => AxisConfig::new(stick.deadzone, value, stick.inverted)?,
+                    };
+                }
+                "--invert-x" => options.stick_x.inverted = true,
+                "--rumble" => options.rumble = true,
+                "--rumble-strength" => {
+                    let strength = next_value(&mut args, &arg)?;
+                    options.rumble_strength = strength
+                        .parse()
+                        .ok()
+                        .filter(|strength| (1..=100).contains(strength))
+                        .ok_or_else(|| {
+                            format!("Invalid rumble strength '{}' (expected 1 to 100)", strength)
+                        })?;
+                }
+                "--mouse-control" => options.mouse_control = true,
+                "--mouse-deadzone" | "--mouse-sensitivity" => {
+                    let value = next_value(&mut args, &arg)?;
+                    let value: f32 = value
+                        .parse()
+                        .map_err(|_| format!("Invalid value '{}' for '{}'", value, arg))?;
+                    let mouse = options.mouse_x;
+                    options.mouse_x = match arg.as_str() {
+                        "--mouse-deadzone" => AxisConfig::new(value, mouse.sensitivity, false)?,
+                        _ => AxisConfig::new(mouse.deadzone, value, false)?,
+                    };
+                }
+                // already read by config_args
+                "--config" => {
+                    next_value(&mut args, &arg)?;
+                }
+                // -v, -vv and so on, each v asking for more
+                flag if flag.len() > 1
+                    && flag.starts_with('-')
+                    && flag[1..].bytes().all(|c| c == b'v') =>
+                {
+                    options.verbosity += flag.len() - 1
+                }
+                // the one argument without a flag, as in `space-invaders invaders.bin`
+                path if !path.starts_with('-') => options.rom = path.into(),
+                _ => return Err(format!("Unknown argument '{}'", arg)),
+            }
+        }
 
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    Key::Character("1") => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
+        // built last so the blend applies wherever it appears on the command line
+        options.pixel_grid = grid_intensity
+            .map(|intensity| PixelGrid::new(intensity, options.blend))
+            .transpose()?;
+        options.persistence = persistence
+            .map(|amount| Persistence::new(amount, options.blend))
+            .transpose()?;
 
-                        val.set_start_1p(false);
+        Ok(options)
+    }
+}
 
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    Key::Character("2") => {
-                        let mut val = SpaceInvadersInput1::from_bytes([self
-                            .inputs
-                            .1
-                            .load(Ordering::Relaxed)]);
+/// The config file given with `--config`, or the one in [`Config::default_path`] if it
+/// exists
+fn config_file(args: &[String]) -> Result<Config, String> {
+    let path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) => args
+            .get(i + 1)
+            .map(PathBuf::from)
+            .ok_or_else(|| "Missing value for '--config'".to_string())?,
+        None => match Config::default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
 
-                        val.set_start_2p(false);
+    let config = Config::load(&path)?;
+    // checked on its own first so a bad value is blamed on the file
+    Options::from_args(config.to_args())
+        .map_err(|e| format!("In config {}: {}", path.display(), e))?;
+    Ok(config)
+}
 
-                        self.inputs.1.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    Key::Character("w") => {
-                        let mut val = SpaceInvadersInput2::from_bytes([self
-                            .inputs
-                            .2
-                            .load(Ordering::Relaxed)]);
+/// The flags from the config file with the `SPACE_INVADERS_*` environment variables
+/// merged over it, see [`Config::from_env`]
+fn config_args(args: &[String]) -> Result<Vec<String>, String> {
+    let file = config_file(args)?;
+    let env = Config::from_env(std::env::vars())?;
+    // checked on their own like the config file, the variables are already blamed
+    // for values of the wrong type
+    Options::from_args(env.to_args())
+        .map_err(|e| format!("In {}* environment variables: {}", ENV_PREFIX, e))?;
+    // merged before they're flags so the environment can turn off a switch the file
+    // turned on
+    Ok(file.merge(&env)?.to_args())
+}
 
-                        val.set_p2_shot(false);
+/// A size given as `WxH`, both above zero
+fn parse_size(size: &str) -> Option<(usize, usize)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    (width > 0 && height > 0).then_some((width, height))
+}
 
-                        self.inputs.2.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    Key::Character("a") => {
-                        let mut val = SpaceInvadersInput2::from_bytes([self
-                            .inputs
-                            .2
-                            .load(Ordering::Relaxed)]);
+fn next_value(args: &mut impl Iterator<Item = String>, flag: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Missing value for '{}'", flag))
+}
 
-                        val.set_p2_left(false);
+/// Maps the number of `-v`s to the most detailed messages logged:
+///
+/// | flag    | level |
+/// |---------|-------|
+/// |         | error |
+/// | `-v`    | warn  |
+/// | `-vv`   | info  |
+/// | `-vvv`  | debug |
+/// | `-vvvv` | trace |
+fn log_level(verbosity: usize) -> LevelFilter {
+    match verbosity {
+        0 => LevelFilter::Error,
+        1 => LevelFilter::Warn,
+        2 => LevelFilter::Info,
+        3 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
 
-                        self.inputs.2.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    Key::Character("d") => {
-                        let mut val = SpaceInvadersInput2::from_bytes([self
-                            .inputs
-                            .2
-                            .load(Ordering::Relaxed)]);
+/// Where the ROM is read from without `--rom` or a path, relative to the working
+/// directory
+const DEFAULT_ROM: &str = "src/assets/invaders.bin";
 
-                        val.set_p2_right(false);
+const NO_WINDOW_HINT: &str =
+    "Without a display only --info, --disasm, --selftest, --compare-rom, --write-trace and --verify-trace can be used, they don't open a window.";
 
-                        self.inputs.2.store(val.into_bytes()[0], Ordering::Relaxed);
-                    }
-                    _ => {}
-                }
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    // the command line goes last so it overrides the environment, which overrides the
+    // config file
+    let options = Options::from_args(config_args(&args)?.into_iter().chain(args))?;
+
+    // RUST_LOG is applied on top, so it still has the last word
+    let logger = env_logger::Builder::new()
+        .filter_level(log_level(options.verbosity))
+        .parse_default_env()
+        .build();
+    let level = logger.filter();
+    let log_tail = LogTail::new(Box::new(logger));
+    let log_lines = log_tail.lines();
+    log_tail.init(level)?;
+    // as early as it can go with the log to draw on, and before --dump-on-exit's hook
+    // so the dump is written first
+    let crash_reporter = CrashReporter::install(log_lines);
+
+    let rom_path = options.rom.clone();
+    // a directory holds the four chips' files, and so can the default ROM's when
+    // the single file isn't there
+    let parts_dir = if rom_path.is_dir() {
+        Some(rom_path.as_path())
+    } else if rom_path == Path::new(DEFAULT_ROM) && !rom_path.exists() {
+        rom_path.parent()
+    } else {
+        None
+    };
+    let rom_parts = parts_dir.and_then(rom_parts_in);
+    let rom_data = match rom_parts {
+        Some(ref parts) => load_rom_parts(parts.each_ref().map(PathBuf::as_path)).map(Vec::from),
+        None => std::fs::read(&rom_path)
+            .map_err(|e| format!("Could not read ROM {}: {}", rom_path.display(), e)),
+    };
+    // the likeliest thing to go wrong, so it gets a plain message of its own
+    let rom_data = match rom_data {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("{}", e);
+            if rom_path == Path::new(DEFAULT_ROM) {
+                eprintln!(
+                    "Give the path to the ROM, or to a directory of invaders.h, .g, .f and .e, as in: space-invaders /path/to/invaders.bin"
+                );
             }
-            _ => {}
+            std::process::exit(1);
         }
+    };
+    if let (Some(_), Some(dir)) = (&rom_parts, parts_dir) {
+        info!(
+            "Loaded the ROM from invaders.h to invaders.e in {}",
+            dir.display()
+        );
     }
-}
 
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+    if options.info {
+        // the whole file, rom_image would hide a dump of the wrong size
+        let info = RomInfo::new(&rom_data);
+        print!("{}", info);
+        if !info.size_ok() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
-    let rom = match load_rom(Path::new("src/assets/invaders.bin")) {
+    let rom = match rom_image(&rom_data, options.pad_rom) {
         Ok(rom) => rom,
-        Err(e) => return Err(Box::new(e)),
+        Err(e) => {
+            eprintln!("{}: {}", rom_path.display(), e);
+            std::process::exit(1);
+        }
     };
+    crash_reporter.set_rom(&rom);
+
+    if options.disasm {
+        for (addr, len, mnemonic) in disassemble(&rom, 0x0000) {
+            let bytes: Vec<String> = rom[addr as usize..addr as usize + len]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            println!("{:04X}  {:<8}  {}", addr, bytes.join(" "), mnemonic);
+        }
+        return Ok(());
+    }
+
+    if options.self_test {
+        match run_self_test(rom) {
+            Ok(frame) => {
+                println!("Self-test passed: attract mode reached at frame {}", frame);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Self-test failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 
-    let memory = SpaceInvadersMemory::new(rom);
+    if let Some(ref path) = options.compare_rom {
+        let other = load_rom(path, options.pad_rom)?;
+        // the inputs only, the capture's checksum is for one of the two ROMs at most
+        let inputs = match options.replay_capture {
+            Some(ref path) => {
+                Capture::load(path)
+                    .map_err(|e| format!("Could not load capture {}: {}", path.display(), e))?
+                    .inputs
+            }
+            None => Vec::new(),
+        };
+        let frames = options.compare_frames.unwrap_or(if inputs.is_empty() {
+            COMPARE_FRAMES
+        } else {
+            inputs.len() as u64
+        });
 
-    let mut space_invaders = SpaceInvaders::new(memory);
+        match compare_roms(rom, other, &inputs, frames) {
+            Some(divergence) => {
+                print!("{}", divergence);
+                std::process::exit(1);
+            }
+            None => {
+                println!("No divergence in {} frames", frames);
+                return Ok(());
+            }
+        }
+    }
 
-    let event_loop = EventLoop::builder().with_wayland().build()?;
-    event_loop.set_control_flow(ControlFlow::Poll);
-    event_loop.run_app(&mut space_invaders)?;
-    Ok(())
-}
+    if let Some(ref path) = options.write_trace {
+        let frames = options.trace_frames.unwrap_or(TRACE_FRAMES);
+        let mut out = BufWriter::new(File::create(path)?);
+        write_trace(rom, frames, &mut out)?;
+        out.flush()?;
+        println!("Traced {} frames to {}", frames, path.display());
+        return Ok(());
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::ShiftRegister;
+    if let Some(ref path) = options.verify_trace {
+        let mut trace = BufReader::new(File::open(path)?);
+        match verify_trace(rom, &mut trace)
+            .map_err(|e| format!("Could not check trace {}: {}", path.display(), e))?
+        {
+            Some(divergence) => {
+                print!("{}", divergence);
+                std::process::exit(1);
+            }
+            None => {
+                println!("Matched every instruction of {}", path.display());
+                return Ok(());
+            }
+        }
+    }
 
-    #[test]
-    fn test_shift_register() {
-        let mut sr = ShiftRegister::new();
-        assert_eq!(sr.amount, 0);
-        assert_eq!(sr.register, 0);
+    // carries on afterwards, so --test-pattern shows what the table describes
+    if options.pixel_map {
+        print!("{}", pixel_map(options.rotation));
+    }
 
-        sr.input_data(0xAA);
-        assert_eq!(sr.register, 0xAA00);
+    // a missing file only costs that one sound, a missing directory is likely a typo
+    if let Some(ref dir) = options.samples {
+        if !dir.is_dir() {
+            return Err(format!("Sample directory {} doesn't exist", dir.display()).into());
+        }
+    }
 
-        sr.input_data(0xFF); // 0b11111111
-        assert_eq!(sr.register, 0xFFAA);
+    let memory = SpaceInvadersMemory::new(rom, options.rotation);
+    let inputs = new_inputs();
+    options.dip_switches.write(&inputs);
+    let mut machine = Machine::new(memory, inputs, options.cabinet);
+    machine.autofire = options.autofire;
+    machine.set_step_mode(options.step_mode);
+    if let Some(fraction) = options.mid_screen_fraction {
+        machine.set_mid_screen_fraction(fraction)?;
+    }
 
-        sr.input_data(0x12); // 0b00010010
-        assert_eq!(sr.register, 0x12FF);
+    let mut recorders = Recorders {
+        crash_reporter: Some(crash_reporter),
+        ..Default::default()
+    };
+    if let Some(timeout) = options.auto_restart {
+        recorders.auto_restart = Some(AutoRestart::new(
+            timeout,
+            options.restart_when,
+            Instant::now(),
+        ));
+    }
+    if options.watch_rom && rom_parts.is_some() {
+        warn!("--watch-rom only watches a ROM in a single file");
+    } else if options.watch_rom {
+        recorders.rom_watcher = Some((FileWatcher::new(rom_path.clone()), options.pad_rom));
+        info!("Watching {} for changes", rom_path.display());
+    }
+    // replay first so a capture of a replay starts from the replayed inputs
+    if let Some(ref path) = options.replay_capture {
+        let capture = Capture::load(path)
+            .map_err(|e| format!("Could not load capture {}: {}", path.display(), e))?;
+        if capture.rom_crc32 != crc32(&rom) {
+            return Err(format!(
+                "Capture {} was made with a different ROM (CRC-32 {:08x}, loaded ROM is {:08x})",
+                path.display(),
+                capture.rom_crc32,
+                crc32(&rom)
+            )
+            .into());
+        }
+        recorders.capture_replay = Some(CaptureReplay::start(capture, &mut machine));
+    }
+    if let Some(ref path) = options.capture {
+        recorders.capture = Some(CaptureRecorder::start(path.clone(), &rom, &mut machine));
+    }
+    if let Some(ref path) = options.events {
+        recorders.event_writer = Some(EventWriter::create(path)?);
+    }
+    if let Some(ref path) = options.dump_on_exit {
+        recorders.memory_dump = Some(MemoryDump::new(path.clone(), options.dump_vram));
+    }
+    if let Some(ref path) = options.audio_log {
+        recorders.audio_log = Some(AudioLog::new(BufWriter::new(File::create(path)?)));
+    }
+    if let Some(ref path) = options.timing_csv {
+        recorders.timing_log = Some(TimingLog::new(BufWriter::new(File::create(path)?))?);
+    }
 
-        sr.input_amount(0);
-        assert_eq!(sr.output(), 0x12);
+    let mut space_invaders = SpaceInvaders::new(machine, options, recorders);
 
-        sr.input_amount(2);
-        assert_eq!(sr.output(), 0b01001011);
+    // winit picks the platform's backend, on Linux Wayland when there's a Wayland
+    // session and X11 otherwise
+    let event_loop = match EventLoop::new() {
+        Ok(event_loop) => event_loop,
+        Err(e) => {
+            eprintln!(
+                "Could not connect to the display: {}\n{}",
+                e, NO_WINDOW_HINT
+            );
+            std::process::exit(1);
+        }
+    };
+    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop.run_app(&mut space_invaders)?;
 
-        sr.input_amount(7);
-        assert_eq!(sr.output(), 0b01111111);
+    if let Some(e) = space_invaders.error.take() {
+        eprintln!("{}\n{}", e, NO_WINDOW_HINT);
+        std::process::exit(1);
+    }
+    if let Some(machine) = space_invaders.into_machine() {
+        info!(
+            "Stopped at frame {}, {:?} of emulated time",
+            machine.frame(),
+            Duration::from_nanos(machine.emu_clock())
+        );
+        info!(
+            "Ports read: {}; written: {}",
+            machine.ports_read(),
+            machine.ports_written()
+        );
     }
+    Ok(())
 }
@@ -0,0 +1,877 @@
+use std::cell::Cell;
+use std::fmt;
+use std::io::Write;
+
+use emu8080::MemoryAccess;
+
+use crate::addr;
+use crate::{
+    ADDRESS_MASK, RAM_END, RAM_MIRROR_START, RAM_SIZE, RAM_START, ROM_END, ROM_SIZE, ROM_START,
+    SCREEN_HEIGHT_PIXELS, SCREEN_SIZE_PIXELS, SCREEN_WIDTH_PIXELS, VRAM_END, VRAM_SIZE, VRAM_START,
+};
+
+/// Orientation used when expanding guest VRAM into the display buffer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RotationMode {
+    /// the game's own framebuffer layout, 256 pixels wide and 224 tall
+    None,
+    /// rotated 90 degrees counter-clockwise, as mounted in the upright cabinet
+    #[default]
+    CounterClockwise,
+    /// rotated 90 degrees clockwise
+    Clockwise,
+}
+
+impl RotationMode {
+    pub fn display_width(self) -> usize {
+        match self {
+            RotationMode::None => SCREEN_WIDTH_PIXELS,
+            RotationMode::CounterClockwise | RotationMode::Clockwise => SCREEN_HEIGHT_PIXELS,
+        }
+    }
+
+    pub fn display_height(self) -> usize {
+        match self {
+            RotationMode::None => SCREEN_HEIGHT_PIXELS,
+            RotationMode::CounterClockwise | RotationMode::Clockwise => SCREEN_WIDTH_PIXELS,
+        }
+    }
+
+    /// Maps a guest pixel address (VRAM byte address * 8 + bit) to its index in the display,
+    /// optionally turned a further 180 degrees
+    pub fn display_pixel_index(self, pixel_address: usize, flipped: bool) -> usize {
+        // the game draws 224 scanlines of 256 pixels each, bit 0 of each byte first
+        let screen_row = pixel_address / SCREEN_WIDTH_PIXELS;
+        let screen_col = pixel_address % SCREEN_WIDTH_PIXELS;
+
+        let (display_row, display_col) = match self {
+            RotationMode::None => (screen_row, screen_col),
+            RotationMode::CounterClockwise => (SCREEN_WIDTH_PIXELS - 1 - screen_col, screen_row),
+            RotationMode::Clockwise => (screen_col, SCREEN_HEIGHT_PIXELS - 1 - screen_row),
+        };
+
+        let index = (display_row * self.display_width()) + display_col;
+
+        if flipped {
+            SCREEN_SIZE_PIXELS - 1 - index
+        } else {
+            index
+        }
+    }
+}
+
+impl std::str::FromStr for RotationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(RotationMode::None),
+            "ccw" => Ok(RotationMode::CounterClockwise),
+            "cw" => Ok(RotationMode::Clockwise),
+            _ => Err(format!(
+                "Unknown rotation mode '{}' (expected none, ccw or cw)",
+                s
+            )),
+        }
+    }
+}
+
+pub struct SpaceInvadersMemory {
+    rom: [u8; ROM_SIZE],
+    ram: [u8; RAM_SIZE],
+    /// one bit per pixel as the game sees it, only expanded for the display by
+    /// [`SpaceInvadersMemory::render_frame`]
+    vram: [u8; VRAM_SIZE],
+    rotation: RotationMode,
+    flipped: bool,
+    /// only added to with the `access-counters` feature
+    access: Cell<AccessCounts>,
+}
+
+/// Identifies a [`SpaceInvadersMemory::write_dump`]
+pub const DUMP_MAGIC: &[u8; 6] = b"SIDUMP";
+const DUMP_VERSION: u16 = 1;
+
+/// What answers at a CPU address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// read only, writes are ignored
+    Rom,
+    Ram,
+    Vram,
+    /// reads as 0, writes are ignored
+    Unmapped,
+}
+
+impl Region {
+    /// The addresses the region occupies before mirroring. [`Region::Unmapped`] is the
+    /// gap at 0x4000-0x5FFF, each of its repeats at 0x8000 and up is unmapped too.
+    pub fn range(self) -> std::ops::Range<usize> {
+        match self {
+            Region::Rom => ROM_START..ROM_END,
+            Region::Ram => RAM_START..RAM_END,
+            Region::Vram => VRAM_START..VRAM_END,
+            Region::Unmapped => VRAM_END..RAM_MIRROR_START,
+        }
+    }
+
+    pub fn writable(self) -> bool {
+        matches!(self, Region::Ram | Region::Vram)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::Rom => "ROM",
+            Region::Ram => "RAM",
+            Region::Vram => "VRAM",
+            Region::Unmapped => "unmapped",
+        }
+    }
+}
+
+const REGIONS: [Region; 4] = [Region::Rom, Region::Ram, Region::Vram, Region::Unmapped];
+
+/// Bytes read from and written to each region, indexed by [`Region`]. Only counted
+/// with the `access-counters` feature, see [`SpaceInvadersMemory::take_access_counts`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessCounts {
+    pub reads: [u64; REGIONS.len()],
+    pub writes: [u64; REGIONS.len()],
+}
+
+/// The fewest, average and most accesses to each region in a frame, over every frame
+/// added
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccessStats {
+    pub frames: u64,
+    pub min: AccessCounts,
+    pub max: AccessCounts,
+    pub total: AccessCounts,
+}
+
+impl AccessStats {
+    pub fn add_frame(&mut self, counts: AccessCounts) {
+        let first = self.frames == 0;
+        self.frames += 1;
+
+        let pairs = [
+            (
+                &counts.reads,
+                &mut self.min.reads,
+                &mut self.max.reads,
+                &mut self.total.reads,
+            ),
+            (
+                &counts.writes,
+                &mut self.min.writes,
+                &mut self.max.writes,
+                &mut self.total.writes,
+            ),
+        ];
+        for (counts, min, max, total) in pairs {
+            for i in 0..REGIONS.len() {
+                min[i] = if first {
+                    counts[i]
+                } else {
+                    min[i].min(counts[i])
+                };
+                max[i] = max[i].max(counts[i]);
+                total[i] += counts[i];
+            }
+        }
+    }
+}
+
+/// Each region that was accessed, as `VRAM r 0/12/30 w 100/250/900` for the min,
+/// average and max reads and writes per frame
+impl fmt::Display for AccessStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frames = self.frames.max(1);
+        let mut first = true;
+        for (i, region) in REGIONS.into_iter().enumerate() {
+            if self.max.reads[i] == 0 && self.max.writes[i] == 0 {
+                continue;
+            }
+            if !first {
+                write!(f, ", ")?;
+            }
+            first = false;
+
+            write!(
+                f,
+                "{} r {}/{}/{} w {}/{}/{}",
+                region.name(),
+                self.min.reads[i],
+                self.total.reads[i] / frames,
+                self.max.reads[i],
+                self.min.writes[i],
+                self.total.writes[i] / frames,
+                self.max.writes[i]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps a CPU address to the ROM, RAM or VRAM behind it, or `None` if nothing answers.
+///
+/// Only 15 address lines are decoded, so 0x8000-0xFFFF repeats 0x0000-0x7FFF. Within
+/// that, 0x4000-0x5FFF is ROM space the invaders board leaves empty and 0x6000-0x7FFF
+/// mirrors RAM and VRAM at 0x2000-0x3FFF.
+fn map_address(addr: u16) -> Option<usize> {
+    let addr = addr as usize & ADDRESS_MASK;
+
+    if addr < VRAM_END {
+        Some(addr)
+    } else if addr >= RAM_MIRROR_START {
+        Some(addr - RAM_MIRROR_START + RAM_START)
+    } else {
+        None
+    }
+}
+
+/// The region behind an address and the offset into that region's storage
+fn dispatch(addr: u16) -> (Region, usize) {
+    match map_address(addr) {
+        Some(addr) if addr < ROM_END => (Region::Rom, addr - ROM_START),
+        Some(addr) if addr < RAM_END => (Region::Ram, addr - RAM_START),
+        Some(addr) => (Region::Vram, addr - VRAM_START),
+        None => (Region::Unmapped, 0),
+    }
+}
+
+impl MemoryAccess for SpaceInvadersMemory {
+    fn read_byte(&self, addr: u16) -> u8 {
+        let (region, offset) = dispatch(addr);
+        self.count_access(region, false, 1);
+        match (region, offset) {
+            (Region::Rom, offset) => self.rom[offset],
+            (Region::Ram, offset) => self.ram[offset],
+            (Region::Vram, offset) => self.read_vram(offset),
+            (Region::Unmapped, _) => 0,
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, val: u8) {
+        let (region, offset) = dispatch(addr);
+        self.count_access(region, true, 1);
+        match (region, offset) {
+            (Region::Ram, offset) => self.ram[offset] = val,
+            (Region::Vram, offset) => self.write_vram(offset, val),
+            (Region::Rom | Region::Unmapped, _) => {}
+        }
+    }
+
+    // an access that runs off the end of a region, such as a word straddling ROM and
+    // RAM, is put together a byte at a time so each byte goes where it is mapped
+    fn read_bytes<const C: usize>(&self, addr: u16) -> [u8; C] {
+        let region = match dispatch(addr) {
+            (Region::Rom, offset) => self.rom.get(offset..offset + C),
+            (Region::Ram, offset) => self.ram.get(offset..offset + C),
+            (Region::Vram, offset) => self.vram.get(offset..offset + C),
+            (Region::Unmapped, _) => None,
+        };
+
+        match region.and_then(|bytes| bytes.try_into().ok()) {
+            Some(bytes) => {
+                self.count_access(dispatch(addr).0, false, C as u64);
+                bytes
+            }
+            None => std::array::from_fn(|i| self.read_byte(addr.wrapping_add(i as u16))),
+        }
+    }
+
+    fn write_bytes(&mut self, addr: u16, val: &[u8]) {
+        let (region, offset) = dispatch(addr);
+        let bytes = match region {
+            Region::Ram => self.ram.get_mut(offset..offset + val.len()),
+            Region::Vram => self.vram.get_mut(offset..offset + val.len()),
+            Region::Rom | Region::Unmapped => None,
+        };
+        if let Some(bytes) = bytes {
+            bytes.copy_from_slice(val);
+            self.count_access(region, true, val.len() as u64);
+            return;
+        }
+
+        for (i, &byte) in val.iter().enumerate() {
+            self.write_byte(addr.wrapping_add(i as u16), byte);
+        }
+    }
+}
+
+impl SpaceInvadersMemory {
+    pub fn new(rom: [u8; ROM_SIZE], rotation: RotationMode) -> Self {
+        SpaceInvadersMemory {
+            rom,
+            ram: [0 as u8; RAM_SIZE],
+            vram: [0; VRAM_SIZE],
+            rotation,
+            flipped: false,
+            access: Cell::default(),
+        }
+    }
+
+    #[inline(always)]
+    fn count_access(&self, region: Region, write: bool, bytes: u64) {
+        #[cfg(feature = "access-counters")]
+        {
+            let mut counts = self.access.get();
+            let counts_for = if write {
+                &mut counts.writes
+            } else {
+                &mut counts.reads
+            };
+            counts_for[region as usize] += bytes;
+            self.access.set(counts);
+        }
+        #[cfg(not(feature = "access-counters"))]
+        let _ = (region, write, bytes);
+    }
+
+    /// The accesses counted since the last call, then starts again from zero. Always
+    /// zero without the `access-counters` feature.
+    pub fn take_access_counts(&mut self) -> AccessCounts {
+        self.access.take()
+    }
+
+    /// Builds memory from a ROM image no longer than [`ROM_SIZE`], filling the rest
+    /// of the ROM with zeros
+    pub fn from_rom_slice(rom: &[u8], rotation: RotationMode) -> Result<Self, String> {
+        if rom.len() > ROM_SIZE {
+            return Err(format!(
+                "ROM is {} bytes but at most {} fit",
+                rom.len(),
+                ROM_SIZE
+            ));
+        }
+
+        let mut image = [0u8; ROM_SIZE];
+        image[..rom.len()].copy_from_slice(rom);
+        Ok(Self::new(image, rotation))
+    }
+
+    /// Swaps in another ROM image. RAM and VRAM are left as they are, so the CPU should
+    /// be reset along with it.
+    pub fn set_rom(&mut self, rom: [u8; ROM_SIZE]) {
+        self.rom = rom;
+    }
+
+    /// Zeroes work RAM and VRAM as they are at power on, keeping the ROM
+    pub fn clear(&mut self) {
+        self.ram.fill(0);
+        self.vram.fill(0);
+    }
+
+    /// A copy of work RAM (0x2000-0x23FF)
+    pub fn save_ram(&self) -> [u8; RAM_SIZE] {
+        self.ram
+    }
+
+    /// Overwrites work RAM. Only safe between instructions, and the game's idea of what
+    /// is going on may no longer match the CPU registers or the screen.
+    pub fn load_ram(&mut self, ram: &[u8; RAM_SIZE]) {
+        self.ram = *ram;
+    }
+
+    /// Writes work RAM, and VRAM if asked for, to look at after the fact.
+    ///
+    /// The dump is [`DUMP_MAGIC`], a u16 version and a u8 count of regions, then each
+    /// region's start address and length as u16s, then the bytes of each region in the
+    /// same order. Numbers are little-endian. VRAM is one bit per pixel, as the game
+    /// sees it.
+    pub fn write_dump(&self, out: &mut impl Write, vram: bool) -> std::io::Result<()> {
+        let mut regions = vec![Region::Ram];
+        if vram {
+            regions.push(Region::Vram);
+        }
+
+        out.write_all(DUMP_MAGIC)?;
+        out.write_all(&DUMP_VERSION.to_le_bytes())?;
+        out.write_all(&[regions.len() as u8])?;
+        for region in &regions {
+            let range = region.range();
+            out.write_all(&(range.start as u16).to_le_bytes())?;
+            out.write_all(&(range.len() as u16).to_le_bytes())?;
+        }
+
+        for region in regions {
+            let bytes: Vec<u8> = region
+                .range()
+                .map(|addr| self.read_byte(addr as u16))
+                .collect();
+            out.write_all(&bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// The region an address falls in, after mirroring
+    pub fn region_of(&self, addr: u16) -> Region {
+        dispatch(addr).0
+    }
+
+    pub fn rotation(&self) -> RotationMode {
+        self.rotation
+    }
+
+    /// VRAM as the game sees it, one bit per pixel with the lowest bit of each byte
+    /// first, 32 bytes to a line of 256 pixels
+    pub fn vram(&self) -> &[u8; VRAM_SIZE] {
+        &self.vram
+    }
+
+    /// Expands VRAM into the display as 32-bit RGBA pixels, white on black, laid out
+    /// according to the rotation mode and flip. `out` is
+    /// [`DISPLAY_BUFFER_SIZE`](crate::DISPLAY_BUFFER_SIZE) bytes.
+    pub fn render_frame(&self, out: &mut [u8]) {
+        render_vram(&self.vram, self.rotation, self.flipped, out);
+    }
+
+    /// Expands VRAM into 32-bit RGBA pixels in the game's own layout, 256 pixels
+    /// across and 224 lines down with the lowest bit of each byte on the left, whatever
+    /// the rotation or flip
+    pub fn raw_vram(&self, out: &mut [u8]) {
+        for (pixels, &val) in out.chunks_exact_mut(8 * 4).zip(&self.vram) {
+            for (i, out) in pixels.chunks_exact_mut(4).enumerate() {
+                out.copy_from_slice(&pixel(val, i));
+            }
+        }
+    }
+
+    /// Turns the display 180 degrees from the next [`Self::render_frame`] on
+    pub fn set_flipped(&mut self, flipped: bool) {
+        self.flipped = flipped;
+    }
+
+    pub fn flipped(&self) -> bool {
+        self.flipped
+    }
+
+    fn write_vram(&mut self, address: usize, val: u8) {
+        self.vram[address] = val;
+    }
+
+    fn read_vram(&self, address: usize) -> u8 {
+        self.vram[address]
+    }
+
+    /// Player 1's score as stored, in BCD. See [`Self::score_as_displayed`].
+    pub fn get_p1_score(&self) -> u16 {
+        u16::from_le_bytes(self.read_bytes::<2>(addr::P1_SCORE).try_into().unwrap())
+    }
+
+    /// Player 2's score as stored, in BCD. See [`Self::score_as_displayed`].
+    pub fn get_p2_score(&self) -> u16 {
+        u16::from_le_bytes(self.read_bytes::<2>(addr::P2_SCORE).try_into().unwrap())
+    }
+
+    /// The score shown on screen for `player` (1 or 2), or `None` for any other player
+    pub fn score_as_displayed(&self, player: u8) -> Option<u16> {
+        match player {
+            1 => Some(bcd_to_decimal(self.get_p1_score())),
+            2 => Some(bcd_to_decimal(self.get_p2_score())),
+            _ => None,
+        }
+    }
+
+    /// The score and ships left of the player whose turn it is
+    pub fn scoreboard(&self) -> Scoreboard {
+        let player = if self.read_byte(addr::PLAYER_DATA_MSB) == 0x22 {
+            2
+        } else {
+            1
+        };
+        let ships = match player {
+            1 => addr::P1_SHIPS,
+            _ => addr::P2_SHIPS,
+        };
+        Scoreboard {
+            player,
+            score: self.score_as_displayed(player).unwrap_or(0),
+            ships: self.read_byte(ships),
+        }
+    }
+}
+
+/// The current player's standing, for `--accessible-hud`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Scoreboard {
+    pub player: u8,
+    pub score: u16,
+    pub ships: u8,
+}
+
+impl Scoreboard {
+    /// Short enough to draw large, with the score padded as the game pads it
+    pub fn lines(self) -> [String; 2] {
+        [
+            format!("P{} {:04}", self.player, self.score),
+            format!("LIVES {}", self.ships),
+        ]
+    }
+}
+
+/// Expands a copy of VRAM, laid out as [`SpaceInvadersMemory::vram`], as
+/// [`SpaceInvadersMemory::render_frame`] does
+pub fn render_vram(vram: &[u8; VRAM_SIZE], rotation: RotationMode, flipped: bool, out: &mut [u8]) {
+    for (address, &val) in vram.iter().enumerate() {
+        for i in 0..8 {
+            let index = rotation.display_pixel_index(address * 8 + i, flipped);
+            out[index * 4..index * 4 + 4].copy_from_slice(&pixel(val, i));
+        }
+    }
+}
+
+/// Bit `i` of a VRAM byte as an RGBA pixel
+fn pixel(val: u8, i: usize) -> [u8; 4] {
+    if val & (1 << i) != 0 {
+        [0xFF, 0xFF, 0xFF, 0xFF]
+    } else {
+        [0x00, 0x00, 0x00, 0xFF]
+    }
+}
+
+/// Converts a packed BCD value, as the game stores scores and credits, to binary
+pub fn bcd_to_decimal(bcd: u16) -> u16 {
+    let mut value = 0;
+    for shift in [12, 8, 4, 0] {
+        value = (value * 10) + ((bcd >> shift) & 0xF);
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{
+        bcd_to_decimal, AccessCounts, AccessStats, Region, RotationMode, Scoreboard,
+        SpaceInvadersMemory,
+    };
+    use crate::{addr, DISPLAY_BUFFER_SIZE, ROM_SIZE, SCREEN_SIZE_PIXELS, VRAM_SIZE};
+    use emu8080::MemoryAccess;
+
+    fn lit_pixels(memory: &SpaceInvadersMemory) -> Vec<usize> {
+        let mut frame = vec![0; DISPLAY_BUFFER_SIZE];
+        memory.render_frame(&mut frame);
+        (0..SCREEN_SIZE_PIXELS)
+            .filter(|i| frame[i * 4] != 0)
+            .collect()
+    }
+
+    #[test]
+    fn test_read_bytes_across_regions() {
+        let mut rom = [0; ROM_SIZE];
+        rom[0x0000] = 0x78;
+        rom[0x1FFF] = 0x12;
+        let mut memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+        memory.write_byte(0x2000, 0xAB);
+        memory.write_byte(0x23FF, 0x34);
+        memory.write_byte(0x2400, 0x56);
+        memory.write_byte(0x3FFF, 0x9A);
+
+        assert_eq!(memory.read_bytes::<2>(0x1FFF), [0x12, 0xAB]);
+        assert_eq!(memory.read_bytes::<2>(0x23FF), [0x34, 0x56]);
+        assert_eq!(memory.read_bytes::<2>(0x3FFF), [0x9A, 0x00]);
+        assert_eq!(memory.read_bytes::<2>(0xFFFF), [0x9A, 0x78]);
+        assert_eq!(memory.read_bytes::<3>(0x1FFF), [0x12, 0xAB, 0x00]);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut rom = [0; ROM_SIZE];
+        rom[0x0000] = 0x12;
+        let mut memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+
+        // nothing is fitted at 0x4000-0x5FFF
+        memory.write_byte(0x4000, 0xFF);
+        assert_eq!(memory.read_byte(0x4000), 0x00);
+        assert_eq!(memory.read_byte(0x0000), 0x12);
+
+        // RAM and VRAM show up again at 0x6000
+        memory.write_byte(0x6000, 0x34);
+        assert_eq!(memory.read_byte(0x2000), 0x34);
+        memory.write_byte(0x2400, 0x56);
+        assert_eq!(memory.read_byte(0x6400), 0x56);
+        assert_eq!(memory.read_bytes::<2>(0x63FF), [0x00, 0x56]);
+
+        // the top address line is ignored
+        assert_eq!(memory.read_byte(0x8000), 0x12);
+        assert_eq!(memory.read_byte(0xA000), 0x34);
+        assert_eq!(memory.read_byte(0xE400), 0x56);
+    }
+
+    #[test]
+    fn test_from_rom_slice() {
+        let memory =
+            SpaceInvadersMemory::from_rom_slice(&[0x12, 0x34], RotationMode::default()).unwrap();
+        assert_eq!(memory.read_bytes::<3>(0x0000), [0x12, 0x34, 0x00]);
+        assert_eq!(memory.read_byte(0x1FFF), 0x00);
+
+        assert!(
+            SpaceInvadersMemory::from_rom_slice(&[0; ROM_SIZE], RotationMode::default()).is_ok()
+        );
+        assert!(
+            SpaceInvadersMemory::from_rom_slice(&[0; ROM_SIZE + 1], RotationMode::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_save_and_load_ram() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        memory.write_byte(0x2000, 0x12);
+        memory.write_byte(0x23FF, 0x34);
+        memory.write_byte(0x2400, 0x56);
+        let saved = memory.save_ram();
+
+        memory.write_byte(0x2000, 0x00);
+        memory.write_byte(0x23FF, 0x00);
+        memory.write_byte(0x2400, 0x00);
+        memory.load_ram(&saved);
+
+        assert_eq!(memory.read_byte(0x2000), 0x12);
+        assert_eq!(memory.read_byte(0x23FF), 0x34);
+        // VRAM is left alone
+        assert_eq!(memory.read_byte(0x2400), 0x00);
+    }
+
+    #[test]
+    fn test_region_of() {
+        let memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        for (addr, region) in [
+            (0x0000, Region::Rom),
+            (0x1FFF, Region::Rom),
+            (0x2000, Region::Ram),
+            (0x23FF, Region::Ram),
+            (0x2400, Region::Vram),
+            (0x3FFF, Region::Vram),
+            (0x4000, Region::Unmapped),
+            (0x5FFF, Region::Unmapped),
+            (0x6000, Region::Ram),
+            (0x63FF, Region::Ram),
+            (0x6400, Region::Vram),
+            (0x7FFF, Region::Vram),
+            (0x8000, Region::Rom),
+            (0xC000, Region::Unmapped),
+            (0xFFFF, Region::Vram),
+        ] {
+            assert_eq!(memory.region_of(addr), region, "{:#06X}", addr);
+        }
+
+        for region in [Region::Rom, Region::Ram, Region::Vram, Region::Unmapped] {
+            let range = region.range();
+            assert_eq!(memory.region_of(range.start as u16), region);
+            assert_eq!(memory.region_of(range.end as u16 - 1), region);
+        }
+        assert!(!Region::Rom.writable());
+        assert!(Region::Vram.writable());
+    }
+
+    #[test]
+    fn test_access_methods_agree() {
+        let rom = std::array::from_fn(|i| i as u8);
+        let data = [0xA5, 0x5A, 0xC3];
+
+        for boundary in [
+            0x0000u16, 0x2000, 0x2400, 0x4000, 0x6000, 0x6400, 0x8000, 0xA000, 0xC000, 0xE000,
+        ] {
+            for addr in [boundary.wrapping_sub(2), boundary.wrapping_sub(1), boundary] {
+                let mut by_byte = SpaceInvadersMemory::new(rom, RotationMode::default());
+                let mut by_slice = SpaceInvadersMemory::new(rom, RotationMode::default());
+                for (i, &byte) in data.iter().enumerate() {
+                    by_byte.write_byte(addr.wrapping_add(i as u16), byte);
+                }
+                by_slice.write_bytes(addr, &data);
+
+                let expected: [u8; 3] =
+                    std::array::from_fn(|i| by_byte.read_byte(addr.wrapping_add(i as u16)));
+                assert_eq!(by_slice.read_bytes::<3>(addr), expected, "{:#06X}", addr);
+                assert_eq!(by_byte.read_bytes::<3>(addr), expected, "{:#06X}", addr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_bytes_vram() {
+        let data: Vec<u8> = (0..VRAM_SIZE + 0x10).map(|i| (i * 7) as u8).collect();
+        // all of VRAM and on into the unmapped gap, and from RAM into VRAM in the mirror
+        for (addr, len) in [
+            (0x2400u16, VRAM_SIZE + 0x10),
+            (0x3FF0, 0x20),
+            (0x63F8, 0x10),
+        ] {
+            let mut by_byte = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+            let mut by_slice = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+            for (i, &byte) in data[..len].iter().enumerate() {
+                by_byte.write_byte(addr.wrapping_add(i as u16), byte);
+            }
+            by_slice.write_bytes(addr, &data[..len]);
+
+            assert_eq!(by_slice.vram(), by_byte.vram(), "{:#06X}", addr);
+            assert_eq!(by_slice.save_ram(), by_byte.save_ram(), "{:#06X}", addr);
+        }
+    }
+
+    #[test]
+    fn test_write_dump() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        memory.write_byte(0x2001, 0x42);
+        memory.write_byte(0x2400, 0x81);
+
+        let mut dump = Vec::new();
+        memory.write_dump(&mut dump, false).unwrap();
+        assert_eq!(&dump[..13], b"SIDUMP\x01\x00\x01\x00\x20\x00\x04");
+        assert_eq!(dump.len(), 13 + 0x400);
+        assert_eq!(dump[13 + 1], 0x42);
+
+        dump.clear();
+        memory.write_dump(&mut dump, true).unwrap();
+        assert_eq!(&dump[13..17], &[0x00, 0x24, 0x00, 0x1C]);
+        assert_eq!(dump.len(), 17 + 0x400 + 0x1C00);
+        assert_eq!(dump[17 + 0x400], 0x81);
+    }
+
+    #[test]
+    fn test_bcd_to_decimal() {
+        assert_eq!(bcd_to_decimal(0x0000), 0);
+        assert_eq!(bcd_to_decimal(0x0150), 150);
+        assert_eq!(bcd_to_decimal(0x9990), 9990);
+    }
+
+    #[test]
+    fn test_score_as_displayed() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        assert_eq!(memory.score_as_displayed(1), Some(0));
+
+        // a mystery ship worth 300 after a few invaders
+        memory.write_bytes(0x20F8, &[0x60, 0x03]);
+        assert_eq!(memory.get_p1_score(), 0x0360);
+        assert_eq!(memory.score_as_displayed(1), Some(360));
+
+        memory.write_bytes(0x20FC, &[0x70, 0x12]);
+        assert_eq!(memory.score_as_displayed(2), Some(1270));
+        assert_eq!(memory.score_as_displayed(3), None);
+    }
+
+    #[test]
+    fn test_scoreboard() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        memory.write_bytes(addr::P1_SCORE, &[0x60, 0x03]);
+        memory.write_byte(addr::P1_SHIPS, 3);
+        memory.write_byte(addr::PLAYER_DATA_MSB, 0x21);
+        let scoreboard = memory.scoreboard();
+        assert_eq!(
+            scoreboard,
+            Scoreboard {
+                player: 1,
+                score: 360,
+                ships: 3
+            }
+        );
+        assert_eq!(scoreboard.lines(), ["P1 0360", "LIVES 3"]);
+
+        memory.write_byte(addr::P2_SHIPS, 1);
+        memory.write_byte(addr::PLAYER_DATA_MSB, 0x22);
+        assert_eq!(memory.scoreboard().lines(), ["P2 0000", "LIVES 1"]);
+    }
+
+    #[test]
+    fn test_flip_screen() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        memory.write_byte(0x2400, 0b0000_0011);
+        memory.write_byte(0x3000, 0b1000_0001);
+
+        let upright = lit_pixels(&memory);
+        memory.set_flipped(true);
+        let mut flipped: Vec<usize> = lit_pixels(&memory)
+            .into_iter()
+            .map(|i| SCREEN_SIZE_PIXELS - 1 - i)
+            .collect();
+        flipped.sort();
+        assert_eq!(flipped, upright);
+
+        // the guest still sees the same bytes
+        assert_eq!(memory.read_byte(0x2400), 0b0000_0011);
+        assert_eq!(memory.read_byte(0x3000), 0b1000_0001);
+        assert_eq!(memory.vram()[0x3000 - 0x2400], 0b1000_0001);
+
+        memory.set_flipped(false);
+        assert_eq!(lit_pixels(&memory), upright);
+    }
+
+    #[test]
+    fn test_rotation_modes() {
+        // (mode, first pixel of the frame, last pixel of the frame)
+        let cases = [
+            (RotationMode::None, 0, 223 * 256 + 255),
+            (RotationMode::CounterClockwise, 255 * 224, 223),
+            (RotationMode::Clockwise, 223, 255 * 224),
+        ];
+
+        for (mode, first, last) in cases {
+            let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], mode);
+
+            memory.write_byte(0x2400, 0b0000_0001);
+            assert_eq!(lit_pixels(&memory), vec![first], "{:?}", mode);
+            memory.write_byte(0x2400, 0);
+
+            memory.write_byte(0x3FFF, 0b1000_0000);
+            assert_eq!(lit_pixels(&memory), vec![last], "{:?}", mode);
+
+            memory.write_byte(0x2ABC, 0xA5);
+            assert_eq!(memory.read_byte(0x2ABC), 0xA5, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_raw_vram() {
+        for mode in [RotationMode::CounterClockwise, RotationMode::None] {
+            let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], mode);
+            memory.set_flipped(true);
+            memory.write_byte(0x2400, 0b0000_0001);
+            memory.write_byte(0x2421, 0b1000_0000);
+
+            let mut raw = vec![0; DISPLAY_BUFFER_SIZE];
+            memory.raw_vram(&mut raw);
+            let lit: Vec<usize> = (0..raw.len() / 4).filter(|i| raw[i * 4] != 0).collect();
+            // line 1 starts 32 bytes in, its second byte covers pixels 8 to 15
+            assert_eq!(lit, vec![0, 256 + 15], "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_access_stats() {
+        let mut stats = AccessStats::default();
+        let mut counts = AccessCounts::default();
+        counts.reads[Region::Rom as usize] = 10;
+        counts.writes[Region::Vram as usize] = 4;
+        stats.add_frame(counts);
+        counts.reads[Region::Rom as usize] = 20;
+        counts.writes[Region::Vram as usize] = 0;
+        stats.add_frame(counts);
+
+        assert_eq!(stats.frames, 2);
+        assert_eq!(
+            stats.to_string(),
+            "ROM r 10/15/20 w 0/0/0, VRAM r 0/0/0 w 0/2/4"
+        );
+    }
+
+    #[cfg(feature = "access-counters")]
+    #[test]
+    fn test_access_counts() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        memory.read_byte(0x0000);
+        memory.read_bytes::<2>(0x0001);
+        memory.write_bytes(0x2000, &[1, 2, 3]);
+        memory.write_byte(0x2400, 0xFF);
+        memory.write_byte(0x4000, 0xFF);
+
+        let counts = memory.take_access_counts();
+        assert_eq!(counts.reads[Region::Rom as usize], 3);
+        assert_eq!(counts.writes[Region::Ram as usize], 3);
+        assert_eq!(counts.writes[Region::Vram as usize], 1);
+        assert_eq!(counts.writes[Region::Unmapped as usize], 1);
+        assert_eq!(memory.take_access_counts(), AccessCounts::default());
+    }
+}
@@ -0,0 +1,1027 @@
+//! Effects applied to the RGBA display buffer on its way to the window.
+//!
+//! These work on the finished display buffer, so they stack on top of anything that
+//! has already coloured it. The buffer holds sRGB values; [`Blend::Linear`] converts
+//! to linear light and back around any arithmetic so darkening looks physically right
+//! rather than muddy. Effects precompute their output levels, so the choice of blend
+//! costs nothing per frame and the plain path without effects never touches it.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::memory::RotationMode;
+use crate::{DISPLAY_TIME_NANO_SEC, SCREEN_HEIGHT_PIXELS, SCREEN_WIDTH_PIXELS};
+
+/// The two colours of the monochrome monitor. Applied before any [`ColorOverlay`], which
+/// tints it like gel over a coloured screen would.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl Palette {
+    pub const WHITE: Palette = Palette::on_black([0xFF, 0xFF, 0xFF]);
+    /// P1 phosphor
+    pub const GREEN: Palette = Palette::on_black([0x33, 0xFF, 0x33]);
+    /// P3 phosphor
+    pub const AMBER: Palette = Palette::on_black([0xFF, 0xB0, 0x00]);
+    pub const BLUE: Palette = Palette::on_black([0x66, 0xCC, 0xFF]);
+
+    const fn on_black(foreground: [u8; 3]) -> Self {
+        Palette {
+            foreground,
+            background: [0x00; 3],
+        }
+    }
+
+    /// Recolours a white on black RGBA display buffer
+    pub fn apply(&self, frame: &mut [u8]) {
+        for pixel in frame.chunks_exact_mut(4) {
+            let color = if pixel[0] != 0 {
+                self.foreground
+            } else {
+                self.background
+            };
+            pixel[..3].copy_from_slice(&color);
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::WHITE
+    }
+}
+
+impl std::str::FromStr for Palette {
+    type Err = String;
+
+    /// A preset name, or a foreground and background in hex such as `FFB000,100800`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "white" => return Ok(Palette::WHITE),
+            "green" => return Ok(Palette::GREEN),
+            "amber" => return Ok(Palette::AMBER),
+            "blue" => return Ok(Palette::BLUE),
+            _ => {}
+        }
+
+        let parse_color = |hex: &str| -> Option<[u8; 3]> {
+            let value = u32::from_str_radix(hex.trim_start_matches('#'), 16).ok()?;
+            (hex.trim_start_matches('#').len() == 6).then(|| {
+                let [_, r, g, b] = value.to_be_bytes();
+                [r, g, b]
+            })
+        };
+
+        s.split_once(',')
+            .and_then(|(foreground, background)| {
+                Some(Palette {
+                    foreground: parse_color(foreground)?,
+                    background: parse_color(background)?,
+                })
+            })
+            .ok_or_else(|| {
+                format!(
+                    "Unknown phosphor '{}' (expected white, green, amber, blue or two hex colours like FFB000,000000)",
+                    s
+                )
+            })
+    }
+}
+
+/// `--overlay` value for [`ColorOverlay::classic`] rather than a PNG
+pub const CLASSIC_OVERLAY: &str = "classic";
+
+/// Rows of the upright screen, counted from the top, under the cabinet's red gel,
+/// which covers the saucer
+const CLASSIC_RED_ROWS: Range<usize> = 32..64;
+/// Rows under the green gel, over the shields and the player's cannon
+const CLASSIC_GREEN_ROWS: Range<usize> = 184..240;
+/// Below that the green only reaches across the ships in reserve, leaving the credits
+/// on the right white
+const CLASSIC_RESERVE_ROWS: Range<usize> = 240..256;
+const CLASSIC_RESERVE_COLUMNS: Range<usize> = 0..136;
+
+const GEL_RED: [u8; 3] = [0xFF, 0x20, 0x20];
+const GEL_GREEN: [u8; 3] = [0x20, 0xFF, 0x20];
+
+/// Tints lit pixels with a colour per display pixel, like the strips of coloured gel
+/// stuck over the monitor in the cabinet
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorOverlay {
+    width: usize,
+    height: usize,
+    /// RGB for each display pixel, row by row
+    colors: Vec<[u8; 3]>,
+}
+
+impl ColorOverlay {
+    pub fn new(width: usize, height: usize, colors: Vec<[u8; 3]>) -> Result<Self, String> {
+        if colors.len() != width * height {
+            return Err(format!(
+                "Overlay has {} colours for a {}x{} display",
+                colors.len(),
+                width,
+                height
+            ));
+        }
+        Ok(ColorOverlay {
+            width,
+            height,
+            colors,
+        })
+    }
+
+    /// Loads an overlay from a PNG exactly the size of the display, e.g. a scan of a
+    /// cabinet's gel. Any colour type and bit depth is accepted; alpha is ignored.
+    pub fn from_png(path: &Path, width: usize, height: usize) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(|e| e.to_string())?;
+
+        if (info.width as usize, info.height as usize) != (width, height) {
+            return Err(format!(
+                "overlay is {}x{} but the display is {}x{}",
+                info.width, info.height, width, height
+            ));
+        }
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => return Err("palette was not expanded".to_string()),
+        };
+
+        let colors = buffer
+            .chunks_exact(info.line_size)
+            .take(height)
+            .flat_map(|line| line.chunks_exact(channels).take(width))
+            .map(|pixel| match channels {
+                1 | 2 => [pixel[0]; 3],
+                _ => [pixel[0], pixel[1], pixel[2]],
+            })
+            .collect();
+
+        Self::new(width, height, colors)
+    }
+
+    /// The red and green strips of the original cabinet, lined up with the display
+    /// however it's rotated
+    pub fn classic(rotation: RotationMode) -> Self {
+        let (width, height) = (rotation.display_width(), rotation.display_height());
+        let colors = (0..width * height)
+            .map(|index| {
+                let (row, col) = (index / width, index % width);
+                // where the pixel sits on the screen as the upright cabinet shows it
+                let (row, col) = match rotation {
+                    RotationMode::None => (SCREEN_WIDTH_PIXELS - 1 - col, row),
+                    RotationMode::CounterClockwise => (row, col),
+                    RotationMode::Clockwise => (
+                        SCREEN_WIDTH_PIXELS - 1 - row,
+                        SCREEN_HEIGHT_PIXELS - 1 - col,
+                    ),
+                };
+                if CLASSIC_RED_ROWS.contains(&row) {
+                    GEL_RED
+                } else if CLASSIC_GREEN_ROWS.contains(&row)
+                    || (CLASSIC_RESERVE_ROWS.contains(&row)
+                        && CLASSIC_RESERVE_COLUMNS.contains(&col))
+                {
+                    GEL_GREEN
+                } else {
+                    [0xFF; 3]
+                }
+            })
+            .collect();
+        ColorOverlay {
+            width,
+            height,
+            colors,
+        }
+    }
+
+    /// Multiplies every pixel of an RGBA display buffer by its overlay colour
+    pub fn apply(&self, frame: &mut [u8]) {
+        for (pixel, color) in frame.chunks_exact_mut(4).zip(&self.colors) {
+            for (value, &tint) in pixel.iter_mut().zip(color) {
+                *value = ((*value as u16 * tint as u16) / 255) as u8;
+            }
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+/// Each game pixel becomes a square this many host pixels across, the last row and
+/// column of which are the gridline. Scaling by a whole number keeps every cell the
+/// same size, so the grid can't beat against the display's own pixels.
+pub const PIXEL_GRID_SCALE: usize = 3;
+
+/// How effects combine colours
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Blend {
+    /// directly on the stored sRGB values
+    #[default]
+    Srgb,
+    /// in linear light, converting back to sRGB for output
+    Linear,
+}
+
+impl Blend {
+    /// Scales an sRGB channel value by `gain`
+    pub fn scale(self, value: u8, gain: f32) -> u8 {
+        match self {
+            Blend::Srgb => (value as f32 * gain) as u8,
+            Blend::Linear => linear_to_srgb(srgb_to_linear(value) * gain),
+        }
+    }
+}
+
+pub fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let value = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round() as u8
+}
+
+/// Dark lines between pixels, like the gaps between phosphor dots
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelGrid {
+    /// 0.0 leaves the lines invisible, 1.0 makes them black
+    pub intensity: f32,
+    /// what each channel value becomes on a gridline
+    line_levels: [u8; 256],
+}
+
+impl PixelGrid {
+    pub fn new(intensity: f32, blend: Blend) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&intensity) {
+            return Err(format!(
+                "Pixel grid intensity must be between 0 and 1, got {}",
+                intensity
+            ));
+        }
+
+        let line_levels = std::array::from_fn(|value| blend.scale(value as u8, 1.0 - intensity));
+        Ok(PixelGrid {
+            intensity,
+            line_levels,
+        })
+    }
+
+    /// Size of the buffer the grid is drawn into for a display of the given size
+    pub fn buffer_size(width: usize, height: usize) -> (usize, usize) {
+        (width * PIXEL_GRID_SCALE, height * PIXEL_GRID_SCALE)
+    }
+
+    /// Expands `src`, an RGBA image `width` pixels wide, into `dst` at
+    /// [`PIXEL_GRID_SCALE`] times the size with gridlines drawn in
+    pub fn expand(&self, src: &[u8], width: usize, dst: &mut [u8]) {
+        let dst_width = width * PIXEL_GRID_SCALE;
+
+        for (i, src_pixel) in src.chunks_exact(4).enumerate() {
+            let mut line_pixel = [0u8; 4];
+            for (line, &value) in line_pixel.iter_mut().zip(src_pixel) {
+                *line = self.line_levels[value as usize];
+            }
+            // keep alpha as it was
+            line_pixel[3] = src_pixel[3];
+
+            let x = (i % width) * PIXEL_GRID_SCALE;
+            let y = (i / width) * PIXEL_GRID_SCALE;
+            for dy in 0..PIXEL_GRID_SCALE {
+                for dx in 0..PIXEL_GRID_SCALE {
+                    let on_line = dx == PIXEL_GRID_SCALE - 1 || dy == PIXEL_GRID_SCALE - 1;
+                    let offset = ((y + dy) * dst_width + x + dx) * 4;
+                    dst[offset..offset + 4].copy_from_slice(if on_line {
+                        &line_pixel
+                    } else {
+                        src_pixel
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// How much brightness a pixel keeps from one frame to the next when persistence is
+/// turned on without an amount
+pub const DEFAULT_PERSISTENCE: f32 = 0.5;
+
+/// Phosphor persistence, where a pixel that goes dark fades out over a few frames
+/// rather than at once, softening the invaders' animation the way a real tube does.
+/// It works on finished colours, so trails keep the palette's and overlay's tint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Persistence {
+    /// how much of its brightness a pixel keeps after one frame, 0 to below 1
+    pub amount: f32,
+    blend: Blend,
+    /// the last frame shown, trails and all
+    previous: Vec<u8>,
+    shown: Option<Instant>,
+}
+
+impl Persistence {
+    pub fn new(amount: f32, blend: Blend) -> Result<Self, String> {
+        if !(0.0..1.0).contains(&amount) {
+            return Err(format!(
+                "Persistence must be at least 0 and below 1, got {}",
+                amount
+            ));
+        }
+        Ok(Persistence {
+            amount,
+            blend,
+            previous: Vec::new(),
+            shown: None,
+        })
+    }
+
+    /// Lights `frame` with what's left of the frames shown before it, faded by the
+    /// time since the last one was shown at, and keeps the result for next time
+    pub fn apply(&mut self, frame: &mut [u8], now: Instant) {
+        let shown = self.shown.replace(now);
+        let Some(shown) = shown.filter(|_| self.previous.len() == frame.len()) else {
+            self.previous = frame.to_vec();
+            return;
+        };
+
+        let frames = now.duration_since(shown).as_nanos() as f32 / DISPLAY_TIME_NANO_SEC as f32;
+        let gain = self.amount.powf(frames);
+        // always some way darker, so rounding can't leave a trail lit forever
+        let levels: [u8; 256] = std::array::from_fn(|value| {
+            let value = value as u8;
+            self.blend.scale(value, gain).min(value.saturating_sub(1))
+        });
+        for (pixel, previous) in frame
+            .chunks_exact_mut(4)
+            .zip(self.previous.chunks_exact_mut(4))
+        {
+            for (value, &faded) in pixel[..3].iter_mut().zip(&previous[..3]) {
+                *value = (*value).max(levels[faded as usize]);
+            }
+            previous.copy_from_slice(pixel);
+        }
+    }
+
+    /// Forgets the frames shown so far, so the next starts without trails
+    pub fn clear(&mut self) {
+        self.previous.clear();
+        self.shown = None;
+    }
+}
+
+/// 5x7 glyphs for A to Z then 0 to 9, a row per byte from the top with the leftmost
+/// pixel in bit 4
+const FONT: [[u8; GLYPH_HEIGHT]; 36] = [
+    [
+        0b01110, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+    ],
+    [
+        0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+    ],
+    [
+        0b01110, 0b10001, 0b10000, 0b10000, 0b10000, 0b10001, 0b01110,
+    ],
+    [
+        0b11110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b11110,
+    ],
+    [
+        0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b11111,
+    ],
+    [
+        0b11111, 0b10000, 0b10000, 0b11110, 0b10000, 0b10000, 0b10000,
+    ],
+    [
+        0b01110, 0b10001, 0b10000, 0b10111, 0b10001, 0b10001, 0b01111,
+    ],
+    [
+        0b10001, 0b10001, 0b10001, 0b11111, 0b10001, 0b10001, 0b10001,
+    ],
+    [
+        0b01110, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+    ],
+    [
+        0b00111, 0b00010, 0b00010, 0b00010, 0b00010, 0b10010, 0b01100,
+    ],
+    [
+        0b10001, 0b10010, 0b10100, 0b11000, 0b10100, 0b10010, 0b10001,
+    ],
+    [
+        0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b10000, 0b11111,
+    ],
+    [
+        0b10001, 0b11011, 0b10101, 0b10101, 0b10001, 0b10001, 0b10001,
+    ],
+    [
+        0b10001, 0b10001, 0b11001, 0b10101, 0b10011, 0b10001, 0b10001,
+    ],
+    [
+        0b01110, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+    ],
+    [
+        0b11110, 0b10001, 0b10001, 0b11110, 0b10000, 0b10000, 0b10000,
+    ],
+    [
+        0b01110, 0b10001, 0b10001, 0b10001, 0b10101, 0b10010, 0b01101,
+    ],
+    [
+        0b11110, 0b10001, 0b10001, 0b11110, 0b10100, 0b10010, 0b10001,
+    ],
+    [
+        0b01111, 0b10000, 0b10000, 0b01110, 0b00001, 0b00001, 0b11110,
+    ],
+    [
+        0b11111, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100, 0b00100,
+    ],
+    [
+        0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01110,
+    ],
+    [
+        0b10001, 0b10001, 0b10001, 0b10001, 0b10001, 0b01010, 0b00100,
+    ],
+    [
+        0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+    ],
+    [
+        0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+    ],
+    [
+        0b10001, 0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100,
+    ],
+    [
+        0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+    ],
+    [
+        0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+    ],
+    [
+        0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+    ],
+    [
+        0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+    ],
+    [
+        0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+    ],
+    [
+        0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+    ],
+    [
+        0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+    ],
+    [
+        0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+    ],
+    [
+        0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+    ],
+    [
+        0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+    ],
+    [
+        0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+    ],
+];
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+/// around the text inside a box, and between the paused badge and the edges
+const BADGE_MARGIN: usize = 2;
+
+/// The glyph for a letter or digit, either case. Anything else is blank.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        c @ 'A'..='Z' => FONT[c as usize - 'A' as usize],
+        c @ '0'..='9' => FONT[26 + c as usize - '0' as usize],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Draws a small "PAUSED" badge in the top right corner of an RGBA buffer `width`
+/// pixels across. The game still shows through it, dimmed, so nothing is hidden.
+pub fn draw_paused_badge(frame: &mut [u8], width: usize) {
+    let (badge_width, _) = text_box_size(&["PAUSED"]);
+    let left = width.saturating_sub(badge_width + BADGE_MARGIN);
+    draw_text_box(frame, width, (left, BADGE_MARGIN), &["PAUSED"]);
+}
+
+/// The width and height of the box [`draw_text_box`] draws for `lines`
+pub fn text_box_size(lines: &[&str]) -> (usize, usize) {
+    scaled_text_box_size(lines, 1)
+}
+
+/// The width and height of the box [`draw_scaled_text_box`] draws for `lines`
+pub fn scaled_text_box_size(lines: &[&str], scale: usize) -> (usize, usize) {
+    let columns = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    (
+        ((columns * (GLYPH_WIDTH + 1)).saturating_sub(1) + 2 * BADGE_MARGIN) * scale,
+        ((lines.len() * (GLYPH_HEIGHT + 1)).saturating_sub(1) + 2 * BADGE_MARGIN) * scale,
+    )
+}
+
+/// Draws `lines` of text in a box with its top left corner at `(left, top)` in an RGBA
+/// buffer `width` pixels across. The background is dimmed rather than blacked out, and
+/// whatever falls outside the buffer is left off.
+pub fn draw_text_box(frame: &mut [u8], width: usize, (left, top): (usize, usize), lines: &[&str]) {
+    text_box(frame, width, (left, top), lines, 1, |channel, lit| {
+        if lit {
+            ((channel as u16 + 3 * 0xFF) / 4) as u8
+        } else {
+            channel / 4
+        }
+    });
+}
+
+/// Draws `lines` of text as [`draw_text_box`] does but `scale` times the size, white
+/// on solid black so it reads the same over anything
+pub fn draw_scaled_text_box(
+    frame: &mut [u8],
+    width: usize,
+    (left, top): (usize, usize),
+    lines: &[&str],
+    scale: usize,
+) {
+    text_box(frame, width, (left, top), lines, scale, |_, lit| {
+        if lit {
+            0xFF
+        } else {
+            0x00
+        }
+    });
+}
+
+/// Sets each colour channel of the box to `shade(channel, lit)`
+fn text_box(
+    frame: &mut [u8],
+    width: usize,
+    (left, top): (usize, usize),
+    lines: &[&str],
+    scale: usize,
+    shade: impl Fn(u8, bool) -> u8,
+) {
+    let (box_width, box_height) = scaled_text_box_size(lines, scale);
+    let glyphs: Vec<Vec<[u8; GLYPH_HEIGHT]>> = lines
+        .iter()
+        .map(|line| line.chars().map(glyph).collect())
+        .collect();
+
+    for y in 0..box_height {
+        for x in 0..box_width.min(width.saturating_sub(left)) {
+            let i = ((top + y) * width + left + x) * 4;
+            let Some(pixel) = frame.get_mut(i..i + 3) else {
+                return;
+            };
+
+            let text_x = (x / scale).wrapping_sub(BADGE_MARGIN);
+            let text_y = (y / scale).wrapping_sub(BADGE_MARGIN);
+            let (column, row) = (text_x % (GLYPH_WIDTH + 1), text_y % (GLYPH_HEIGHT + 1));
+            let lit = column < GLYPH_WIDTH
+                && row < GLYPH_HEIGHT
+                && glyphs
+                    .get(text_y / (GLYPH_HEIGHT + 1))
+                    .and_then(|line| line.get(text_x / (GLYPH_WIDTH + 1)))
+                    .is_some_and(|glyph| glyph[row] >> (GLYPH_WIDTH - 1 - column) & 1 != 0);
+
+            for channel in pixel {
+                *channel = shade(*channel, lit);
+            }
+        }
+    }
+}
+
+/// Where `--accessible-hud` goes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Corner {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl std::str::FromStr for Corner {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "top-left" => Ok(Corner::TopLeft),
+            "top-right" => Ok(Corner::TopRight),
+            "bottom-left" => Ok(Corner::BottomLeft),
+            "bottom-right" => Ok(Corner::BottomRight),
+            _ => Err(format!(
+                "Unknown corner '{}' (expected top-left, top-right, bottom-left or bottom-right)",
+                s
+            )),
+        }
+    }
+}
+
+impl Corner {
+    /// The top left of a box `size` big in this corner of a `width` by `height`
+    /// buffer, [`BADGE_MARGIN`] in from the edges
+    pub fn place(
+        self,
+        (box_width, box_height): (usize, usize),
+        (width, height): (usize, usize),
+    ) -> (usize, usize) {
+        let right = width.saturating_sub(box_width + BADGE_MARGIN);
+        let bottom = height.saturating_sub(box_height + BADGE_MARGIN);
+        match self {
+            Corner::TopLeft => (BADGE_MARGIN, BADGE_MARGIN),
+            Corner::TopRight => (right, BADGE_MARGIN),
+            Corner::BottomLeft => (BADGE_MARGIN, bottom),
+            Corner::BottomRight => (right, bottom),
+        }
+    }
+}
+
+/// Draws the game's scanline `line` in `color` across an RGBA buffer laid out for
+/// `rotation`. On the upright display scanlines run up the screen, so it's a column.
+pub fn draw_scanline(frame: &mut [u8], rotation: RotationMode, line: usize, color: [u8; 3]) {
+    if line >= SCREEN_HEIGHT_PIXELS {
+        return;
+    }
+    for column in 0..SCREEN_WIDTH_PIXELS {
+        let i = rotation.display_pixel_index(line * SCREEN_WIDTH_PIXELS + column, false) * 4;
+        frame[i..i + 3].copy_from_slice(&color);
+    }
+}
+
+/// A small copy of the frame at a fixed size, for `--thumbnail`
+pub struct Thumbnail {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Thumbnail {
+    pub fn new(width: usize, height: usize) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err(format!("Thumbnail can't be {}x{}", width, height));
+        }
+        Ok(Thumbnail {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        })
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// RGBA, `width` pixels across
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Scales `frame`, an RGBA image `frame_width` pixels across, to the thumbnail's
+    /// size. Each thumbnail pixel is the average of every frame pixel it overlaps, so
+    /// thin lines fade rather than flicker in and out as they move.
+    pub fn update(&mut self, frame: &[u8], frame_width: usize) {
+        let frame_height = frame.len() / 4 / frame_width;
+        // the frame pixels a thumbnail pixel covers, at least one even when enlarging
+        let span = |i: usize, size: usize, frame_size: usize| {
+            (i * frame_size / size)..((i + 1) * frame_size).div_ceil(size)
+        };
+
+        for y in 0..self.height {
+            let rows = span(y, self.height, frame_height);
+            for x in 0..self.width {
+                let columns = span(x, self.width, frame_width);
+
+                let mut sum = [0u32; 4];
+                for row in rows.clone() {
+                    for column in columns.clone() {
+                        let i = (row * frame_width + column) * 4;
+                        for (total, &value) in sum.iter_mut().zip(&frame[i..i + 4]) {
+                            *total += value as u32;
+                        }
+                    }
+                }
+
+                let count = (rows.len() * columns.len()) as u32;
+                let i = (y * self.width + x) * 4;
+                for (value, total) in self.pixels[i..i + 4].iter_mut().zip(sum) {
+                    *value = ((total + count / 2) / count) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::memory::RotationMode;
+    use crate::render::{
+        draw_paused_badge, draw_scaled_text_box, draw_scanline, draw_text_box, linear_to_srgb,
+        scaled_text_box_size, srgb_to_linear, text_box_size, Blend, ColorOverlay, Corner, Palette,
+        Persistence, PixelGrid, Thumbnail, PIXEL_GRID_SCALE,
+    };
+    use crate::{DISPLAY_BUFFER_SIZE, DISPLAY_TIME_NANO_SEC};
+
+    #[test]
+    fn test_palette() {
+        assert_eq!("amber".parse(), Ok(Palette::AMBER));
+        assert_eq!(
+            "#102030,000001".parse(),
+            Ok(Palette {
+                foreground: [0x10, 0x20, 0x30],
+                background: [0x00, 0x00, 0x01],
+            })
+        );
+        assert!("purple".parse::<Palette>().is_err());
+        assert!("12345,000000".parse::<Palette>().is_err());
+        assert!("GGGGGG,000000".parse::<Palette>().is_err());
+
+        let mut frame = [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0xFF];
+        Palette::GREEN.apply(&mut frame);
+        assert_eq!(frame, [0x33, 0xFF, 0x33, 0xFF, 0x00, 0x00, 0x00, 0xFF]);
+
+        // an overlay tints the background too, as gel over a lit screen would
+        let palette = Palette {
+            foreground: [0xFF; 3],
+            background: [0x40; 3],
+        };
+        let overlay = ColorOverlay::new(2, 1, vec![[0xFF, 0x00, 0x00]; 2]).unwrap();
+        let mut frame = [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0xFF];
+        palette.apply(&mut frame);
+        overlay.apply(&mut frame);
+        assert_eq!(frame, [0xFF, 0x00, 0x00, 0xFF, 0x40, 0x00, 0x00, 0xFF]);
+    }
+
+    #[test]
+    fn test_color_overlay() {
+        assert!(ColorOverlay::new(2, 2, vec![[0; 3]; 3]).is_err());
+
+        let overlay =
+            ColorOverlay::new(2, 1, vec![[0xFF, 0x00, 0x00], [0x00, 0x80, 0xFF]]).unwrap();
+        let mut frame = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        overlay.apply(&mut frame);
+        assert_eq!(frame, [0xFF, 0x00, 0x00, 0xFF, 0x00, 0x80, 0xFF, 0xFF]);
+
+        // unlit pixels stay black
+        let mut frame = [0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF];
+        overlay.apply(&mut frame);
+        assert_eq!(frame, [0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF]);
+
+        assert!(ColorOverlay::from_png("does/not/exist.png".as_ref(), 2, 1).is_err());
+    }
+
+    #[test]
+    fn test_classic_overlay() {
+        let color = |overlay: &ColorOverlay, index: usize| {
+            let mut frame = vec![0xFF; DISPLAY_BUFFER_SIZE];
+            overlay.apply(&mut frame);
+            [frame[index * 4], frame[index * 4 + 1], frame[index * 4 + 2]]
+        };
+        let (white, red, green) = ([0xFF; 3], [0xFF, 0x20, 0x20], [0x20, 0xFF, 0x20]);
+
+        let upright = ColorOverlay::classic(RotationMode::CounterClockwise);
+        assert_eq!(upright.size(), (224, 256));
+        let at = |row: usize, col: usize| row * 224 + col;
+        assert_eq!(color(&upright, at(10, 100)), white);
+        assert_eq!(color(&upright, at(40, 100)), red);
+        assert_eq!(color(&upright, at(100, 100)), white);
+        assert_eq!(color(&upright, at(200, 100)), green);
+        // the reserve ships are green, the credits beside them aren't
+        assert_eq!(color(&upright, at(250, 20)), green);
+        assert_eq!(color(&upright, at(250, 200)), white);
+
+        // the same strips whichever way round the display is
+        let clockwise = ColorOverlay::classic(RotationMode::Clockwise);
+        assert_eq!(color(&clockwise, at(255 - 40, 223 - 100)), red);
+        assert_eq!(color(&clockwise, at(255 - 250, 223 - 20)), green);
+        let unrotated = ColorOverlay::classic(RotationMode::None);
+        assert_eq!(unrotated.size(), (256, 224));
+        assert_eq!(color(&unrotated, 100 * 256 + (255 - 40)), red);
+        assert_eq!(color(&unrotated, 20 * 256 + (255 - 250)), green);
+        assert_eq!(color(&unrotated, 200 * 256 + (255 - 250)), white);
+    }
+
+    #[test]
+    fn test_blend() {
+        for value in [0, 1, 10, 128, 254, 255] {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+
+        assert_eq!(Blend::Srgb.scale(0xFF, 0.5), 0x7F);
+        // half the light is a lot brighter than half the sRGB value
+        assert_eq!(Blend::Linear.scale(0xFF, 0.5), 0xBC);
+        assert_eq!(Blend::Linear.scale(0xFF, 0.0), 0x00);
+    }
+
+    #[test]
+    fn test_pixel_grid() {
+        assert!(PixelGrid::new(1.5, Blend::Srgb).is_err());
+
+        let white = [0xFF, 0xFF, 0xFF, 0xFF];
+        let black = [0x00, 0x00, 0x00, 0xFF];
+        let src = [white, black].concat();
+
+        let (width, height) = PixelGrid::buffer_size(2, 1);
+        assert_eq!((width, height), (2 * PIXEL_GRID_SCALE, PIXEL_GRID_SCALE));
+
+        let mut dst = vec![0; width * height * 4];
+        PixelGrid::new(0.5, Blend::Srgb)
+            .unwrap()
+            .expand(&src, 2, &mut dst);
+
+        let pixel = |x: usize, y: usize| &dst[(y * width + x) * 4..(y * width + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), white);
+        assert_eq!(pixel(1, 1), white);
+        assert_eq!(pixel(2, 0), [0x7F, 0x7F, 0x7F, 0xFF]);
+        assert_eq!(pixel(0, 2), [0x7F, 0x7F, 0x7F, 0xFF]);
+        assert_eq!(pixel(3, 0), black);
+        assert_eq!(pixel(5, 2), black);
+    }
+
+    #[test]
+    fn test_persistence() {
+        assert!(Persistence::new(1.0, Blend::Srgb).is_err());
+
+        let white = [0xFF, 0xFF, 0xFF, 0xFF];
+        let black = [0x00, 0x00, 0x00, 0xFF];
+        let start = Instant::now();
+        let at = |frames: u64| start + Duration::from_nanos(DISPLAY_TIME_NANO_SEC * frames);
+        let mut persistence = Persistence::new(0.5, Blend::Srgb).unwrap();
+
+        let mut frame = [white, black].concat();
+        persistence.apply(&mut frame, at(0));
+        assert_eq!(frame, [white, black].concat());
+
+        // the white pixel fades by half a frame, the newly lit one is lit at once
+        let mut frame = [black, white].concat();
+        persistence.apply(&mut frame, at(1));
+        assert_eq!(frame, [[0x7F, 0x7F, 0x7F, 0xFF], white].concat());
+        // and by a quarter over two frames
+        let mut frame = [black, black].concat();
+        persistence.apply(&mut frame, at(3));
+        assert_eq!(
+            frame,
+            [[0x1F, 0x1F, 0x1F, 0xFF], [0x3F, 0x3F, 0x3F, 0xFF]].concat()
+        );
+
+        // trails always go out, even redrawn with no time passing
+        for _ in 0..0x3E {
+            persistence.apply(&mut [black, black].concat(), at(3));
+        }
+        let mut frame = [black, black].concat();
+        persistence.apply(&mut frame, at(3));
+        assert_eq!(frame, [black, black].concat());
+
+        // a frame of another size starts over
+        let mut frame = [black, black, black].concat();
+        persistence.apply(&mut frame, at(4));
+        assert_eq!(frame, [black, black, black].concat());
+    }
+
+    #[test]
+    fn test_paused_badge() {
+        let width = 224;
+        let mut frame = vec![0xFF; width * 256 * 4];
+        draw_paused_badge(&mut frame, width);
+        let pixel = |x: usize, y: usize| frame[(y * width + x) * 4];
+
+        // 6 letters 5 wide with a gap between, a margin inside and out
+        let left = width - (35 + 4 + 2);
+        assert_eq!(pixel(left - 1, 2), 0xFF);
+        assert_eq!(pixel(left, 2), 0x3F);
+        // the top left of the P, then the gap inside it
+        assert_eq!(pixel(left + 2, 4), 0xFF);
+        assert_eq!(pixel(left + 3, 5), 0x3F);
+        assert_eq!(pixel(width - 3, 2 + 10), 0x3F);
+        assert_eq!(pixel(width - 3, 2 + 11), 0xFF);
+        // alpha is left alone
+        assert_eq!(frame[(2 * width + left) * 4 + 3], 0xFF);
+    }
+
+    #[test]
+    fn test_text_box() {
+        // the longest line sets the width, and each line below adds a glyph and a gap
+        assert_eq!(text_box_size(&["AB", "C"]), (11 + 4, 15 + 4));
+        assert_eq!(text_box_size(&[]), (4, 4));
+
+        let width = 20;
+        let mut frame = vec![0xFF; width * 20 * 4];
+        draw_text_box(&mut frame, width, (14, 1), &["i", "1"]);
+        let pixel = |x: usize, y: usize| frame[(y * width + x) * 4];
+
+        assert_eq!(pixel(13, 1), 0xFF);
+        assert_eq!(pixel(14, 1), 0x3F);
+        // the top bar of the I, lower case drawn as upper, then a 1 on the next line
+        assert_eq!(pixel(17, 3), 0xFF);
+        assert_eq!(pixel(16, 3), 0x3F);
+        assert_eq!(pixel(18, 11), 0xFF);
+        assert_eq!(pixel(17, 11), 0x3F);
+        // cut off at the right edge rather than wrapping onto the next row
+        assert_eq!(pixel(0, 2), 0xFF);
+    }
+
+    #[test]
+    fn test_scaled_text_box() {
+        assert_eq!(scaled_text_box_size(&["AB", "C"], 3), (45, 57));
+
+        let width = 40;
+        let mut frame = vec![0x80; width * 40 * 4];
+        draw_scaled_text_box(&mut frame, width, (0, 0), &["1"], 2);
+        let pixel = |x: usize, y: usize| frame[(y * width + x) * 4];
+
+        // solid black around the text whatever was there, and each dot of the 1 is 2x2
+        assert_eq!(pixel(0, 0), 0x00);
+        assert_eq!(pixel(4, 4), 0x00);
+        assert_eq!(pixel(8, 4), 0xFF);
+        assert_eq!(pixel(9, 5), 0xFF);
+        assert_eq!(pixel(10, 4), 0x00);
+        // the box is 9x11 scaled, the rest is left alone
+        assert_eq!(pixel(17, 21), 0x00);
+        assert_eq!(pixel(18, 21), 0x80);
+    }
+
+    #[test]
+    fn test_corner() {
+        assert_eq!("bottom-right".parse(), Ok(Corner::BottomRight));
+        assert!("middle".parse::<Corner>().is_err());
+
+        let (size, buffer) = ((20, 10), (224, 256));
+        assert_eq!(Corner::TopLeft.place(size, buffer), (2, 2));
+        assert_eq!(Corner::TopRight.place(size, buffer), (202, 2));
+        assert_eq!(Corner::BottomLeft.place(size, buffer), (2, 244));
+        assert_eq!(Corner::BottomRight.place(size, buffer), (202, 244));
+    }
+
+    #[test]
+    fn test_scanline() {
+        let mut frame = vec![0; DISPLAY_BUFFER_SIZE];
+        draw_scanline(&mut frame, RotationMode::CounterClockwise, 10, [0xFF, 0, 0]);
+        let lit: Vec<usize> = (0..frame.len() / 4).filter(|i| frame[i * 4] != 0).collect();
+        // a column of the upright display, 224 wide
+        assert_eq!(lit.len(), 256);
+        assert!(lit.iter().all(|i| i % 224 == 10));
+
+        let mut frame = vec![0; DISPLAY_BUFFER_SIZE];
+        draw_scanline(&mut frame, RotationMode::None, 10, [0xFF, 0, 0]);
+        assert_eq!(
+            frame[10 * 256 * 4..11 * 256 * 4].iter().step_by(4).min(),
+            Some(&0xFF)
+        );
+
+        // past the last line, nothing to draw
+        draw_scanline(&mut frame, RotationMode::None, 224, [0, 0xFF, 0]);
+        assert!(frame.chunks(4).all(|pixel| pixel[1] == 0));
+    }
+
+    #[test]
+    fn test_thumbnail() {
+        assert!(Thumbnail::new(0, 10).is_err());
+
+        // a 4x2 frame, white on the left half and black on the right
+        let mut frame = Vec::new();
+        for _ in 0..2 {
+            frame.extend_from_slice(&[0xFF; 8]);
+            frame.extend_from_slice(&[0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF]);
+        }
+
+        let mut thumbnail = Thumbnail::new(2, 1).unwrap();
+        thumbnail.update(&frame, 4);
+        assert_eq!(
+            thumbnail.pixels(),
+            [0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0xFF]
+        );
+
+        // the middle pixel straddles the edge and comes out grey
+        let mut thumbnail = Thumbnail::new(3, 1).unwrap();
+        thumbnail.update(&frame, 4);
+        assert_eq!(&thumbnail.pixels()[4..8], [0x80, 0x80, 0x80, 0xFF]);
+    }
+}
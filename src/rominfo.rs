@@ -0,0 +1,259 @@
+//! Identifying a ROM image, for `--info`.
+//!
+//! The board carries four 2K chips, mapped in order from 0x0000. Dumps are usually
+//! distributed as the four files from MAME's `invaders` set concatenated, so each
+//! chip is checked against those CRCs to point at the one that's bad. The four files
+//! can also be loaded as they are, see [`load_rom_parts`].
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use crate::capture::crc32;
+use crate::disasm::disassemble;
+use crate::ROM_SIZE;
+
+pub const CHIP_SIZE: usize = 0x800;
+
+/// MAME's names and CRC-32s for the chips of the `invaders` set
+pub const KNOWN_CHIPS: [(&str, u32); 4] = [
+    ("invaders.h", 0x734F_5AD8),
+    ("invaders.g", 0x6BFA_CA4A),
+    ("invaders.f", 0x0CCE_AD96),
+    ("invaders.e", 0x14E5_38B0),
+];
+
+/// Instructions disassembled from the reset vector
+const RESET_INSTRUCTIONS: usize = 8;
+
+/// Reads a ROM image from a file, see [`rom_image`]
+pub fn load_rom(path: &Path, pad: bool) -> Result<[u8; ROM_SIZE], String> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Could not read ROM {}: {}", path.display(), e))?;
+    rom_image(&data, pad)
+}
+
+/// Reads a ROM split across the four chips' files, `paths` in order from 0x0000, as
+/// MAME's `invaders` set has it. Each file has to be exactly one chip's worth.
+pub fn load_rom_parts(paths: [&Path; 4]) -> Result<[u8; ROM_SIZE], String> {
+    let mut image = [0u8; ROM_SIZE];
+    for (chip, path) in image.chunks_exact_mut(CHIP_SIZE).zip(paths) {
+        let data = std::fs::read(path)
+            .map_err(|e| format!("Could not read ROM {}: {}", path.display(), e))?;
+        if data.len() != CHIP_SIZE {
+            return Err(format!(
+                "ROM part {} is {} bytes, expected {}",
+                path.display(),
+                data.len(),
+                CHIP_SIZE
+            ));
+        }
+        chip.copy_from_slice(&data);
+    }
+    Ok(image)
+}
+
+/// The chips' files under their names in MAME's set, `invaders.h` to `invaders.e`, if
+/// all four are in `dir`
+pub fn rom_parts_in(dir: &Path) -> Option<[PathBuf; 4]> {
+    let paths = KNOWN_CHIPS.map(|(name, _)| dir.join(name));
+    paths.iter().all(|path| path.is_file()).then_some(paths)
+}
+
+/// Fits a ROM image to the board's [`ROM_SIZE`]. One that's too short is an error
+/// unless `pad` is set, for homebrew that doesn't fill every chip, and the rest is
+/// zeros. One that's too long is cut short with a warning.
+pub fn rom_image(data: &[u8], pad: bool) -> Result<[u8; ROM_SIZE], String> {
+    if data.len() < ROM_SIZE && !pad {
+        return Err(format!(
+            "ROM is {} bytes, expected {} (--pad-rom fills the rest with zeros)",
+            data.len(),
+            ROM_SIZE
+        ));
+    }
+    if data.len() > ROM_SIZE {
+        warn!(
+            "ROM is {} bytes, only the first {} are used",
+            data.len(),
+            ROM_SIZE
+        );
+    }
+
+    let mut image = [0u8; ROM_SIZE];
+    let len = data.len().min(ROM_SIZE);
+    image[..len].copy_from_slice(&data[..len]);
+    Ok(image)
+}
+
+pub struct RomInfo {
+    pub size: usize,
+    pub crc32: u32,
+    /// the CRC of each chip and whether it matches the known dump
+    pub chips: Vec<(u32, bool)>,
+    /// the first few instructions run after reset, as (address, bytes, mnemonic)
+    pub reset: Vec<(u16, Vec<u8>, String)>,
+}
+
+impl RomInfo {
+    pub fn new(rom: &[u8]) -> Self {
+        let chips = rom
+            .chunks(CHIP_SIZE)
+            .zip(KNOWN_CHIPS)
+            .map(|(chip, (_, known))| {
+                let crc = crc32(chip);
+                (crc, chip.len() == CHIP_SIZE && crc == known)
+            })
+            .collect();
+
+        let reset = disassemble(&rom[..rom.len().min(0x40)], 0x0000)
+            .into_iter()
+            .take(RESET_INSTRUCTIONS)
+            .map(|(addr, len, mnemonic)| {
+                let start = addr as usize;
+                (addr, rom[start..start + len].to_vec(), mnemonic)
+            })
+            .collect();
+
+        RomInfo {
+            size: rom.len(),
+            crc32: crc32(rom),
+            chips,
+            reset,
+        }
+    }
+
+    pub fn size_ok(&self) -> bool {
+        self.size == ROM_SIZE
+    }
+
+    /// True if every chip matches the known `invaders` dump
+    pub fn known(&self) -> bool {
+        self.size_ok() && self.chips.iter().all(|&(_, matches)| matches)
+    }
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let size_note = if self.size_ok() {
+            String::new()
+        } else {
+            format!(" (expected {})", ROM_SIZE)
+        };
+        writeln!(f, "Size:    {} bytes{}", self.size, size_note)?;
+        writeln!(f, "CRC-32:  {:08X}", self.crc32)?;
+        writeln!(
+            f,
+            "Dump:    {}",
+            if self.known() {
+                "matches invaders (Midway)"
+            } else {
+                "unknown"
+            }
+        )?;
+
+        writeln!(f, "Chips:")?;
+        for (i, (crc, matches)) in self.chips.iter().enumerate() {
+            let (name, known) = KNOWN_CHIPS[i];
+            let status = if *matches {
+                "ok".to_string()
+            } else {
+                format!("expected {:08X}", known)
+            };
+            writeln!(
+                f,
+                "  {:04X}-{:04X}  {}  {:08X}  {}",
+                i * CHIP_SIZE,
+                (i + 1) * CHIP_SIZE - 1,
+                name,
+                crc,
+                status
+            )?;
+        }
+
+        writeln!(f, "Reset:")?;
+        for (addr, bytes, mnemonic) in &self.reset {
+            let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            writeln!(f, "  {:04X}  {:<8}  {}", addr, bytes.join(" "), mnemonic)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rominfo::{load_rom_parts, rom_image, rom_parts_in, RomInfo, CHIP_SIZE};
+    use crate::{test_rom, ROM_SIZE};
+
+    #[test]
+    fn test_rom_info() {
+        let mut rom = vec![0; ROM_SIZE];
+        rom[..4].copy_from_slice(&[0x00, 0xC3, 0xD4, 0x18]);
+
+        let info = RomInfo::new(&rom);
+        assert!(info.size_ok());
+        assert!(!info.known());
+        assert_eq!(info.chips.len(), 4);
+        assert_eq!(info.reset[0], (0x0000, vec![0x00], "NOP".to_string()));
+        assert_eq!(
+            info.reset[1],
+            (0x0001, vec![0xC3, 0xD4, 0x18], "JMP $18D4".to_string())
+        );
+
+        let text = info.to_string();
+        assert!(text.contains("Dump:    unknown"));
+        assert!(text.contains("0001  C3 D4 18  JMP $18D4"));
+
+        let short = RomInfo::new(&rom[..0x1000]);
+        assert!(!short.size_ok());
+        assert_eq!(short.chips.len(), 2);
+    }
+
+    #[test]
+    fn test_rom_parts() {
+        let dir = std::env::temp_dir().join(format!("rom-parts-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(rom_parts_in(&dir), None);
+
+        let names = ["invaders.h", "invaders.g", "invaders.f", "invaders.e"];
+        for (i, name) in names.iter().enumerate() {
+            std::fs::write(dir.join(name), vec![i as u8; CHIP_SIZE]).unwrap();
+        }
+        let parts = rom_parts_in(&dir).unwrap();
+        let rom = load_rom_parts(parts.each_ref().map(|path| path.as_path())).unwrap();
+        for i in 0..4 {
+            assert!(rom[i * CHIP_SIZE..(i + 1) * CHIP_SIZE]
+                .iter()
+                .all(|&byte| byte == i as u8));
+        }
+
+        // every part has to be a whole chip
+        std::fs::write(dir.join("invaders.f"), vec![0; CHIP_SIZE - 1]).unwrap();
+        let error = load_rom_parts(parts.each_ref().map(|path| path.as_path())).unwrap_err();
+        assert!(error.contains("invaders.f"), "{}", error);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rom_image() {
+        let exact: Vec<u8> = (0..ROM_SIZE).map(|i| i as u8).collect();
+        assert_eq!(rom_image(&exact, false).unwrap().to_vec(), exact);
+
+        let short = &exact[..0x1800];
+        assert!(rom_image(short, false).is_err());
+        let padded = rom_image(short, true).unwrap();
+        assert_eq!(&padded[..0x1800], short);
+        assert!(padded[0x1800..].iter().all(|&byte| byte == 0));
+
+        let mut long = exact.clone();
+        long.extend_from_slice(&[0xFF; 0x100]);
+        assert_eq!(rom_image(&long, false).unwrap().to_vec(), exact);
+    }
+
+    #[test]
+    fn test_known_rom() {
+        assert!(RomInfo::new(&test_rom()).known());
+    }
+}
@@ -0,0 +1,226 @@
+//! Power-on health check for a ROM dump and the emulator core.
+//!
+//! The original `invaders` ROM has no RAM/ROM test routine of its own, so this runs
+//! the checks an operator would make when switching a board on, and names the one
+//! that failed:
+//!
+//! 1. rom: each 2K chip's CRC-32 matches MAME's `invaders` set, see
+//!    [`KNOWN_CHIPS`]
+//! 2. memory: every byte of RAM (0x2000-0x23FF) and VRAM (0x2400-0x3FFF) holds the
+//!    values written to it, and writes to ROM (0x0000-0x1FFF) are ignored
+//! 3. attract: running from reset, the game draws something (normally the
+//!    `SCORE<1> HI-SCORE SCORE<2>` header) while the game mode flag at 0x20EF stays
+//!    0, meaning no game was started by stray inputs
+//! 4. screen: at [`ATTRACT_FRAME`] the screen is the attract mode's `PLAY SPACE
+//!    INVADERS` with [`ATTRACT_FRAME_HASH`], which it holds for half a second either
+//!    side so small differences in interrupt timing don't move it
+//!
+//! [`draw_test_pattern`] checks the other end of the pipeline, from VRAM to the window,
+//! without running the CPU at all, and [`pixel_map`] gives the sums behind it.
+
+use emu8080::MemoryAccess;
+
+use crate::addr;
+use crate::capture::crc32;
+use crate::machine::{new_inputs, CabinetType, Machine};
+use crate::memory::{RotationMode, SpaceInvadersMemory};
+use crate::rominfo::{CHIP_SIZE, KNOWN_CHIPS};
+use crate::{RAM_START, ROM_SIZE, SCREEN_HEIGHT_PIXELS, SCREEN_WIDTH_PIXELS, VRAM_END, VRAM_START};
+
+/// The frame the screen is checked on, a little under four seconds from reset
+pub const ATTRACT_FRAME: u64 = 220;
+
+/// [`Machine::frame_hash`] of the `invaders` attract mode at [`ATTRACT_FRAME`]
+pub const ATTRACT_FRAME_HASH: u64 = 0x4279_0868_D6BF_1667;
+
+/// Lines of VRAM in [`pixel_map`], the first and last and the two either side of the
+/// middle
+const PIXEL_MAP_LINES: [usize; 4] = [0, 111, 112, SCREEN_HEIGHT_PIXELS - 1];
+
+const LINE_BYTES: usize = SCREEN_WIDTH_PIXELS / 8;
+
+/// Returns the frame the game reached attract mode on, or the failed check's name and
+/// what went wrong
+pub fn run_self_test(rom: [u8; ROM_SIZE]) -> Result<u64, String> {
+    check_rom(&rom).map_err(|e| format!("rom check: {}", e))?;
+    check_memory(rom).map_err(|e| format!("memory check: {}", e))?;
+
+    let memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+    let mut machine = Machine::new(memory, new_inputs(), CabinetType::Upright);
+
+    let mut attract = None;
+    while machine.frame() < ATTRACT_FRAME {
+        machine.run_frame();
+
+        if machine.memory.read_byte(addr::GAME_MODE) != 0 {
+            return Err(format!(
+                "attract check: game mode flag set at frame {} without a game being started",
+                machine.frame()
+            ));
+        }
+
+        if attract.is_none() && machine.memory.vram().iter().any(|&byte| byte != 0) {
+            attract = Some(machine.frame());
+        }
+    }
+    let attract = attract.ok_or_else(|| {
+        format!(
+            "attract check: nothing was drawn in the first {} frames",
+            ATTRACT_FRAME
+        )
+    })?;
+
+    let hash = machine.frame_hash();
+    if hash != ATTRACT_FRAME_HASH {
+        return Err(format!(
+            "screen check: frame {} hashes to {:016X}, expected {:016X}",
+            ATTRACT_FRAME, hash, ATTRACT_FRAME_HASH
+        ));
+    }
+
+    Ok(attract)
+}
+
+/// Fills VRAM, through the same writes the CPU makes, with a pattern that shows up
+/// mistakes in orientation and scaling. In the game's own coordinates (256 pixels
+/// across, 224 lines down) it draws:
+///
+/// - a one pixel border around the edge
+/// - a solid 16x16 block in the top left corner, the first pixels the game draws
+/// - a diagonal from the top left, one pixel across for each line down
+/// - along the bottom, columns that get closer together from left to right
+pub fn draw_test_pattern(memory: &mut SpaceInvadersMemory) {
+    let lit = |x: usize, y: usize| {
+        let border =
+            x == 0 || y == 0 || x == SCREEN_WIDTH_PIXELS - 1 || y == SCREEN_HEIGHT_PIXELS - 1;
+        let corner = x < 16 && y < 16;
+        let diagonal = x == y;
+        let gradient = y >= SCREEN_HEIGHT_PIXELS - 48 && x.is_multiple_of(8 - x / 32);
+        border || corner || diagonal || gradient
+    };
+
+    for y in 0..SCREEN_HEIGHT_PIXELS {
+        for byte in 0..SCREEN_WIDTH_PIXELS / 8 {
+            let mut val = 0u8;
+            for bit in 0..8 {
+                if lit(byte * 8 + bit, y) {
+                    val |= 1 << bit;
+                }
+            }
+            let addr = VRAM_START + y * (SCREEN_WIDTH_PIXELS / 8) + byte;
+            memory.write_byte(addr as u16, val);
+        }
+    }
+}
+
+/// A table of where `rotation` puts the first and last pixel of the first and last
+/// byte on a few lines of VRAM, to check the rotation by hand against the test pattern
+pub fn pixel_map(rotation: RotationMode) -> String {
+    let mut table = String::from("vram_addr  bit  display_row  display_col\n");
+    for line in PIXEL_MAP_LINES {
+        for byte in [0, LINE_BYTES - 1] {
+            let address = line * LINE_BYTES + byte;
+            for bit in [0, 7] {
+                let index = rotation.display_pixel_index(address * 8 + bit, false);
+                table.push_str(&format!(
+                    "${:04X}      {}    {:>11}  {:>11}\n",
+                    VRAM_START + address,
+                    bit,
+                    index / rotation.display_width(),
+                    index % rotation.display_width()
+                ));
+            }
+        }
+    }
+    table
+}
+
+fn check_rom(rom: &[u8; ROM_SIZE]) -> Result<(), String> {
+    for (i, (chip, (name, known))) in rom.chunks_exact(CHIP_SIZE).zip(KNOWN_CHIPS).enumerate() {
+        let crc = crc32(chip);
+        if crc != known {
+            return Err(format!(
+                "chip {} at {:04X}-{:04X} has CRC-32 {:08X}, expected {:08X}",
+                name,
+                i * CHIP_SIZE,
+                (i + 1) * CHIP_SIZE - 1,
+                crc,
+                known
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_memory(rom: [u8; ROM_SIZE]) -> Result<(), String> {
+    let mut memory = SpaceInvadersMemory::new(rom, RotationMode::default());
+
+    for addr in RAM_START..VRAM_END {
+        let addr = addr as u16;
+        for pattern in [0x00, 0xFF, 0x55, 0xAA] {
+            memory.write_byte(addr, pattern);
+            let read = memory.read_byte(addr);
+            if read != pattern {
+                return Err(format!(
+                    "wrote {:#04X} to {:#06X} but read back {:#04X}",
+                    pattern, addr, read
+                ));
+            }
+        }
+    }
+
+    memory.write_byte(0x0000, !rom[0]);
+    if memory.read_byte(0x0000) != rom[0] {
+        return Err("ROM is writable".to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{RotationMode, SpaceInvadersMemory};
+    use crate::selftest::{draw_test_pattern, pixel_map, run_self_test};
+    use crate::ROM_SIZE;
+    use emu8080::MemoryAccess;
+
+    #[test]
+    fn test_draw_test_pattern() {
+        let mut memory = SpaceInvadersMemory::new([0; ROM_SIZE], RotationMode::default());
+        draw_test_pattern(&mut memory);
+
+        // top line is all border
+        assert_eq!(memory.read_bytes::<32>(0x2400), [0xFF; 32]);
+        // second line: corner block, diagonal at x = 1 and the right hand border
+        assert_eq!(memory.read_bytes::<3>(0x2420), [0xFF, 0xFF, 0x00]);
+        assert_eq!(memory.read_byte(0x243F), 0x80);
+        // diagonal at (100, 100)
+        assert_eq!(memory.read_byte(0x2400 + 100 * 32 + 12), 0x10);
+    }
+
+    #[test]
+    fn test_self_test_names_the_bad_chip() {
+        let error = run_self_test([0; ROM_SIZE]).unwrap_err();
+        assert!(
+            error.starts_with("rom check: chip invaders.h at 0000-07FF"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_pixel_map() {
+        let table = pixel_map(RotationMode::CounterClockwise);
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows.len(), 1 + 4 * 4);
+        // the first pixel the game draws is bottom left, the last top right
+        assert_eq!(rows[1], "$2400      0            255            0");
+        assert_eq!(rows[16], "$3FFF      7              0          223");
+
+        let table = pixel_map(RotationMode::None);
+        assert_eq!(
+            table.lines().nth(2),
+            Some("$2400      7              0            7")
+        );
+    }
+}
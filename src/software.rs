@@ -0,0 +1,167 @@
+//! Drawing the finished frame on the CPU, for machines without a GPU that wgpu can use.
+//!
+//! [`SoftwareSurface`] takes the same RGBA frame as `pixels` and presents it through
+//! `softbuffer`, which copies it into the window with the platform's own 2D calls.
+//! It scales the way [`crate::filter`] describes: whole multiples with black bars, or
+//! with [`Filter::Linear`](crate::filter::Filter::Linear) stretched to fill as much of
+//! the window as the aspect ratio allows. Either way it samples the nearest pixel,
+//! since blending every pixel of every frame is more than the CPU path is worth.
+
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use winit::window::Window;
+
+use crate::filter::{fit, integer_fit};
+
+pub struct SoftwareSurface {
+    // kept for as long as the surface, some platforms need the connection open
+    _context: softbuffer::Context<Arc<Window>>,
+    surface: softbuffer::Surface<Arc<Window>, Arc<Window>>,
+    /// RGBA, as [`pixels::Pixels::frame_mut`] has it
+    frame: Vec<u8>,
+    frame_size: (u32, u32),
+    surface_size: (u32, u32),
+    /// whole multiples only, for the nearest filter and fullscreen
+    integer: bool,
+}
+
+impl SoftwareSurface {
+    pub fn new(
+        window: Arc<Window>,
+        frame_size: (u32, u32),
+        surface_size: (u32, u32),
+        integer: bool,
+    ) -> Result<Self, String> {
+        let context = softbuffer::Context::new(window.clone())
+            .map_err(|e| format!("Could not connect to the display: {}", e))?;
+        let surface = softbuffer::Surface::new(&context, window)
+            .map_err(|e| format!("Could not set up the window surface: {}", e))?;
+
+        let mut software = SoftwareSurface {
+            _context: context,
+            surface,
+            frame: Vec::new(),
+            frame_size: (0, 0),
+            surface_size: (0, 0),
+            integer,
+        };
+        software.resize_buffer(frame_size.0, frame_size.1);
+        software.resize_surface(surface_size.0, surface_size.1)?;
+        Ok(software)
+    }
+
+    /// The frame to draw next, as RGBA rows from the top
+    pub fn frame_mut(&mut self) -> &mut [u8] {
+        &mut self.frame
+    }
+
+    pub fn resize_buffer(&mut self, width: u32, height: u32) {
+        self.frame_size = (width, height);
+        self.frame.resize(width as usize * height as usize * 4, 0);
+    }
+
+    /// Ignores a size of zero, which is all a minimised window has
+    pub fn resize_surface(&mut self, width: u32, height: u32) -> Result<(), String> {
+        let (Some(nonzero_width), Some(nonzero_height)) =
+            (NonZeroU32::new(width), NonZeroU32::new(height))
+        else {
+            return Ok(());
+        };
+        self.surface
+            .resize(nonzero_width, nonzero_height)
+            .map_err(|e| e.to_string())?;
+        self.surface_size = (width, height);
+        Ok(())
+    }
+
+    /// Whole multiples only or not, as when going in or out of fullscreen
+    pub fn set_integer(&mut self, integer: bool) {
+        self.integer = integer;
+    }
+
+    pub fn render(&mut self) -> Result<(), String> {
+        if self.surface_size.0 == 0 || self.surface_size.1 == 0 {
+            return Ok(());
+        }
+        let mut buffer = self.surface.buffer_mut().map_err(|e| e.to_string())?;
+        scale_frame(
+            &self.frame,
+            self.frame_size,
+            &mut buffer,
+            self.surface_size,
+            self.integer,
+        );
+        buffer.present().map_err(|e| e.to_string())
+    }
+}
+
+/// Scales an RGBA `frame` into `out`, a `surface` sized buffer of `0x00RRGGBB` pixels,
+/// centred with black around it. A surface smaller than the frame crops it when
+/// `integer` is set, as [`integer_fit`] does.
+pub fn scale_frame(
+    frame: &[u8],
+    frame_size: (u32, u32),
+    out: &mut [u32],
+    surface: (u32, u32),
+    integer: bool,
+) {
+    out.fill(0);
+    if frame_size.0 == 0 || frame_size.1 == 0 {
+        return;
+    }
+
+    let (x, y, width, height) = if integer {
+        let (scale, x, y) = integer_fit(frame_size, surface);
+        (x, y, frame_size.0 * scale, frame_size.1 * scale)
+    } else {
+        let (x, y, width, height) = fit(frame_size, surface);
+        (x as u32, y as u32, width as u32, height as u32)
+    };
+
+    let (frame_width, surface_width) = (frame_size.0 as usize, surface.0 as usize);
+    for row in 0..height.min(surface.1 - y) {
+        let source_row = (row as u64 * frame_size.1 as u64 / height as u64) as usize;
+        let line = &mut out[(y + row) as usize * surface_width..][..surface_width];
+        for column in 0..width.min(surface.0 - x) {
+            let source_column = (column as u64 * frame_size.0 as u64 / width as u64) as usize;
+            let pixel = &frame[(source_row * frame_width + source_column) * 4..][..3];
+            line[(x + column) as usize] =
+                (pixel[0] as u32) << 16 | (pixel[1] as u32) << 8 | pixel[2] as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::software::scale_frame;
+
+    const RED: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+    const GREEN: [u8; 4] = [0x00, 0xFF, 0x00, 0xFF];
+
+    #[test]
+    fn test_scale_frame() {
+        // a 2x1 frame, red then green
+        let frame = [RED, GREEN].concat();
+
+        // doubled, with a black column either side
+        let mut out = vec![0xDEAD; 6 * 2];
+        scale_frame(&frame, (2, 1), &mut out, (6, 2), true);
+        #[rustfmt::skip]
+        let doubled = [
+            0, 0xFF0000, 0xFF0000, 0x00FF00, 0x00FF00, 0,
+            0, 0xFF0000, 0xFF0000, 0x00FF00, 0x00FF00, 0,
+        ];
+        assert_eq!(out, doubled);
+
+        // stretched one and a half times instead, with a black row below
+        let mut out = vec![0xDEAD; 3 * 2];
+        scale_frame(&frame, (2, 1), &mut out, (3, 2), false);
+        assert_eq!(out, [0xFF0000, 0xFF0000, 0x00FF00, 0, 0, 0]);
+
+        // cropped rather than shrunk
+        let mut out = vec![0; 1];
+        scale_frame(&frame, (2, 1), &mut out, (1, 1), true);
+        assert_eq!(out, [0xFF0000]);
+    }
+}
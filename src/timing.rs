@@ -0,0 +1,242 @@
+//! Per-frame timing of the emulator loop as CSV, for `--timing-csv`.
+//!
+//! The loop runs a few instructions at a time and sleeps off whatever is left of the
+//! time they'd take on the real machine. Each row adds up those batches for one frame:
+//!
+//! ```text
+//! frame,wall_ns,emulated_ns,sleep_ns,overran
+//! 1,16702114,16667000,15480220,false
+//! ```
+//!
+//! `overran` is true if any batch in the frame took longer to run than it emulated,
+//! leaving nothing to sleep off. A batch that runs into a vblank counts towards the
+//! frame that vblank ends.
+//!
+//! What happens to the time lost to an overrun is up to the [`Catchup`] mode given
+//! with `--catchup`, carried out by a [`Pacer`].
+
+use std::io::Write;
+use std::time::Duration;
+
+use crate::DISPLAY_TIME_NANO_SEC;
+
+const HEADER: &str = "frame,wall_ns,emulated_ns,sleep_ns,overran";
+
+/// Rows between flushes, so a buffered writer only goes to the disk about once a
+/// second and the writing doesn't cause overruns of its own
+const FLUSH_FRAMES: u64 = 60;
+
+#[derive(Default)]
+struct FrameTiming {
+    wall: Duration,
+    emulated: Duration,
+    sleep: Duration,
+    overran: bool,
+}
+
+/// The most `--catchup drop` will owe before forgetting the rest, so a long stall
+/// doesn't leave the display frozen while it catches up
+const DROP_MAX_BEHIND: Duration = Duration::from_millis(250);
+
+/// What the loop does about the time lost when a batch takes longer to run than it
+/// would on the real machine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Catchup {
+    /// keeps the game on the wall clock, running flat out until it has made up the
+    /// lost time and not showing the frames it runs on the way. The game's speed stays
+    /// right but the picture skips, and up to [`DROP_MAX_BEHIND`] is made up.
+    Drop,
+    /// forgets the lost time, so the game runs slower for as long as the host can't
+    /// keep up and never speeds up afterwards. Every frame is shown, but a slow host
+    /// plays a slow game.
+    Slow,
+    /// makes up lost time like `Drop` but shows every frame and owes at most a frame,
+    /// so a stall can't turn into a burst of fast play with the sound racing to keep up
+    #[default]
+    Clamp,
+}
+
+impl std::str::FromStr for Catchup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Catchup::Drop),
+            "slow" => Ok(Catchup::Slow),
+            "clamp" => Ok(Catchup::Clamp),
+            _ => Err(format!(
+                "Unknown catch-up mode '{}' (expected drop, slow or clamp)",
+                s
+            )),
+        }
+    }
+}
+
+/// Works out how long to sleep after each batch, keeping track of any time owed
+#[derive(Default)]
+pub struct Pacer {
+    catchup: Catchup,
+    behind: Duration,
+}
+
+impl Pacer {
+    pub fn new(catchup: Catchup) -> Self {
+        Pacer {
+            catchup,
+            behind: Duration::ZERO,
+        }
+    }
+
+    /// The sleep after a batch that took `exec` to run and emulated `emulated`. Time
+    /// owed comes out of the sleep until it's made up.
+    pub fn pace(&mut self, exec: Duration, emulated: Duration) -> Duration {
+        if emulated < exec {
+            let max_behind = match self.catchup {
+                Catchup::Drop => DROP_MAX_BEHIND,
+                Catchup::Slow => Duration::ZERO,
+                Catchup::Clamp => Duration::from_nanos(DISPLAY_TIME_NANO_SEC),
+            };
+            self.behind = (self.behind + (exec - emulated)).min(max_behind);
+            return Duration::ZERO;
+        }
+
+        let spare = emulated - exec;
+        let made_up = spare.min(self.behind);
+        self.behind -= made_up;
+        spare - made_up
+    }
+
+    /// Time lost to overruns and not made up yet
+    pub fn behind(&self) -> Duration {
+        self.behind
+    }
+
+    /// Whether frames shouldn't be shown, true with `Drop` while a frame or more behind
+    pub fn dropping(&self) -> bool {
+        self.catchup == Catchup::Drop && self.behind >= Duration::from_nanos(DISPLAY_TIME_NANO_SEC)
+    }
+}
+
+pub struct TimingLog<W: Write> {
+    out: W,
+    frame: FrameTiming,
+    rows: u64,
+}
+
+impl<W: Write> TimingLog<W> {
+    pub fn new(mut out: W) -> std::io::Result<Self> {
+        writeln!(out, "{}", HEADER)?;
+        Ok(TimingLog {
+            out,
+            frame: FrameTiming::default(),
+            rows: 0,
+        })
+    }
+
+    /// Adds a batch of instructions. `wall` includes the `sleep` after it.
+    pub fn record_batch(
+        &mut self,
+        wall: Duration,
+        emulated: Duration,
+        sleep: Duration,
+        overran: bool,
+    ) {
+        self.frame.wall += wall;
+        self.frame.emulated += emulated;
+        self.frame.sleep += sleep;
+        self.frame.overran |= overran;
+    }
+
+    /// Writes the row for `frame` from the batches since the last one
+    pub fn end_frame(&mut self, frame: u64) -> std::io::Result<()> {
+        let timing = std::mem::take(&mut self.frame);
+        writeln!(
+            self.out,
+            "{},{},{},{},{}",
+            frame,
+            timing.wall.as_nanos(),
+            timing.emulated.as_nanos(),
+            timing.sleep.as_nanos(),
+            timing.overran
+        )?;
+
+        self.rows += 1;
+        if self.rows.is_multiple_of(FLUSH_FRAMES) {
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timing::{Catchup, Pacer, TimingLog, DROP_MAX_BEHIND};
+    use crate::DISPLAY_TIME_NANO_SEC;
+    use std::time::Duration;
+
+    #[test]
+    fn test_catchup_from_str() {
+        assert_eq!("drop".parse(), Ok(Catchup::Drop));
+        assert_eq!("slow".parse(), Ok(Catchup::Slow));
+        assert_eq!("clamp".parse(), Ok(Catchup::Clamp));
+        assert!("skip".parse::<Catchup>().is_err());
+    }
+
+    #[test]
+    fn test_pacer() {
+        let ms = Duration::from_millis;
+        let frame = Duration::from_nanos(DISPLAY_TIME_NANO_SEC);
+
+        // on time, the rest of the batch is slept off
+        let mut pacer = Pacer::new(Catchup::Slow);
+        assert_eq!(pacer.pace(ms(2), ms(10)), ms(8));
+        // lost time is forgotten
+        assert_eq!(pacer.pace(ms(30), ms(10)), Duration::ZERO);
+        assert_eq!(pacer.pace(ms(2), ms(10)), ms(8));
+
+        // made up out of the next sleeps
+        let mut pacer = Pacer::new(Catchup::Clamp);
+        assert_eq!(pacer.pace(ms(15), ms(10)), Duration::ZERO);
+        assert_eq!(pacer.behind(), ms(5));
+        assert_eq!(pacer.pace(ms(2), ms(10)), ms(3));
+        assert_eq!(pacer.behind(), Duration::ZERO);
+        // but never more than a frame
+        pacer.pace(ms(500), ms(10));
+        assert_eq!(pacer.behind(), frame);
+        assert!(!pacer.dropping());
+
+        let mut pacer = Pacer::new(Catchup::Drop);
+        pacer.pace(ms(500), ms(10));
+        assert_eq!(pacer.behind(), DROP_MAX_BEHIND);
+        assert!(pacer.dropping());
+        while pacer.behind() >= frame {
+            assert_eq!(pacer.pace(ms(1), ms(10)), Duration::ZERO);
+        }
+        assert!(!pacer.dropping());
+    }
+
+    #[test]
+    fn test_timing_log() {
+        let mut log = TimingLog::new(Vec::new()).unwrap();
+        let us = Duration::from_micros;
+
+        log.record_batch(us(10), us(10), us(8), false);
+        log.record_batch(us(5), us(5), us(3), false);
+        log.end_frame(1).unwrap();
+        log.record_batch(us(12), us(10), Duration::ZERO, true);
+        log.end_frame(2).unwrap();
+
+        let csv = String::from_utf8(log.finish().unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "frame,wall_ns,emulated_ns,sleep_ns,overran\n\
+             1,15000,15000,11000,false\n\
+             2,12000,10000,0,true\n"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Golden instruction traces, for checking the CPU core against a run that's known
+//! to be good with `--write-trace` and `--verify-trace`.
+//!
+//! A trace follows a ROM headless from power on with no inputs, so through the boot
+//! and the attract mode's demo game. It records the address and cycle count of every
+//! instruction and a checksum of memory at every vblank, so a core that takes a
+//! different branch, counts cycles differently or computes a different value shows up
+//! on the instruction it happens, or at worst the vblank after.
+//!
+//! The file starts with `SITRACE`, the format version as a little endian `u16`, the
+//! ROM's CRC-32 as a `u32` and the number of frames traced as a `u64`. Then comes an
+//! entry per instruction and per vblank, each starting with a tag byte:
+//!
+//! | tag | followed by                                                              |
+//! |-----|--------------------------------------------------------------------------|
+//! | `I` | the instruction's address (`u16`) and cycles taken (`u8`)                |
+//! | `F` | the CRC-32 of work RAM then VRAM (`u32`), after the vblank's instruction |
+//!
+//! Numbers are little endian. A frame takes about 20K of trace.
+
+use std::fmt;
+use std::io::{Error, ErrorKind, Read, Write};
+
+use emu8080::MemoryAccess;
+
+use crate::capture::{crc32, vram_snapshot};
+use crate::disasm::disassemble_instruction;
+use crate::machine::Machine;
+use crate::ROM_SIZE;
+
+pub const TRACE_VERSION: u16 = 1;
+
+/// Frames traced unless told otherwise, ten seconds of emulated time
+pub const TRACE_FRAMES: u64 = 600;
+
+const MAGIC: &[u8; 7] = b"SITRACE";
+
+/// What the core did differently from the trace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// the instruction before jumped, returned or was interrupted somewhere else
+    Pc {
+        expected: u16,
+    },
+    Cycles {
+        expected: u8,
+        actual: u8,
+    },
+    /// the checksum of RAM and VRAM at the vblank
+    Memory {
+        expected: u32,
+        actual: u32,
+    },
+    /// the core reached a vblank where the trace didn't, or the other way round
+    Vblank {
+        expected: bool,
+    },
+}
+
+/// The first instruction the core and the trace disagreed on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceDivergence {
+    /// instructions run from power on, counting this one
+    pub instruction: u64,
+    pub frame: u64,
+    pub pc: u16,
+    /// this instruction as disassembled from memory
+    pub mnemonic: String,
+    /// the instruction run before this one, as address and mnemonic
+    pub previous: Option<(u16, String)>,
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for TraceDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Diverged at instruction {} (frame {})",
+            self.instruction, self.frame
+        )?;
+        if let Some((pc, ref mnemonic)) = self.previous {
+            writeln!(f, "  previous  {:04X}  {}", pc, mnemonic)?;
+        }
+        writeln!(f, "  this      {:04X}  {}", self.pc, self.mnemonic)?;
+        match self.mismatch {
+            Mismatch::Pc { expected } => {
+                writeln!(f, "  expected to be at {:04X}", expected)
+            }
+            Mismatch::Cycles { expected, actual } => {
+                writeln!(f, "  took {} cycles, expected {}", actual, expected)
+            }
+            Mismatch::Memory { expected, actual } => writeln!(
+                f,
+                "  memory checksum {:08x} at vblank, expected {:08x}",
+                actual, expected
+            ),
+            Mismatch::Vblank { expected: true } => {
+                writeln!(f, "  expected a vblank after the previous instruction")
+            }
+            Mismatch::Vblank { expected: false } => {
+                writeln!(f, "  raised a vblank the trace doesn't have")
+            }
+        }
+    }
+}
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn memory_checksum(machine: &Machine) -> u32 {
+    crc32(&[&machine.memory.save_ram()[..], &vram_snapshot(machine)].concat())
+}
+
+/// Runs `rom` for `frames` frames, writing the trace of every instruction to `out`
+pub fn write_trace(rom: [u8; ROM_SIZE], frames: u64, out: &mut impl Write) -> Result<(), Error> {
+    out.write_all(MAGIC)?;
+    out.write_all(&TRACE_VERSION.to_le_bytes())?;
+    out.write_all(&crc32(&rom).to_le_bytes())?;
+    out.write_all(&frames.to_le_bytes())?;
+
+    let mut machine = Machine::with_program(&rom).map_err(invalid)?;
+    while machine.frame() < frames {
+        let mut entry = [b'I', 0, 0, 0];
+        let step = machine.step_with(|info| {
+            entry[1..3].copy_from_slice(&info.pc.to_le_bytes());
+            entry[3] = info.cycles as u8;
+        });
+        out.write_all(&entry)?;
+
+        if step.vblank {
+            out.write_all(&[b'F'])?;
+            out.write_all(&memory_checksum(&machine).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs `rom` against the trace read from `trace`, stopping at the first instruction
+/// that doesn't match. Errors are for a trace that can't be read, or was made with
+/// another ROM.
+pub fn verify_trace(
+    rom: [u8; ROM_SIZE],
+    trace: &mut impl Read,
+) -> Result<Option<TraceDivergence>, Error> {
+    let mut header = [0u8; 21];
+    trace.read_exact(&mut header)?;
+    if &header[..7] != MAGIC {
+        return Err(invalid("not an instruction trace"));
+    }
+    let version = u16::from_le_bytes([header[7], header[8]]);
+    if version != TRACE_VERSION {
+        return Err(invalid(format!(
+            "trace format version {} is not supported (expected {})",
+            version, TRACE_VERSION
+        )));
+    }
+    let rom_crc32 = u32::from_le_bytes(header[9..13].try_into().unwrap());
+    if rom_crc32 != crc32(&rom) {
+        return Err(invalid(format!(
+            "trace was made with a different ROM (CRC-32 {:08x}, loaded ROM is {:08x})",
+            rom_crc32,
+            crc32(&rom)
+        )));
+    }
+    let frames = u64::from_le_bytes(header[13..21].try_into().unwrap());
+
+    let mut machine = Machine::with_program(&rom).map_err(invalid)?;
+    let mut instruction = 0;
+    let mut previous = None;
+    let mut tag = [0u8; 1];
+    while machine.frame() < frames {
+        let (pc, frame) = (machine.cpu.pc(), machine.frame());
+        let (_, mnemonic) = disassemble_instruction(&machine.memory.read_bytes::<3>(pc));
+        instruction += 1;
+        let diverged = |mismatch| TraceDivergence {
+            instruction,
+            frame,
+            pc,
+            mnemonic: mnemonic.clone(),
+            previous: previous.clone(),
+            mismatch,
+        };
+
+        let mut entry = [0u8; 3];
+        trace.read_exact(&mut tag)?;
+        if tag[0] == b'F' {
+            return Ok(Some(diverged(Mismatch::Vblank { expected: true })));
+        }
+        if tag[0] != b'I' {
+            return Err(invalid(format!("unknown trace entry '{}'", tag[0] as char)));
+        }
+        trace.read_exact(&mut entry)?;
+        let expected_pc = u16::from_le_bytes([entry[0], entry[1]]);
+        if pc != expected_pc {
+            return Ok(Some(diverged(Mismatch::Pc {
+                expected: expected_pc,
+            })));
+        }
+
+        let step = machine.step_with(|_| {});
+        if step.cycles as u8 != entry[2] {
+            return Ok(Some(diverged(Mismatch::Cycles {
+                expected: entry[2],
+                actual: step.cycles as u8,
+            })));
+        }
+
+        if step.vblank {
+            let mut checksum = [0u8; 5];
+            trace.read_exact(&mut checksum)?;
+            if checksum[0] != b'F' {
+                return Ok(Some(diverged(Mismatch::Vblank { expected: false })));
+            }
+            let expected = u32::from_le_bytes(checksum[1..].try_into().unwrap());
+            let actual = memory_checksum(&machine);
+            if actual != expected {
+                return Ok(Some(diverged(Mismatch::Memory { expected, actual })));
+            }
+        }
+
+        previous = Some((pc, mnemonic));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trace::{verify_trace, write_trace, Mismatch};
+    use crate::{test_rom, ROM_SIZE};
+
+    fn rom(program: &[u8]) -> [u8; ROM_SIZE] {
+        let mut rom = [0u8; ROM_SIZE];
+        rom[..program.len()].copy_from_slice(program);
+        rom
+    }
+
+    #[test]
+    fn test_trace() {
+        // MVI A,$42; STA $2010; JMP $0005
+        let program = [0x3E, 0x42, 0x32, 0x10, 0x20, 0xC3, 0x05, 0x00];
+        let mut trace = Vec::new();
+        write_trace(rom(&program), 3, &mut trace).unwrap();
+        assert_eq!(
+            verify_trace(rom(&program), &mut trace.as_slice()).unwrap(),
+            None
+        );
+
+        // another ROM's trace is refused rather than compared
+        assert!(verify_trace(rom(&[0x00]), &mut trace.as_slice()).is_err());
+        assert!(verify_trace(rom(&program), &mut &b"SICAPT"[..]).is_err());
+
+        // the second instruction was at $0002 and took 13 cycles
+        let mut bad = trace.clone();
+        let second = 21 + 4 + 5;
+        assert_eq!(&bad[second..second + 4], [b'I', 0x02, 0x00, 13]);
+        bad[second + 3] = 16;
+        let divergence = verify_trace(rom(&program), &mut bad.as_slice())
+            .unwrap()
+            .unwrap();
+        assert_eq!(divergence.instruction, 2);
+        assert_eq!(divergence.pc, 0x0002);
+        assert_eq!(divergence.mnemonic, "STA $2010");
+        assert_eq!(divergence.previous, Some((0x0000, "MVI A,$42".to_string())));
+        assert_eq!(
+            divergence.mismatch,
+            Mismatch::Cycles {
+                expected: 16,
+                actual: 13
+            }
+        );
+        assert!(divergence.to_string().contains("0002  STA $2010"));
+    }
+
+    #[test]
+    fn test_trace_game() {
+        let rom = test_rom();
+        let mut trace = Vec::new();
+        write_trace(rom, 60, &mut trace).unwrap();
+        assert_eq!(verify_trace(rom, &mut trace.as_slice()).unwrap(), None);
+    }
+}
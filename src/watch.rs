@@ -0,0 +1,114 @@
+//! Noticing when a file is rewritten, for `--watch-rom`.
+//!
+//! The file's size and modification time are checked every [`WATCH_INTERVAL`] rather
+//! than asking the OS for notifications. A change is only reported once the file has
+//! stayed the same for [`SETTLE_TIME`], since assemblers and build scripts often write
+//! in bursts, or delete the old file before the new one is written, and reading in
+//! between gets half a ROM.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+pub const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+pub const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// What's compared between checks, `None` while the file is missing
+type Stamp = Option<(SystemTime, u64)>;
+
+pub struct FileWatcher {
+    path: PathBuf,
+    stamp: Stamp,
+    checked: Option<Instant>,
+    /// when the stamp last changed, until the change is reported
+    changed: Option<Instant>,
+}
+
+impl FileWatcher {
+    /// Starts from the file as it is now, so only later changes are reported
+    pub fn new(path: PathBuf) -> Self {
+        let stamp = stamp(&path);
+        FileWatcher {
+            path,
+            stamp,
+            checked: None,
+            changed: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks the file if it's been [`WATCH_INTERVAL`] since the last time, returning
+    /// true once for each change after it has settled
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if self
+            .checked
+            .is_some_and(|checked| now.duration_since(checked) < WATCH_INTERVAL)
+        {
+            return false;
+        }
+        self.checked = Some(now);
+
+        let stamp = stamp(&self.path);
+        if stamp != self.stamp {
+            self.stamp = stamp;
+            self.changed = Some(now);
+            return false;
+        }
+
+        // a file deleted and not put back isn't worth reporting
+        if stamp.is_some()
+            && self
+                .changed
+                .is_some_and(|changed| now.duration_since(changed) >= SETTLE_TIME)
+        {
+            self.changed = None;
+            return true;
+        }
+        false
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::watch::{FileWatcher, SETTLE_TIME, WATCH_INTERVAL};
+
+    #[test]
+    fn test_file_watcher() {
+        let path = std::env::temp_dir().join(format!("watch-test-{}.bin", std::process::id()));
+        std::fs::write(&path, [0u8; 4]).unwrap();
+        let mut watcher = FileWatcher::new(path.clone());
+        let start = Instant::now();
+        assert!(!watcher.poll(start));
+
+        // the size changes, so it's seen however coarse the modification times are
+        std::fs::write(&path, [0u8; 8]).unwrap();
+        // not checked again until the interval is up
+        assert!(!watcher.poll(start + WATCH_INTERVAL / 2));
+        assert!(!watcher.poll(start + WATCH_INTERVAL));
+        // still settling, then reported just once
+        assert!(!watcher.poll(start + WATCH_INTERVAL * 2));
+        let settled = start + WATCH_INTERVAL + SETTLE_TIME;
+        assert!(watcher.poll(settled));
+        assert!(!watcher.poll(settled + WATCH_INTERVAL));
+
+        // gone, and then back
+        std::fs::remove_file(&path).unwrap();
+        let gone = settled + WATCH_INTERVAL * 2;
+        assert!(!watcher.poll(gone));
+        assert!(!watcher.poll(gone + SETTLE_TIME));
+        std::fs::write(&path, [0u8; 2]).unwrap();
+        let back = gone + SETTLE_TIME + WATCH_INTERVAL;
+        assert!(!watcher.poll(back));
+        assert!(watcher.poll(back + SETTLE_TIME));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
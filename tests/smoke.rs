@@ -0,0 +1,70 @@
+//! Runs the real ROM headless from power on, as a check that the CPU core, memory map,
+//! I/O and interrupts work together. Skipped when `src/assets/invaders.bin` isn't
+//! there, so the tree still builds and tests without it.
+
+use emu8080::MemoryAccess;
+
+use space_invaders::addr;
+use space_invaders::machine::Machine;
+use space_invaders::selftest::run_self_test;
+use space_invaders::ROM_SIZE;
+
+/// Five seconds of emulated time, well into the attract mode
+const FRAMES: u64 = 300;
+
+const ROM_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/assets/invaders.bin");
+
+/// The game ROM, or `None` if it isn't there. One that's there but the wrong size
+/// fails the test rather than skipping it.
+fn rom() -> Option<[u8; ROM_SIZE]> {
+    let rom = match std::fs::read(ROM_PATH) {
+        Ok(rom) => rom,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!("{} not found, skipping", ROM_PATH);
+            return None;
+        }
+        Err(e) => panic!("Could not read {}: {}", ROM_PATH, e),
+    };
+    let len = rom.len();
+    Some(
+        rom.try_into()
+            .unwrap_or_else(|_| panic!("{} is {} bytes, expected {}", ROM_PATH, len, ROM_SIZE)),
+    )
+}
+
+#[test]
+fn test_attract_mode() {
+    let Some(rom) = rom() else {
+        return;
+    };
+    let blank = Machine::with_program(&rom).unwrap().frame_hash();
+
+    let mut machine = Machine::with_program(&rom).unwrap();
+    while machine.frame() < FRAMES {
+        machine.run_frame();
+    }
+    // sat on the attract mode with nobody having put a coin in
+    assert_eq!(machine.memory.read_byte(addr::GAME_MODE), 0);
+    assert_eq!(machine.memory.read_byte(addr::CREDITS), 0);
+    assert_ne!(machine.frame_hash(), blank, "nothing was drawn");
+
+    // and the same screen every time
+    let mut again = Machine::with_program(&rom).unwrap();
+    while again.frame() < FRAMES {
+        again.run_frame();
+    }
+    assert_eq!(again.frame_hash(), machine.frame_hash());
+
+    assert!(run_self_test(rom).is_ok());
+}
+
+#[test]
+fn test_game_starts() {
+    let Some(rom) = rom() else {
+        return;
+    };
+    let mut machine = Machine::with_program(&rom).unwrap();
+    let started = machine.run_until_playing(600).expect("game never started");
+    assert!(started < 600);
+    assert_ne!(machine.memory.read_byte(addr::GAME_MODE), 0);
+}